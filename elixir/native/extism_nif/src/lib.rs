@@ -21,6 +21,7 @@ struct ExtismContext {
 unsafe impl Sync for ExtismContext {}
 unsafe impl Send for ExtismContext {}
 
+#[allow(non_local_definitions)]
 fn load(env: Env, _: Term) -> bool {
     rustler::resource!(ExtismContext, env);
     true
@@ -50,7 +51,7 @@ fn context_reset(ctx: ResourceArc<ExtismContext>) {
 
 #[rustler::nif]
 fn context_free(ctx: ResourceArc<ExtismContext>) {
-    let context = &ctx.ctx.read().unwrap();
+    let context = ctx.ctx.read().unwrap();
     std::mem::drop(context)
 }
 
@@ -85,7 +86,7 @@ fn plugin_call(
     let mut plugin = unsafe { Plugin::from_id(plugin_id, context) };
     let result = match plugin.call(name, input) {
         Err(e) => Err(to_rustler_error(e)),
-        Ok(result) => match str::from_utf8(&result) {
+        Ok(result) => match str::from_utf8(result) {
             Ok(output) => Ok(output.to_string()),
             Err(_e) => Err(rustler::Error::Term(Box::new(
                 "Could not read output from plugin",
@@ -1,4 +1,4 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::path::{Path, PathBuf};
 
 #[deprecated]
@@ -7,10 +7,23 @@ pub type ManifestMemory = MemoryOptions;
 #[derive(Default, serde::Serialize, serde::Deserialize)]
 #[cfg_attr(feature = "json_schema", derive(schemars::JsonSchema))]
 pub struct MemoryOptions {
+    /// The maximum size of each memory used by the plugin, in pages. Calls fail with `memory
+    /// limit exceeded` when a memory tries to grow beyond this
     #[serde(alias = "max")]
     pub max_pages: Option<u32>,
 }
 
+/// Format that plugin output is required to match
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "json_schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Any,
+    Utf8,
+    Json,
+}
+
 #[derive(serde::Serialize, serde::Deserialize)]
 #[cfg_attr(feature = "json_schema", derive(schemars::JsonSchema))]
 pub struct HttpRequest {
@@ -149,6 +162,12 @@ fn base64_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::
     schema.into()
 }
 
+#[cfg(feature = "json_schema")]
+fn base64_map_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+    use schemars::JsonSchema;
+    <BTreeMap<String, String>>::json_schema(gen)
+}
+
 #[derive(Default, serde::Serialize, serde::Deserialize)]
 #[cfg_attr(feature = "json_schema", derive(schemars::JsonSchema))]
 pub struct Manifest {
@@ -158,16 +177,68 @@ pub struct Manifest {
     pub memory: MemoryOptions,
     #[serde(default)]
     pub config: BTreeMap<String, String>,
+    /// Config keys with secret values, these keys are never logged and are skipped by
+    /// `Manifest::public_config`. Secret values aren't added to the WASI environment, plugins can
+    /// only read them using `extism_config_get`
+    #[serde(default)]
+    pub secret_config: BTreeSet<String>,
     #[serde(default)]
     pub allowed_hosts: Option<Vec<String>>,
+    /// Only download modules referenced by URL using HTTPS from one of `allowed_hosts`, module
+    /// URLs aren't restricted when this is disabled
+    #[serde(default)]
+    pub restrict_module_urls: bool,
     #[serde(default)]
     pub allowed_paths: Option<BTreeMap<PathBuf, PathBuf>>,
-    #[serde(default = "default_timeout")]
+    /// The time limit for each call in milliseconds, `0` or `null` means calls have no time
+    /// limit. When this is unset the default set using `extism_set_default_timeout_ms` is used,
+    /// or 30 seconds if there isn't one
+    #[serde(
+        default,
+        deserialize_with = "deserialize_timeout",
+        skip_serializing_if = "Option::is_none"
+    )]
     pub timeout_ms: Option<u64>,
+    /// Reinstantiate the plugin after `_start` is called, defaults to `true` when unset. This
+    /// should be disabled for WASI reactor modules that need to keep their state between calls
+    #[serde(default)]
+    pub reinstantiate_after_start: Option<bool>,
+    /// Functions that can only be called once per instance, the plugin is reinstantiated after
+    /// any of these functions are called
+    #[serde(default)]
+    pub reinstantiate_after: Vec<String>,
+    /// When enabled, calls to functions that aren't exported by the plugin will be routed
+    /// to the `_dispatch` export, which receives the offset of the requested function name
+    #[serde(default)]
+    pub dispatch_fallback: bool,
+    /// Validate the output of successful calls, calls that produce output that doesn't match
+    /// will fail
+    #[serde(default)]
+    pub output_must_be: OutputFormat,
+    /// Round the WASI clocks down to this resolution
+    #[serde(default)]
+    pub wasi_clock_resolution_ms: Option<u64>,
+    /// Stop the WASI monotonic clock from advancing
+    #[serde(default)]
+    pub wasi_disable_monotonic_clock: bool,
+    /// The amount of fuel available to the plugin, most WASM instructions consume one unit of
+    /// fuel and calls fail once it runs out. Fuel is unlimited when unset
+    #[serde(default)]
+    pub fuel: Option<u64>,
+    /// Files available to WASI plugins using an in-memory filesystem mounted at `/`, the keys
+    /// are paths in the guest and the values are base64 encoded file contents. Files written by
+    /// the plugin are kept in memory and never touch the host filesystem, writes that would grow
+    /// the filesystem past 64MiB fail
+    #[serde(default, with = "base64_map")]
+    #[cfg_attr(feature = "json_schema", schemars(schema_with = "base64_map_schema"))]
+    pub files: BTreeMap<String, Vec<u8>>,
 }
 
-fn default_timeout() -> Option<u64> {
-    Some(30000)
+// Only called when `timeout_ms` is present, an explicit `null` disables the timeout instead of
+// using the default
+fn deserialize_timeout<'de, D: serde::Deserializer<'de>>(d: D) -> Result<Option<u64>, D::Error> {
+    let timeout: Option<u64> = serde::Deserialize::deserialize(d)?;
+    Ok(Some(timeout.unwrap_or(0)))
 }
 
 impl Manifest {
@@ -175,7 +246,6 @@ impl Manifest {
     pub fn new(wasm: impl IntoIterator<Item = impl Into<Wasm>>) -> Manifest {
         Manifest {
             wasm: wasm.into_iter().map(|x| x.into()).collect(),
-            timeout_ms: default_timeout(),
             ..Default::default()
         }
     }
@@ -240,6 +310,26 @@ impl Manifest {
         self
     }
 
+    /// Add a config value that's marked as secret
+    pub fn with_secret_config(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        let key = key.into();
+        self.config.insert(key.clone(), value.into());
+        self.secret_config.insert(key);
+        self
+    }
+
+    /// Returns true if `key` is marked as secret
+    pub fn is_secret_config(&self, key: &str) -> bool {
+        self.secret_config.contains(key)
+    }
+
+    /// Iterate over the config values that aren't marked as secret
+    pub fn public_config(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.config
+            .iter()
+            .filter(|(k, _)| !self.is_secret_config(k))
+    }
+
     /// Set `timeout_ms`, which will interrupt a plugin function's execution if it meets or
     /// exceeds this value. When an interrupt is made, the plugin will not be able to recover and
     /// continue execution.
@@ -247,6 +337,60 @@ impl Manifest {
         self.timeout_ms = Some(timeout.as_millis() as u64);
         self
     }
+
+    /// Set `reinstantiate_after_start`
+    pub fn with_reinstantiate_after_start(mut self, reinstantiate: bool) -> Self {
+        self.reinstantiate_after_start = Some(reinstantiate);
+        self
+    }
+
+    /// Add a function to `reinstantiate_after`
+    pub fn with_reinstantiate_after(mut self, name: impl Into<String>) -> Self {
+        self.reinstantiate_after.push(name.into());
+        self
+    }
+
+    /// Set `dispatch_fallback`
+    pub fn with_dispatch_fallback(mut self, dispatch_fallback: bool) -> Self {
+        self.dispatch_fallback = dispatch_fallback;
+        self
+    }
+
+    /// Set `output_must_be`
+    pub fn with_output_format(mut self, format: OutputFormat) -> Self {
+        self.output_must_be = format;
+        self
+    }
+
+    /// Set `wasi_clock_resolution_ms`
+    pub fn with_wasi_clock_resolution(mut self, resolution: std::time::Duration) -> Self {
+        self.wasi_clock_resolution_ms = Some(resolution.as_millis() as u64);
+        self
+    }
+
+    /// Set `wasi_disable_monotonic_clock`
+    pub fn with_wasi_monotonic_clock_disabled(mut self, disabled: bool) -> Self {
+        self.wasi_disable_monotonic_clock = disabled;
+        self
+    }
+
+    /// Set `memory.max_pages`
+    pub fn with_memory_limit(mut self, pages: u32) -> Self {
+        self.memory.max_pages = Some(pages);
+        self
+    }
+
+    /// Set `fuel`
+    pub fn with_fuel(mut self, fuel: u64) -> Self {
+        self.fuel = Some(fuel);
+        self
+    }
+
+    /// Add a file to the in-memory filesystem
+    pub fn with_file(mut self, path: impl Into<String>, data: impl Into<Vec<u8>>) -> Self {
+        self.files.insert(path.into(), data.into());
+        self
+    }
 }
 
 mod base64 {
@@ -263,3 +407,30 @@ mod base64 {
         base64::decode(base64.as_bytes()).map_err(serde::de::Error::custom)
     }
 }
+
+mod base64_map {
+    use serde::{Deserialize, Serialize};
+    use serde::{Deserializer, Serializer};
+    use std::collections::BTreeMap;
+
+    pub fn serialize<S: Serializer>(
+        v: &BTreeMap<String, Vec<u8>>,
+        s: S,
+    ) -> Result<S::Ok, S::Error> {
+        let map: BTreeMap<&String, String> =
+            v.iter().map(|(k, v)| (k, base64::encode(v))).collect();
+        map.serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        d: D,
+    ) -> Result<BTreeMap<String, Vec<u8>>, D::Error> {
+        let map = BTreeMap::<String, String>::deserialize(d)?;
+        map.into_iter()
+            .map(|(k, v)| {
+                let data = base64::decode(v.as_bytes()).map_err(serde::de::Error::custom)?;
+                Ok((k, data))
+            })
+            .collect()
+    }
+}
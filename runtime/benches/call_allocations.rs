@@ -0,0 +1,90 @@
+//! Counts the heap allocations made by each plugin call, run using `cargo bench --bench
+//! call_allocations`
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use extism_runtime::{sdk, Context};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+// Reads the input one byte at a time and copies it to the output
+const WASM: &str = r#"(module
+    (import "env" "extism_input_length" (func $input_length (result i64)))
+    (import "env" "extism_input_load_u8" (func $input_load_u8 (param i64) (result i32)))
+    (import "env" "extism_alloc" (func $alloc (param i64) (result i64)))
+    (import "env" "extism_store_u8" (func $store_u8 (param i64 i32)))
+    (import "env" "extism_output_set" (func $output_set (param i64 i64)))
+    (memory (export "memory") 1)
+    (func (export "run") (result i32)
+        (local $len i64) (local $offs i64) (local $i i64)
+        (local.set $len (call $input_length))
+        (local.set $offs (call $alloc (local.get $len)))
+        (block $done
+            (loop $copy
+                (br_if $done (i64.ge_u (local.get $i) (local.get $len)))
+                (call $store_u8
+                    (i64.add (local.get $offs) (local.get $i))
+                    (call $input_load_u8 (local.get $i)))
+                (local.set $i (i64.add (local.get $i) (i64.const 1)))
+                (br $copy)))
+        (call $output_set (local.get $offs) (local.get $len))
+        i32.const 0))"#;
+
+const CALLS: usize = 1000;
+
+fn main() {
+    let ctx = Context::new();
+    let plugin = ctx.new_plugin(WASM, false);
+    assert!(plugin >= 0, "unable to create plugin");
+
+    for size in [16, 1024, 64 * 1024] {
+        let input = vec![b'a'; size];
+        let call = || unsafe {
+            let rc = sdk::extism_plugin_call(
+                &ctx as *const Context as *mut Context,
+                plugin,
+                c"run".as_ptr(),
+                input.as_ptr(),
+                input.len() as u64,
+            );
+            assert_eq!(rc, 0);
+        };
+
+        // The first calls allocate buffers that are reused afterwards
+        for _ in 0..10 {
+            call();
+        }
+
+        let start = ALLOCATIONS.load(Ordering::Relaxed);
+        for _ in 0..CALLS {
+            call();
+        }
+        let n = ALLOCATIONS.load(Ordering::Relaxed) - start;
+        println!(
+            "{size} byte input: {:.2} allocations per call",
+            n as f64 / CALLS as f64
+        );
+    }
+}
@@ -1,14 +1,45 @@
 fn main() {
+    // Functions marked `#[cfg(unix)]` are only declared when `EXTISM_UNIX` is defined, macOS
+    // doesn't define `__unix__` so it's checked separately
+    let mut config = cbindgen::Config::default();
+    config
+        .defines
+        .insert("unix".to_string(), "EXTISM_UNIX".to_string());
+
     if let Ok(bindings) = cbindgen::Builder::new()
+        .with_config(config)
         .with_crate(".")
         .with_language(cbindgen::Language::C)
         .with_no_includes()
         .with_sys_include("stdint.h")
         .with_sys_include("stdbool.h")
         .with_pragma_once(true)
+        .with_after_include(
+            "\n#if !defined(EXTISM_UNIX) && (defined(__unix__) || defined(__APPLE__))\n\
+             #define EXTISM_UNIX\n\
+             #endif",
+        )
         .rename_item("Size", "ExtismSize")
         .rename_item("PluginIndex", "ExtismPlugin")
         .rename_item("Context", "ExtismContext")
+        .rename_item("RetainedOutput", "ExtismRetainedOutput")
+        .rename_item("CallPhase", "ExtismCallPhase")
+        .rename_item("CallObserverCallback", "ExtismCallObserver")
+        .rename_item("IoVec", "ExtismIoVec")
+        .rename_item("BatchCall", "ExtismBatchCall")
+        .rename_item("BatchOutputCallback", "ExtismBatchOutputCallback")
+        .rename_item("TimeoutHandlerCallback", "ExtismTimeoutHandler")
+        .rename_item("EmitCallback", "ExtismEmitCallback")
+        .rename_item("Internal", "ExtismCurrentPlugin")
+        .rename_item("ValType", "ExtismValType")
+        .rename_item("ValUnion", "ExtismValUnion")
+        .rename_item("FunctionCallback", "ExtismFunctionType")
+        .rename_item("FunctionDefinition", "ExtismFunctionDefinition")
+        .rename_item("FreeUserDataCallback", "ExtismFreeUserData")
+        .rename_item("CancelHandle", "ExtismCancelHandle")
+        .rename_item("LogLevel", "ExtismLogLevel")
+        .rename_item("LogCallback", "ExtismLogCallback")
+        .rename_item("Snapshot", "ExtismSnapshot")
         .generate()
     {
         bindings.write_to_file("extism.h");
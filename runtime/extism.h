@@ -3,22 +3,273 @@
 #include <stdint.h>
 #include <stdbool.h>
 
+#if !defined(EXTISM_UNIX) && (defined(__unix__) || defined(__APPLE__))
+#define EXTISM_UNIX
+#endif
+
+/**
+ * Identifies whether a `CallObserver` is being notified before or after a call
+ */
+typedef enum ExtismCallPhase {
+  ExtismCallPhase_Start,
+  ExtismCallPhase_End,
+} ExtismCallPhase;
+
+/**
+ * The level of a message passed to a log callback
+ */
+typedef enum ExtismLogLevel {
+  ExtismLogLevel_Error = 1,
+  ExtismLogLevel_Warn,
+  ExtismLogLevel_Info,
+  ExtismLogLevel_Debug,
+  ExtismLogLevel_Trace,
+} ExtismLogLevel;
+
+/**
+ * A list of all possible value types in WebAssembly.
+ *
+ */
+typedef enum ExtismValType {
+  /**
+   * Signed 32 bit integer.
+   */
+  ExtismValType_I32,
+  /**
+   * Signed 64 bit integer.
+   */
+  ExtismValType_I64,
+  /**
+   * Floating point 32 bit integer.
+   */
+  ExtismValType_F32,
+  /**
+   * Floating point 64 bit integer.
+   */
+  ExtismValType_F64,
+  /**
+   * A 128 bit number.
+   */
+  ExtismValType_V128,
+  /**
+   * A reference to a Wasm function.
+   */
+  ExtismValType_FuncRef,
+  /**
+   * A reference to opaque data in the Wasm instance.
+   */
+  ExtismValType_ExternRef,
+} ExtismValType;
+
+/**
+ * Used to cancel a running call from another thread
+ */
+typedef struct ExtismCancelHandle ExtismCancelHandle;
+
 /**
  * A `Context` is used to store and manage plugins
+ *
+ * Every method that's used by the C API takes `&self`, the plugin registry and error are
+ * locked internally so a thread-safe context can be shared between threads
  */
 typedef struct ExtismContext ExtismContext;
 
+typedef struct ExtismCurrentPlugin ExtismCurrentPlugin;
+
+/**
+ * A copy of a plugin's output that stays valid across calls, see `extism_plugin_output_retain`
+ */
+typedef struct ExtismRetainedOutput ExtismRetainedOutput;
+
+/**
+ * A copy of a plugin's memory that can be restored later using `Plugin::restore`
+ *
+ * This includes the Extism memory and its allocations, every memory and mutable global exported
+ * by the plugin and the location of the output. Globals that aren't exported, such as the
+ * `__stack_pointer` used by most compilers, and tables can't be read from outside the plugin so
+ * they aren't included, restoring a snapshot isn't a complete rollback when a plugin relies on
+ * them
+ */
+typedef struct ExtismSnapshot ExtismSnapshot;
+
 typedef int32_t ExtismPlugin;
 
+typedef void (*ExtismCallObserver)(ExtismPlugin plugin,
+                                   const char *func_name,
+                                   enum ExtismCallPhase phase,
+                                   int32_t rc,
+                                   void *user_data);
+
 typedef uint64_t ExtismSize;
 
+/**
+ * A union of the value types that can be passed to and returned from host functions
+ */
+typedef union ExtismValUnion {
+  int32_t i32;
+  int64_t i64;
+  float f32;
+  double f64;
+} ExtismValUnion;
+
+/**
+ * A value passed to or returned from a host function
+ */
+typedef struct ExtismVal {
+  enum ExtismValType t;
+  union ExtismValUnion v;
+} ExtismVal;
+
+/**
+ * The signature of a host function
+ *
+ * `plugin`: the plugin calling the function, it can be used to access the plugin's memory
+ * `inputs`: the function's arguments
+ * `outputs`: the function's results, these should be set by the callback
+ */
+typedef void (*ExtismFunctionType)(struct ExtismCurrentPlugin *plugin,
+                                   const struct ExtismVal *inputs,
+                                   ExtismSize n_inputs,
+                                   struct ExtismVal *outputs,
+                                   ExtismSize n_outputs,
+                                   void *user_data);
+
+/**
+ * Called to free the `user_data` of a host function once the function is no longer used
+ */
+typedef void (*ExtismFreeUserData)(void *user_data);
+
+/**
+ * A host function that can be imported by a plugin
+ *
+ * `free_user_data` was added after `user_data`, which changes the size of this struct: code
+ * built against an older `extism.h` must be rebuilt and initialize the new field
+ */
+typedef struct ExtismFunctionDefinition {
+  /**
+   * The name of the function
+   */
+  const char *name;
+  /**
+   * The module the function is defined in, `NULL` uses `env`
+   */
+  const char *namespace_;
+  const enum ExtismValType *params;
+  ExtismSize n_params;
+  const enum ExtismValType *results;
+  ExtismSize n_results;
+  ExtismFunctionType func;
+  /**
+   * Passed to `func` each time it is called
+   */
+  void *user_data;
+  /**
+   * Called with `user_data` when the plugin is freed, this can be `NULL`. When functions
+   * passed to the same `extism_plugin_new_with_functions` call share `user_data` it's only
+   * freed once, sharing `user_data` between plugins is the responsibility of the host
+   */
+  ExtismFreeUserData free_user_data;
+} ExtismFunctionDefinition;
+
+typedef void (*ExtismTimeoutHandler)(ExtismPlugin plugin,
+                                     const uint8_t *memory,
+                                     ExtismSize memory_len,
+                                     void *user_data);
+
+/**
+ * A single segment of input for `extism_plugin_call_vectored`
+ */
+typedef struct ExtismIoVec {
+  const uint8_t *ptr;
+  ExtismSize len;
+} ExtismIoVec;
+
+typedef void (*ExtismEmitCallback)(ExtismPlugin plugin,
+                                   const uint8_t *data,
+                                   ExtismSize data_len,
+                                   void *user_data);
+
+/**
+ * A single call for `extism_plugin_call_batch`
+ */
+typedef struct ExtismBatchCall {
+  const char *func_name;
+  const uint8_t *data;
+  ExtismSize data_len;
+} ExtismBatchCall;
+
+/**
+ * Called by `extism_plugin_call_batch` after each call with the index of the call, its return
+ * code and its output, which is only valid until the callback returns
+ */
+typedef void (*ExtismBatchOutputCallback)(ExtismSize index,
+                                          int32_t rc,
+                                          const uint8_t *output,
+                                          ExtismSize output_len,
+                                          void *user_data);
+
+typedef void (*ExtismLogCallback)(enum ExtismLogLevel level,
+                                  const char *target,
+                                  const char *message,
+                                  void *user_data);
+
 /**
  * Create a new context
  */
 struct ExtismContext *extism_context_new(void);
 
 /**
- * Free a context
+ * Create a new context using a JSON object to configure how plugins are created
+ *
+ * `json`: a NULL-terminated JSON object, the supported fields are `wasm_simd`,
+ * `wasm_reference_types`, `wasm_bulk_memory` and `wasm_multi_value`, which enable or disable
+ * the matching WASM features. Plugins that use a disabled feature will fail to load.
+ * `async_support` allows the plugins to be called from async Rust code
+ * `wasm_backtrace` enables or disables capturing backtraces when a call fails, they're enabled
+ * by default
+ * `epoch_interval_ms` sets how often plugin timeouts are checked, between 1 and 60000
+ * milliseconds
+ * `max_table_elements`, `max_instances`, `max_memories` and `max_tables` limit the resources
+ * each plugin can use, plugins that exceed them fail to load and calls fail with `resource
+ * limit exceeded` when a table can't grow. Both set the `ResourceLimit` error code when a plugin
+ * is reinstantiated or reset
+ * `consume_fuel` meters the fuel used by every plugin, otherwise only plugins that set `fuel` in
+ * their manifest are metered
+ * Returns `NULL` if the config is invalid
+ */
+struct ExtismContext *extism_context_new_with_config(const char *json);
+
+/**
+ * Create a new context that can be used from multiple threads
+ *
+ * Access to the plugin registry is synchronized, and each plugin is locked while it's being
+ * called, so distinct plugins can be called in parallel while calls to the same plugin wait for
+ * each other. The context error is shared between threads, so plugin errors should be preferred
+ * when checking why a call failed
+ */
+struct ExtismContext *extism_context_new_threadsafe(void);
+
+/**
+ * Set a callback that will be called at the start and end of every call to
+ * `extism_plugin_call` on a plugin in this context, passing `NULL` removes the observer
+ *
+ * The callback receives the plugin ID, the function name, the `ExtismCallPhase`, the return
+ * code of the call (or -1 when the call is starting) and `user_data`. In a thread-safe context
+ * the callback is called on whichever thread is making the call
+ */
+void extism_context_set_call_observer(struct ExtismContext *ctx,
+                                      ExtismCallObserver callback,
+                                      void *user_data);
+
+/**
+ * Set the maximum number of compiled modules cached by a context, the least recently used
+ * module is evicted when the cache is full. Passing `0` disables the cache
+ */
+void extism_context_set_module_cache_size(struct ExtismContext *ctx, ExtismSize size);
+
+/**
+ * Free a context, in thread-safe contexts any calls running on other threads are cancelled and
+ * the plugins are freed once those calls return
  */
 void extism_context_free(struct ExtismContext *ctx);
 
@@ -34,6 +285,123 @@ ExtismPlugin extism_plugin_new(struct ExtismContext *ctx,
                                ExtismSize wasm_size,
                                bool with_wasi);
 
+/**
+ * Create a new plugin from a file, the file is loaded the same way as the `wasm` argument to
+ * `extism_plugin_new`
+ *
+ * `path`: a NULL-terminated path to a WASM module (wat or wasm) or a JSON encoded manifest
+ * `with_wasi`: enables/disables WASI
+ */
+ExtismPlugin extism_plugin_new_from_file(struct ExtismContext *ctx,
+                                         const char *path,
+                                         bool with_wasi);
+
+/**
+ * Create a new plugin with host functions
+ *
+ * `wasm`: is a WASM module (wat or wasm) or a JSON encoded manifest
+ * `wasm_size`: the length of the `wasm` parameter
+ * `with_wasi`: enables/disables WASI
+ * `functions`: an array of `n_functions` host functions that the plugin can import
+ *
+ * Functions that share `user_data` must use the same `free_user_data` callback (or `NULL`),
+ * otherwise this fails. If this fails every `user_data` with a `free_user_data` callback has
+ * been freed once, using the first callback given for it
+ */
+ExtismPlugin extism_plugin_new_with_functions(struct ExtismContext *ctx,
+                                              const uint8_t *wasm,
+                                              ExtismSize wasm_size,
+                                              bool with_wasi,
+                                              const struct ExtismFunctionDefinition *functions,
+                                              ExtismSize n_functions);
+
+/**
+ * Get a pointer to the start of the memory of the plugin calling a host function, offsets
+ * passed to host functions are relative to this pointer
+ */
+uint8_t *extism_current_plugin_memory(struct ExtismCurrentPlugin *plugin);
+
+/**
+ * Allocate a block of memory in the plugin calling a host function
+ *
+ * Returns the offset of the new block, or 0 if the allocation failed
+ */
+uint64_t extism_current_plugin_memory_alloc(struct ExtismCurrentPlugin *plugin, ExtismSize n);
+
+/**
+ * Get the length of a block of memory allocated in the plugin calling a host function
+ */
+ExtismSize extism_current_plugin_memory_length(struct ExtismCurrentPlugin *plugin, uint64_t offset);
+
+/**
+ * Free a block of memory allocated in the plugin calling a host function
+ */
+void extism_current_plugin_memory_free(struct ExtismCurrentPlugin *plugin, uint64_t offset);
+
+/**
+ * Stop time from counting towards the timeout of the plugin calling a host function until
+ * `extism_resume_timeout` is called. Host functions are already called with the timeout paused,
+ * pauses can be nested and the timeout only resumes once each one has been resumed
+ */
+bool extism_pause_timeout(struct ExtismCurrentPlugin *plugin);
+
+/**
+ * Resume a timeout paused using `extism_pause_timeout`
+ */
+bool extism_resume_timeout(struct ExtismCurrentPlugin *plugin);
+
+/**
+ * Create a new plugin using a JSON object to specify creation options
+ *
+ * `wasm`: is a WASM module (wat or wasm) or a JSON encoded manifest
+ * `wasm_size`: the length of the `wasm` parameter
+ * `opts_json`: a NULL-terminated JSON object, the supported fields are `wasi`, `timeout_ms`,
+ * `id_sequence`, `lock_env`, `rollback_on_error`, `zero_memory_on_reset` and `inherit_env`.
+ * Passing `NULL` uses the default options, WASI plugins only see environment variables from
+ * their config unless `inherit_env` is set
+ */
+ExtismPlugin extism_plugin_new_opts(struct ExtismContext *ctx,
+                                    const uint8_t *wasm,
+                                    ExtismSize wasm_size,
+                                    const char *opts_json);
+
+/**
+ * Check that a JSON manifest is valid without creating a plugin, this parses the manifest and
+ * validates any WASM modules it references. URLs are checked but not fetched
+ *
+ * `err_out`: if validation fails, receives a NUL-terminated error message, which is valid until
+ * the next call to `extism_manifest_validate` on the same thread. This may be `NULL`
+ */
+bool extism_manifest_validate(const uint8_t *json, ExtismSize json_size, const char **err_out);
+
+/**
+ * Create a new plugin from data serialized using `extism_plugin_serialize`, this skips
+ * compilation
+ *
+ * The serialized data is tied to the version of Extism and the target it was created on,
+ * loading it anywhere else will fail with an error
+ *
+ * `data` contains native code that is loaded without being verified, it must come from a
+ * trusted source. Loading untrusted data can run arbitrary code in the host process
+ */
+ExtismPlugin extism_plugin_new_precompiled(struct ExtismContext *ctx,
+                                           const uint8_t *data,
+                                           ExtismSize data_size,
+                                           bool with_wasi);
+
+/**
+ * Serialize a plugin's compiled modules and manifest so the plugin can be loaded using
+ * `extism_plugin_new_precompiled`, secret config values are left out
+ *
+ * `out`: receives a pointer to the serialized plugin, which is valid until the next call to
+ * `extism_plugin_serialize` or until the plugin is freed
+ * `out_len`: receives the length of the serialized plugin
+ */
+bool extism_plugin_serialize(struct ExtismContext *ctx,
+                             ExtismPlugin plugin,
+                             const uint8_t **out,
+                             ExtismSize *out_len);
+
 /**
  * Update a plugin, keeping the existing ID
  *
@@ -48,24 +416,325 @@ bool extism_plugin_update(struct ExtismContext *ctx,
                           ExtismSize wasm_size,
                           bool with_wasi);
 
+/**
+ * Update a plugin, keeping the existing ID
+ *
+ * Similar to `extism_plugin_update` but takes a `bypass_cache` argument, when it's set the
+ * module is always compiled again, even if a module with the same WASM is in the context's
+ * module cache, and the result isn't added to the cache
+ */
+bool extism_plugin_update_with_cache(struct ExtismContext *ctx,
+                                     ExtismPlugin index,
+                                     const uint8_t *wasm,
+                                     ExtismSize wasm_size,
+                                     bool with_wasi,
+                                     bool bypass_cache);
+
+/**
+ * Reset a plugin to a fresh state without recompiling it, this reinstantiates the module and
+ * clears the plugin's memory and output. The contents of the memory are only overwritten if
+ * enabled using `extism_plugin_set_zero_memory`
+ */
+bool extism_plugin_reset(struct ExtismContext *ctx, ExtismPlugin plugin);
+
+/**
+ * Get the time spent running the plugin's most recent call in nanoseconds, this only includes
+ * the time spent executing WASM and host functions. Returns 0 if the plugin hasn't been called
+ */
+uint64_t extism_plugin_last_call_duration_ns(struct ExtismContext *ctx, ExtismPlugin plugin);
+
+/**
+ * Get the WASM backtrace from the plugin's last failed call, formatted with one frame per line
+ *
+ * Returns NULL if the last call didn't fail with a backtrace, or backtraces are disabled using
+ * the `wasm_backtrace` context config. The returned string is valid until the plugin is called
+ * again
+ */
+const char *extism_plugin_last_backtrace(struct ExtismContext *ctx, ExtismPlugin plugin);
+
+/**
+ * Get the plugin's call metrics as a JSON object with the fields:
+ * - `calls`: the number of calls
+ * - `errors`: the number of calls that failed
+ * - `timeouts`: the number of calls that timed out
+ * - `execution_time_ns`: the total time spent running calls in nanoseconds
+ *
+ * The returned string is valid until the next call to this function for the same plugin
+ */
+const char *extism_plugin_metrics_json(struct ExtismContext *ctx, ExtismPlugin plugin);
+
+/**
+ * Set all of the plugin's call metrics to zero
+ */
+bool extism_plugin_metrics_reset(struct ExtismContext *ctx, ExtismPlugin plugin);
+
+/**
+ * Allocate `size` bytes in a plugin's memory, the offset can be passed to the plugin as a
+ * function argument. The block stays allocated between calls until it's released using
+ * `extism_plugin_free_block` or the plugin is reset
+ *
+ * Returns the offset of the block, or 0 if the memory couldn't be allocated
+ */
+ExtismSize extism_plugin_alloc(struct ExtismContext *ctx, ExtismPlugin plugin, ExtismSize size);
+
+/**
+ * Copy `data` into a block allocated using `extism_plugin_alloc`
+ *
+ * `offset`: the offset of the block
+ * `data_len`: the number of bytes to write, this can't be larger than the block
+ */
+bool extism_plugin_write(struct ExtismContext *ctx,
+                         ExtismPlugin plugin,
+                         ExtismSize offset,
+                         const uint8_t *data,
+                         ExtismSize data_len);
+
+/**
+ * Free a block allocated using `extism_plugin_alloc`, returns false if there is no block at
+ * `offset`
+ */
+bool extism_plugin_free_block(struct ExtismContext *ctx, ExtismPlugin plugin, ExtismSize offset);
+
+/**
+ * Get the number of bytes currently used by a plugin's memory, this includes the Extism memory
+ * and all memories exported by the plugin
+ */
+ExtismSize extism_plugin_memory_used(struct ExtismContext *ctx, ExtismPlugin plugin);
+
+/**
+ * Get the largest number of bytes used by a plugin's memory since it was created or last reset
+ * using `extism_plugin_reset`
+ */
+ExtismSize extism_plugin_memory_high_water(struct ExtismContext *ctx, ExtismPlugin plugin);
+
 /**
  * Remove a plugin from the registry and free associated memory
  */
 void extism_plugin_free(struct ExtismContext *ctx, ExtismPlugin plugin);
 
+/**
+ * Create a pool of plugins that can be used to call the same WASM without waiting for other
+ * calls to finish, instances are created when they're needed up to `pool_size` and reused
+ * between calls
+ *
+ * `wasm`: is a WASM module (wat or wasm) or a JSON encoded manifest
+ * `wasm_size`: the length of the `wasm` parameter
+ * `with_wasi`: enables/disables WASI
+ * Returns the pool ID, or -1 if the first instance couldn't be created
+ */
+ExtismPlugin extism_plugin_pool_new(struct ExtismContext *ctx,
+                                    const uint8_t *wasm,
+                                    ExtismSize wasm_size,
+                                    bool with_wasi,
+                                    ExtismSize pool_size);
+
+/**
+ * Check out an idle plugin from a pool, the plugin can be used with any `extism_plugin_*`
+ * function and should be returned using `extism_plugin_pool_checkin`
+ *
+ * Returns the plugin ID, or -1 if no plugin could be checked out
+ */
+ExtismPlugin extism_plugin_pool_checkout(struct ExtismContext *ctx, ExtismPlugin pool);
+
+/**
+ * Return a plugin to a pool after calling `extism_plugin_pool_checkout`
+ */
+void extism_plugin_pool_checkin(struct ExtismContext *ctx, ExtismPlugin pool, ExtismPlugin plugin);
+
+/**
+ * Call a function using a plugin from a pool, the plugin is checked out for the duration of the
+ * call
+ *
+ * `plugin_out`: receives the ID of the plugin that was called, which can be used to read the
+ * output or error. The plugin is returned to the pool when the call finishes, so when the
+ * context is shared between threads `extism_plugin_pool_checkout` should be used instead to
+ * make sure the output isn't replaced by another call
+ */
+int32_t extism_plugin_pool_call(struct ExtismContext *ctx,
+                                ExtismPlugin pool,
+                                const char *func_name,
+                                const uint8_t *data,
+                                ExtismSize data_len,
+                                ExtismPlugin *plugin_out);
+
+/**
+ * Remove a pool and all of its plugins
+ */
+void extism_plugin_pool_free(struct ExtismContext *ctx, ExtismPlugin pool);
+
 /**
  * Remove all plugins from the registry
  */
 void extism_context_reset(struct ExtismContext *ctx);
 
 /**
- * Update plugin config values, this will merge with the existing values
+ * Get the number of plugins in a context
+ */
+ExtismSize extism_context_plugin_count(struct ExtismContext *ctx);
+
+/**
+ * Get the IDs of all plugins in a context
+ *
+ * `out`: a buffer that receives up to `out_len` plugin IDs
+ * Returns the total number of plugins, which may be larger than `out_len`
+ */
+ExtismSize extism_context_plugin_ids(struct ExtismContext *ctx,
+                                     ExtismPlugin *out,
+                                     ExtismSize out_len);
+
+/**
+ * Get the IDs of all plugins in a context, sorted by memory usage with the largest first
+ *
+ * `out`: a buffer that receives up to `out_len` plugin IDs
+ * Returns the total number of plugins, which may be larger than `out_len`
+ */
+ExtismSize extism_context_plugins_by_memory(struct ExtismContext *ctx,
+                                            ExtismPlugin *out,
+                                            ExtismSize out_len);
+
+/**
+ * Get the IDs of all plugins in a context, sorted by the time of their last call with the
+ * least recently used first
+ *
+ * `out`: a buffer that receives up to `out_len` plugin IDs
+ * Returns the total number of plugins, which may be larger than `out_len`
+ */
+ExtismSize extism_context_plugins_by_last_used(struct ExtismContext *ctx,
+                                               ExtismPlugin *out,
+                                               ExtismSize out_len);
+
+/**
+ * Update plugin config values, this will merge with the existing values. Keys listed in the
+ * manifest's `secret_config` are never logged or added to the WASI environment
  */
 bool extism_plugin_config(struct ExtismContext *ctx,
                           ExtismPlugin plugin,
                           const uint8_t *json,
                           ExtismSize json_size);
 
+/**
+ * Make the WASI random source deterministic by replacing it with a PRNG seeded with `seed`, the
+ * PRNG is reseeded whenever the plugin is reinstantiated. Returns false if WASI isn't enabled
+ */
+bool extism_plugin_set_random_seed(struct ExtismContext *ctx, ExtismPlugin plugin, uint64_t seed);
+
+/**
+ * Set the time reported by the WASI clocks to `nanos` since the Unix epoch, the clocks stay at
+ * this time until it's set again. The monotonic clock never moves backwards, and timeouts are
+ * still measured using the host clock. Returns false if WASI isn't enabled
+ */
+bool extism_plugin_set_clock(struct ExtismContext *ctx, ExtismPlugin plugin, uint64_t nanos);
+
+/**
+ * Make the IDs returned by the `extism_generate_id` host function deterministic, starting
+ * from `seed`
+ */
+bool extism_plugin_set_id_sequence(struct ExtismContext *ctx, ExtismPlugin plugin, uint64_t seed);
+
+/**
+ * Stop `extism_plugin_config` from updating the plugin's WASI environment, config values will
+ * still be updated
+ */
+bool extism_plugin_lock_env(struct ExtismContext *ctx, ExtismPlugin plugin);
+
+/**
+ * When enabled, the plugin's memory is snapshotted before each call and restored if the call
+ * fails, so a failed call doesn't affect later calls. This makes calls slower because the memory
+ * is copied every time
+ *
+ * The rollback isn't transactional: globals that aren't exported, like `__stack_pointer`, and
+ * tables can't be restored, see `extism_plugin_snapshot`
+ */
+bool extism_plugin_set_rollback(struct ExtismContext *ctx, ExtismPlugin plugin, bool enabled);
+
+/**
+ * Enable or disable zeroing the plugin's memory when it's reset or reinstantiated
+ *
+ * By default only the allocator is reset, so data from earlier calls stays in memory until it's
+ * overwritten. When enabled, the entire memory is zeroed so one input can't be read while
+ * handling the next. This makes resets slower for plugins with large memories
+ */
+bool extism_plugin_set_zero_memory(struct ExtismContext *ctx, ExtismPlugin plugin, bool enabled);
+
+/**
+ * Get a plugin config value, returns false if `key` isn't set
+ *
+ * `out`: receives a pointer to the value, which is valid until the plugin's config is updated
+ * `out_len`: receives the length of the value
+ */
+bool extism_plugin_config_get(struct ExtismContext *ctx,
+                              ExtismPlugin plugin,
+                              const char *key,
+                              const uint8_t **out,
+                              ExtismSize *out_len);
+
+/**
+ * Set the command-line arguments passed to a WASI plugin, `argv` should contain `argc`
+ * NUL-terminated strings
+ *
+ * Changing the arguments requires the plugin to be reinstantiated, this happens automatically
+ * before the next call, so any state in the plugin's memory will be reset
+ */
+bool extism_plugin_set_args(struct ExtismContext *ctx,
+                            ExtismPlugin plugin,
+                            const char *const *argv,
+                            ExtismSize argc);
+
+/**
+ * Set a callback that is called when a call to the plugin times out, passing `NULL` removes
+ * the handler
+ *
+ * The callback runs on the calling thread before `extism_plugin_call` returns, it receives the
+ * plugin ID, a pointer to the plugin's memory and its length, and `user_data`
+ */
+bool extism_plugin_set_timeout_handler(struct ExtismContext *ctx,
+                                       ExtismPlugin plugin,
+                                       ExtismTimeoutHandler callback,
+                                       void *user_data);
+
+/**
+ * Get a handle that can be used to cancel calls to a plugin from another thread, this doesn't
+ * wait for a call that's running. The handle stays valid after the plugin is freed or updated,
+ * but it only cancels calls to the plugin as it was when the handle was created. It must be
+ * released using `extism_plugin_cancel_handle_free`
+ */
+const struct ExtismCancelHandle *extism_plugin_cancel_handle(struct ExtismContext *ctx,
+                                                             ExtismPlugin plugin);
+
+/**
+ * Free a handle returned by `extism_plugin_cancel_handle`
+ */
+void extism_plugin_cancel_handle_free(const struct ExtismCancelHandle *handle);
+
+/**
+ * Cancel the call that is currently running on the plugin associated with `handle`, the call
+ * will fail with the error `cancelled`. This can be called from any thread
+ */
+bool extism_plugin_cancel(const struct ExtismCancelHandle *handle);
+
+/**
+ * Limit the size of each of a plugin's memories to `max_pages` 64KiB pages, `0` removes the
+ * limit. Calls fail with `memory limit exceeded` when a memory tries to grow beyond the limit
+ */
+bool extism_plugin_set_memory_limit(struct ExtismContext *ctx,
+                                    ExtismPlugin plugin,
+                                    uint32_t max_pages);
+
+/**
+ * Set the amount of fuel available to a plugin, replacing any remaining fuel. Most WASM
+ * instructions consume one unit of fuel and calls fail with `fuel exhausted` once it runs out
+ *
+ * This fails unless the plugin's manifest sets `fuel` or the context config enables
+ * `consume_fuel`
+ */
+bool extism_plugin_set_fuel(struct ExtismContext *ctx, ExtismPlugin plugin, uint64_t fuel);
+
+/**
+ * Get the amount of fuel remaining for a plugin, or -1 on error. Plugins that aren't metered
+ * always report `INT64_MAX`
+ */
+int64_t extism_plugin_fuel_remaining(struct ExtismContext *ctx, ExtismPlugin plugin);
+
 /**
  * Returns true if `func_name` exists
  */
@@ -73,12 +742,53 @@ bool extism_plugin_function_exists(struct ExtismContext *ctx,
                                    ExtismPlugin plugin,
                                    const char *func_name);
 
+/**
+ * Get the parameter and result types of the function named `func_name`
+ *
+ * `params_out`: a buffer that receives up to `*n_params` parameter types
+ * `n_params`: the length of `params_out`, this is set to the number of parameters
+ * `results_out`: a buffer that receives up to `*n_results` result types
+ * `n_results`: the length of `results_out`, this is set to the number of results
+ * Returns false if the function doesn't exist
+ */
+bool extism_plugin_function_signature(struct ExtismContext *ctx,
+                                      ExtismPlugin plugin,
+                                      const char *func_name,
+                                      enum ExtismValType *params_out,
+                                      ExtismSize *n_params,
+                                      enum ExtismValType *results_out,
+                                      ExtismSize *n_results);
+
+/**
+ * Get the number of results returned by the function named `func_name`, this can be used to
+ * size the buffer passed to `extism_plugin_call_multi`
+ *
+ * Returns -1 if the function doesn't exist
+ */
+int32_t extism_plugin_function_result_count(struct ExtismContext *ctx,
+                                            ExtismPlugin plugin,
+                                            const char *func_name);
+
+/**
+ * Copy the 16 byte UUID of a plugin into `out`, unlike the plugin index the UUID is never
+ * reused. It stays the same when the config is updated but changes when the plugin is updated
+ */
+bool extism_plugin_id(struct ExtismContext *ctx, ExtismPlugin plugin, uint8_t *out);
+
+/**
+ * Get the number of functions exported by a plugin
+ */
+ExtismSize extism_plugin_function_count(struct ExtismContext *ctx, ExtismPlugin plugin);
+
 /**
  * Call a function
  *
  * `func_name`: is the function to call
  * `data`: is the input data
  * `data_len`: is the length of `data`
+ *
+ * Calling a plugin from one of its own host functions fails with the error
+ * `plugin is already executing`
  */
 int32_t extism_plugin_call(struct ExtismContext *ctx,
                            ExtismPlugin plugin_id,
@@ -86,12 +796,251 @@ int32_t extism_plugin_call(struct ExtismContext *ctx,
                            const uint8_t *data,
                            ExtismSize data_len);
 
+/**
+ * Look up a function so it can be called using `extism_plugin_call_by_handle`, which avoids
+ * looking up the function by name for each call. Handles are invalidated when the plugin is
+ * reset or updated
+ *
+ * Returns the handle, or -1 if the function doesn't exist
+ */
+int32_t extism_plugin_function_resolve(struct ExtismContext *ctx,
+                                       ExtismPlugin plugin,
+                                       const char *func_name);
+
+/**
+ * Call a function using a handle returned by `extism_plugin_function_resolve`, the call fails
+ * if the handle was resolved before the plugin was last reset or updated. The call observer
+ * receives `NULL` as the function name when the handle is invalid
+ *
+ * `handle`: the function handle
+ * `data`: is the input data
+ * `data_len`: is the length of `data`
+ */
+int32_t extism_plugin_call_by_handle(struct ExtismContext *ctx,
+                                     ExtismPlugin plugin_id,
+                                     int32_t handle,
+                                     const uint8_t *data,
+                                     ExtismSize data_len);
+
+/**
+ * Call a function that returns multiple results
+ *
+ * `results`: a buffer that receives up to `results_len` of the function's results, all results
+ * must be `i32` or `i64`
+ * Returns the number of results returned by the function, which may be larger than
+ * `results_len`, or -1 on error
+ */
+int64_t extism_plugin_call_multi(struct ExtismContext *ctx,
+                                 ExtismPlugin plugin_id,
+                                 const char *func_name,
+                                 const uint8_t *data,
+                                 ExtismSize data_len,
+                                 int64_t *results,
+                                 ExtismSize results_len);
+
+/**
+ * Get the value of a global exported by a plugin
+ *
+ * `name`: the name of the global
+ * `out`: receives the value, only numeric globals are supported
+ */
+bool extism_plugin_get_global(struct ExtismContext *ctx,
+                              ExtismPlugin plugin,
+                              const char *name,
+                              struct ExtismVal *out);
+
+/**
+ * Set the value of a mutable global exported by a plugin, this fails if the global is immutable
+ * or `val` doesn't match the global's type
+ *
+ * `name`: the name of the global
+ * `val`: the new value
+ */
+bool extism_plugin_set_global(struct ExtismContext *ctx,
+                              ExtismPlugin plugin,
+                              const char *name,
+                              struct ExtismVal val);
+
+/**
+ * Call a function with typed arguments instead of input data
+ *
+ * `args`: the `n_args` arguments to pass to the function, these must match the function's
+ * parameters
+ * `results`: a buffer that receives the function's results, `n_results` must match the number
+ * of results returned by the function
+ * Returns 0 on success or -1 on error
+ */
+int32_t extism_plugin_call_typed(struct ExtismContext *ctx,
+                                 ExtismPlugin plugin_id,
+                                 const char *func_name,
+                                 const struct ExtismVal *args,
+                                 ExtismSize n_args,
+                                 struct ExtismVal *results,
+                                 ExtismSize n_results);
+
+/**
+ * Call a function with metadata that can be read by the plugin using the `extism_get_meta`
+ * host function
+ *
+ * `meta_json`: a NUL-terminated JSON object, non-string values are converted to their JSON
+ * representation
+ */
+int32_t extism_plugin_call_with_meta(struct ExtismContext *ctx,
+                                     ExtismPlugin plugin_id,
+                                     const char *func_name,
+                                     const uint8_t *data,
+                                     ExtismSize data_len,
+                                     const char *meta_json);
+
+/**
+ * Call a function with a timeout that only applies to this call, the manifest's timeout is
+ * used by other calls
+ *
+ * `timeout_ms`: the timeout in milliseconds, `0` means the call has no time limit
+ */
+int32_t extism_plugin_call_with_timeout(struct ExtismContext *ctx,
+                                        ExtismPlugin plugin_id,
+                                        const char *func_name,
+                                        const uint8_t *data,
+                                        ExtismSize data_len,
+                                        uint64_t timeout_ms);
+
+/**
+ * Call a function with input made up of multiple buffers, the buffers are concatenated in
+ * order to form the input
+ *
+ * `iovecs`: an array of `n_iovecs` input segments
+ */
+int32_t extism_plugin_call_vectored(struct ExtismContext *ctx,
+                                    ExtismPlugin plugin_id,
+                                    const char *func_name,
+                                    const struct ExtismIoVec *iovecs,
+                                    ExtismSize n_iovecs);
+
+/**
+ * Call a function, data the plugin passes to `extism_emit` while it's running is sent to
+ * `callback` immediately, so partial results can be handled before the function returns. The
+ * output set using `extism_output_set` is still available after the call
+ *
+ * `callback`: called with the plugin ID, the emitted data and `user_data`, the data is only
+ * valid until the callback returns
+ * `user_data`: passed to `callback`, it isn't used after this function returns
+ */
+int32_t extism_plugin_call_streaming(struct ExtismContext *ctx,
+                                     ExtismPlugin plugin_id,
+                                     const char *func_name,
+                                     const uint8_t *data,
+                                     ExtismSize data_len,
+                                     ExtismEmitCallback callback,
+                                     void *user_data);
+
+/**
+ * Call several functions in order against the same plugin instance using a single FFI call, the
+ * plugin stays locked until every call has been made
+ *
+ * `calls`: an array of `n_calls` calls
+ * `results_out`: an array of `n_calls` return codes, the return code of each call that was made
+ * is written to the matching index
+ * `continue_on_error`: when false, no more calls are made after the first call that fails
+ * `output`: called after each call that was made with the call's output, this can be `NULL`.
+ * The output of a call that failed is empty, and calls to the plugin from `output` fail
+ * `user_data`: passed to `output`
+ *
+ * Returns the index of the first call that failed, or -1 if every call succeeded. The output
+ * and error are those of the last call that was made
+ */
+int64_t extism_plugin_call_batch(struct ExtismContext *ctx,
+                                 ExtismPlugin plugin_id,
+                                 const struct ExtismBatchCall *calls,
+                                 ExtismSize n_calls,
+                                 int32_t *results_out,
+                                 bool continue_on_error,
+                                 ExtismBatchOutputCallback output,
+                                 void *user_data);
+
+/**
+ * Call a function using length-delimited protobuf messages for input and output
+ *
+ * The request and response are checked to be non-empty, correctly framed and no larger than
+ * 64MiB but are otherwise passed through unchanged
+ */
+int32_t extism_plugin_call_proto(struct ExtismContext *ctx,
+                                 ExtismPlugin plugin_id,
+                                 const char *func_name,
+                                 const uint8_t *request,
+                                 ExtismSize request_len);
+
+/**
+ * Call every function exported by the plugin that starts with `test_` using empty input
+ *
+ * Returns a JSON object mapping each test name to `{"passed": bool, "error": string | null}`,
+ * the returned string is valid until the next call to `extism_plugin_run_tests`
+ */
+const char *extism_plugin_run_tests(struct ExtismContext *ctx, ExtismPlugin plugin_id);
+
 /**
  * Get the error associated with a `Context` or `Plugin`, if `plugin` is `-1` then the context
  * error will be returned
  */
 const char *extism_error(struct ExtismContext *ctx, ExtismPlugin plugin);
 
+/**
+ * Get the length in bytes of the error associated with a `Context` or `Plugin`, not including
+ * the trailing NUL byte. If `plugin` is `-1` then the length of the context error will be
+ * returned. Returns 0 if no error is set.
+ */
+ExtismSize extism_error_length(struct ExtismContext *ctx, ExtismPlugin plugin);
+
+/**
+ * Get the error associated with a `Plugin` as a JSON object with the following fields:
+ * - `kind`: one of `error`, `timeout`, `cancelled`, `trap`, `wasi_exit`, `not_found`,
+ *   `bad_utf8`, `memory_limit`, `resource_limit`, `fuel_exhausted` or `invalid_output`
+ * - `code`: the code returned by `extism_plugin_last_error_code`
+ * - `message`: the same message returned by `extism_error`
+ * - `exit_code`: the WASI exit code, only set when `kind` is `wasi_exit`
+ *
+ * Returns NULL if no error is set, the returned string is valid until the next call to this
+ * function for the same plugin
+ */
+const char *extism_plugin_last_error_json(struct ExtismContext *ctx, ExtismPlugin plugin);
+
+/**
+ * Get a numeric code for the error associated with a `Plugin`, these values are stable and can
+ * be used instead of matching the error message:
+ * - `0`: no error
+ * - `1`: function not found
+ * - `2`: timeout
+ * - `3`: trap
+ * - `4`: WASI exit with a non-zero exit code
+ * - `5`: invalid UTF-8
+ * - `6`: cancelled
+ * - `7`: memory limit exceeded
+ * - `8`: fuel exhausted
+ * - `9`: output doesn't match `output_must_be`
+ * - `10`: any other error
+ * - `11`: resource limit exceeded
+ */
+int32_t extism_plugin_last_error_code(struct ExtismContext *ctx, ExtismPlugin plugin);
+
+/**
+ * Get the exit code passed to WASI `proc_exit` during the last call, this can be used to tell a
+ * plugin that exited with a non-zero code apart from a plugin that failed
+ *
+ * `code`: receives the exit code
+ * Returns false if the last call didn't exit using `proc_exit`
+ */
+bool extism_plugin_last_exit_code(struct ExtismContext *ctx, ExtismPlugin plugin, int32_t *code);
+
+/**
+ * Returns true if the plugin has an error set
+ */
+bool extism_plugin_has_error(struct ExtismContext *ctx, ExtismPlugin plugin);
+
+/**
+ * Returns true if the context has an error set
+ */
+bool extism_context_has_error(struct ExtismContext *ctx);
+
 /**
  * Get the length of a plugin's output data
  */
@@ -102,11 +1051,213 @@ ExtismSize extism_plugin_output_length(struct ExtismContext *ctx, ExtismPlugin p
  */
 const uint8_t *extism_plugin_output_data(struct ExtismContext *ctx, ExtismPlugin plugin);
 
+/**
+ * Copy part of a plugin's output data into `dst`
+ *
+ * `offset`: the position in the output to start reading from
+ * `len`: the size of `dst`
+ * Returns the number of bytes copied, which is 0 once `offset` reaches the end of the output
+ */
+ExtismSize extism_plugin_output_read(struct ExtismContext *ctx,
+                                     ExtismPlugin plugin,
+                                     ExtismSize offset,
+                                     uint8_t *dst,
+                                     ExtismSize len);
+
+/**
+ * Copy a plugin's output into `dst`, at most `dst_len` bytes are copied
+ *
+ * Returns the length of the output, if this is larger than `dst_len` then the output was
+ * truncated and a buffer of the returned length is required to read all of it
+ */
+ExtismSize extism_plugin_output_copy(struct ExtismContext *ctx,
+                                     ExtismPlugin plugin,
+                                     uint8_t *dst,
+                                     ExtismSize dst_len);
+
+/**
+ * Get the data written to stdout by a WASI plugin during the last call, at most 1MiB is
+ * captured and anything written after that is dropped
+ *
+ * `out`: receives a pointer to the data, which is valid until the next call to the plugin
+ * `out_len`: receives the length of the data, this will be 0 if WASI is not enabled
+ */
+bool extism_plugin_wasi_stdout(struct ExtismContext *ctx,
+                               ExtismPlugin plugin,
+                               const uint8_t **out,
+                               ExtismSize *out_len);
+
+/**
+ * Get the data written to stderr by a WASI plugin during the last call, at most 1MiB is
+ * captured and anything written after that is dropped
+ *
+ * `out`: receives a pointer to the data, which is valid until the next call to the plugin
+ * `out_len`: receives the length of the data, this will be 0 if WASI is not enabled
+ */
+bool extism_plugin_wasi_stderr(struct ExtismContext *ctx,
+                               ExtismPlugin plugin,
+                               const uint8_t **out,
+                               ExtismSize *out_len);
+
+/**
+ * Read a file from the in-memory filesystem created from the manifest's `files`, this can be
+ * used to get files written by the plugin
+ *
+ * `path`: the path of the file in the guest
+ * `buf`: receives up to `buf_len` bytes of the file, this can be NULL to only get the length
+ * Returns the length of the file, or -1 if the file doesn't exist
+ */
+int64_t extism_plugin_wasi_read_file(struct ExtismContext *ctx,
+                                     ExtismPlugin plugin,
+                                     const char *path,
+                                     uint8_t *buf,
+                                     ExtismSize buf_len);
+
+#if defined(EXTISM_UNIX)
+/**
+ * Write a plugin's output data directly to the file descriptor `fd`
+ *
+ * Returns the number of bytes written, or -1 if the output couldn't be written completely, in
+ * which case the error message contains the number of bytes that were written
+ */
+int64_t extism_plugin_output_to_fd(struct ExtismContext *ctx, ExtismPlugin plugin, int32_t fd);
+#endif
+
+/**
+ * Touch every page of the plugin's memory ahead of time, this avoids paying the cost of
+ * page faults during latency-sensitive calls
+ */
+bool extism_plugin_prefault_memory(struct ExtismContext *ctx, ExtismPlugin plugin);
+
+/**
+ * Prepare a plugin so the first call doesn't pay any one-time costs, this finishes any pending
+ * reinstantiation, looks up the plugin's exports and faults in its memory
+ */
+bool extism_plugin_prewarm(struct ExtismContext *ctx, ExtismPlugin plugin);
+
+/**
+ * Get the input the plugin sees through `extism_input_load_u8`/`extism_input_load_u64`,
+ * `len` is set to the input length
+ *
+ * Input isn't copied into plugin memory, so this points to the `data` that was passed to the
+ * most recent call and is only valid as long as that buffer is. Input passed to
+ * `extism_plugin_call_vectored` is written to plugin memory, in that case the pointer is valid
+ * until the next call
+ */
+const uint8_t *extism_plugin_debug_input(struct ExtismContext *ctx,
+                                         ExtismPlugin plugin,
+                                         ExtismSize *len);
+
+/**
+ * Returns true if the Extism memory or any memory exported by the plugin grew during the last
+ * call, when this happens pointers into plugin memory, like the one returned by
+ * `extism_plugin_output_data`, should be fetched again
+ */
+bool extism_plugin_memory_grew(struct ExtismContext *ctx, ExtismPlugin plugin);
+
+/**
+ * Get a pointer to the plugin's entire memory, `out_len` is set to the size of the memory in bytes
+ *
+ * This pointer is only valid until the next call to the plugin
+ */
+const uint8_t *extism_plugin_memory_export(struct ExtismContext *ctx,
+                                           ExtismPlugin plugin,
+                                           ExtismSize *out_len);
+
+/**
+ * Overwrite the plugin's entire memory with `data`, the memory will be grown if needed and
+ * any remaining bytes will be zeroed
+ */
+bool extism_plugin_memory_import(struct ExtismContext *ctx,
+                                 ExtismPlugin plugin,
+                                 const uint8_t *data,
+                                 ExtismSize data_len);
+
+/**
+ * Copy the plugin's memory so it can be restored later using `extism_plugin_restore`. This
+ * includes the Extism memory, every memory and mutable global exported by the plugin and the
+ * location of the output, but not tables or globals that aren't exported
+ *
+ * The snapshot must be freed using `extism_plugin_snapshot_free`, returns `NULL` on error
+ */
+struct ExtismSnapshot *extism_plugin_snapshot(struct ExtismContext *ctx, ExtismPlugin plugin);
+
+/**
+ * Overwrite the plugin's memory with a snapshot created by `extism_plugin_snapshot`, the
+ * snapshot can be restored more than once
+ */
+bool extism_plugin_restore(struct ExtismContext *ctx,
+                           ExtismPlugin plugin,
+                           const struct ExtismSnapshot *snapshot);
+
+/**
+ * Free a snapshot created by `extism_plugin_snapshot`
+ */
+void extism_plugin_snapshot_free(struct ExtismSnapshot *snapshot);
+
+/**
+ * Copy a plugin's output into a buffer owned by the runtime, this buffer is not affected by
+ * subsequent calls and must be freed using `extism_plugin_output_release`
+ *
+ * `data`: is set to a pointer to the output data
+ * `data_len`: is set to the length of the output data
+ */
+struct ExtismRetainedOutput *extism_plugin_output_retain(struct ExtismContext *ctx,
+                                                         ExtismPlugin plugin,
+                                                         const uint8_t **data,
+                                                         ExtismSize *data_len);
+
+/**
+ * Free output returned by `extism_plugin_output_retain`
+ */
+void extism_plugin_output_release(struct ExtismRetainedOutput *output);
+
+/**
+ * Change the log level without replacing the log destination. If logging hasn't been set up
+ * yet then logs are written to stderr
+ */
+bool extism_log_set_level(const char *log_level);
+
 /**
  * Set log file and level
  */
 bool extism_log_file(const char *filename, const char *log_level);
 
+/**
+ * Set log file and level, like `extism_log_file` but each record is written as a JSON object
+ * on its own line
+ */
+bool extism_log_file_json(const char *filename, const char *log_level);
+
+/**
+ * Send logs to a callback instead of a file, this is an alternative to `extism_log_file` and
+ * only one of them can be used
+ *
+ * The callback receives the level, target and message of each log record and `user_data`,
+ * the strings are only valid for the duration of the callback. No Extism locks are held
+ * while the callback is running
+ */
+bool extism_log_callback(ExtismLogCallback callback, void *user_data, const char *log_level);
+
+/**
+ * Write any buffered log records, when logging to a file the file is also synced to disk.
+ * This should be called before exiting to make sure no logs are lost
+ */
+void extism_log_drain(void);
+
+/**
+ * Enable or disable parallel compilation of WASM modules, this is enabled by default and
+ * only affects plugins created after it is called
+ */
+void extism_set_parallel_compilation(bool enabled);
+
+/**
+ * Set a timeout in milliseconds for plugins whose manifest doesn't specify one, a timeout set in
+ * the manifest always takes precedence. Passing `0` restores the initial setting, which is 30
+ * seconds. This only affects plugins created after it is called
+ */
+void extism_set_default_timeout_ms(uint64_t ms);
+
 /**
  * Get the Extism version string
  */
@@ -0,0 +1,156 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use cap_std::time::{Duration, Instant, SystemTime};
+use wasi_common::clocks::{WasiClocks, WasiMonotonicClock, WasiSystemClock};
+
+use crate::*;
+
+// Round `d` down to a multiple of `resolution`
+fn truncate(d: Duration, resolution: Duration) -> Duration {
+    if resolution.is_zero() {
+        return d;
+    }
+    let n = d.as_nanos() / resolution.as_nanos();
+    Duration::from_nanos((n * resolution.as_nanos()) as u64)
+}
+
+/// A system clock that only advances in steps of `resolution`
+pub(crate) struct CoarseSystemClock {
+    inner: Box<dyn WasiSystemClock>,
+    resolution: Duration,
+}
+
+impl WasiSystemClock for CoarseSystemClock {
+    fn resolution(&self) -> Duration {
+        self.resolution.max(self.inner.resolution())
+    }
+
+    fn now(&self, precision: Duration) -> SystemTime {
+        let epoch = SystemTime::from_std(std::time::UNIX_EPOCH);
+        let now = self.inner.now(precision);
+        let since_epoch = now.duration_since(epoch).unwrap_or_default();
+        epoch + truncate(since_epoch, self.resolution)
+    }
+}
+
+/// A monotonic clock that only advances in steps of `resolution`
+pub(crate) struct CoarseMonotonicClock {
+    inner: Box<dyn WasiMonotonicClock>,
+    resolution: Duration,
+    start: Instant,
+}
+
+impl WasiMonotonicClock for CoarseMonotonicClock {
+    fn resolution(&self) -> Duration {
+        self.resolution.max(self.inner.resolution())
+    }
+
+    fn now(&self, precision: Duration) -> Instant {
+        let now = self.inner.now(precision);
+        self.start + truncate(now.duration_since(self.start), self.resolution)
+    }
+}
+
+/// A monotonic clock that never advances
+pub(crate) struct FrozenMonotonicClock(Instant);
+
+impl WasiMonotonicClock for FrozenMonotonicClock {
+    fn resolution(&self) -> Duration {
+        Duration::from_nanos(1)
+    }
+
+    fn now(&self, _precision: Duration) -> Instant {
+        self.0
+    }
+}
+
+/// The time reported by a mocked clock, set by the host using `Plugin::set_clock`
+#[derive(Default)]
+pub(crate) struct MockClock {
+    now: AtomicU64,
+    monotonic: AtomicU64,
+}
+
+impl MockClock {
+    /// Set the current time in nanoseconds since the Unix epoch, the monotonic clock only moves
+    /// forward so setting an earlier time doesn't affect it
+    pub fn set(&self, nanos: u64) {
+        self.now.store(nanos, Ordering::SeqCst);
+        self.monotonic.fetch_max(nanos, Ordering::SeqCst);
+    }
+}
+
+/// A system clock that reports the time set on a `MockClock`
+pub(crate) struct MockSystemClock(Arc<MockClock>);
+
+impl WasiSystemClock for MockSystemClock {
+    fn resolution(&self) -> Duration {
+        Duration::from_nanos(1)
+    }
+
+    fn now(&self, _precision: Duration) -> SystemTime {
+        let epoch = SystemTime::from_std(std::time::UNIX_EPOCH);
+        epoch + Duration::from_nanos(self.0.now.load(Ordering::SeqCst))
+    }
+}
+
+/// A monotonic clock that advances when the time set on a `MockClock` increases
+pub(crate) struct MockMonotonicClock {
+    clock: Arc<MockClock>,
+    start: Instant,
+}
+
+impl WasiMonotonicClock for MockMonotonicClock {
+    fn resolution(&self) -> Duration {
+        Duration::from_nanos(1)
+    }
+
+    fn now(&self, _precision: Duration) -> Instant {
+        self.start + Duration::from_nanos(self.clock.monotonic.load(Ordering::SeqCst))
+    }
+}
+
+/// Replace both clocks in `ctx` with clocks controlled by `clock`
+pub(crate) fn mock(ctx: &mut wasmtime_wasi::WasiCtx, clock: Arc<MockClock>) {
+    let start = ctx.clocks.creation_time;
+    ctx.clocks.system = Box::new(MockSystemClock(clock.clone()));
+    ctx.clocks.monotonic = Box::new(MockMonotonicClock { clock, start });
+}
+
+/// Replace the clocks in `ctx` based on the clock options in the manifest
+pub(crate) fn configure(ctx: &mut wasmtime_wasi::WasiCtx, manifest: &Manifest) {
+    let manifest = manifest.as_ref();
+    if manifest.wasi_clock_resolution_ms.is_none() && !manifest.wasi_disable_monotonic_clock {
+        return;
+    }
+
+    let WasiClocks {
+        mut system,
+        mut monotonic,
+        creation_time,
+    } = std::mem::replace(&mut ctx.clocks, wasmtime_wasi::sync::clocks_ctx());
+
+    if let Some(ms) = manifest.wasi_clock_resolution_ms {
+        let resolution = Duration::from_millis(ms);
+        system = Box::new(CoarseSystemClock {
+            inner: system,
+            resolution,
+        });
+        monotonic = Box::new(CoarseMonotonicClock {
+            inner: monotonic,
+            resolution,
+            start: creation_time,
+        });
+    }
+
+    if manifest.wasi_disable_monotonic_clock {
+        monotonic = Box::new(FrozenMonotonicClock(creation_time));
+    }
+
+    ctx.clocks = WasiClocks {
+        system,
+        monotonic,
+        creation_time,
+    };
+}
@@ -2,20 +2,287 @@ use std::collections::{BTreeMap, VecDeque};
 
 use crate::*;
 
-static mut TIMER: std::sync::Mutex<Option<Timer>> = std::sync::Mutex::new(None);
+static TIMER: std::sync::Mutex<Option<Timer>> = std::sync::Mutex::new(None);
 
 /// A `Context` is used to store and manage plugins
+///
+/// Every method that's used by the C API takes `&self`, the plugin registry and error are
+/// locked internally so a thread-safe context can be shared between threads
 pub struct Context {
-    /// Plugin registry
-    pub plugins: BTreeMap<PluginIndex, Plugin>,
+    /// Plugins, pools and reclaimed plugin IDs
+    registry: std::sync::Mutex<Registry>,
 
     /// Error message
-    pub error: Option<std::ffi::CString>,
+    error: std::sync::Mutex<Option<std::ffi::CString>>,
     next_id: std::sync::atomic::AtomicI32,
-    reclaimed_ids: VecDeque<PluginIndex>,
 
     // Timeout thread
     pub(crate) epoch_timer_tx: std::sync::mpsc::SyncSender<TimerAction>,
+
+    /// Called at the start and end of each plugin call
+    call_observer: std::sync::Mutex<Option<CallObserver>>,
+
+    /// Set while `call_observer` is set, so calls don't lock `call_observer` when there isn't one
+    has_call_observer: std::sync::atomic::AtomicBool,
+
+    /// Compiled modules, used to avoid compiling the same WASM more than once
+    pub(crate) module_cache: std::sync::Mutex<ModuleCache>,
+
+    /// Settings used to create plugins
+    pub config: ContextConfig,
+
+    /// Set for contexts created using `Context::new_threadsafe`, plugins are locked while
+    /// they're in use so calls to the same plugin from different threads wait for each other
+    threadsafe: bool,
+
+    /// Key-value store shared by every plugin in the context
+    pub kv: std::sync::Arc<std::sync::Mutex<KvStore>>,
+}
+
+/// The plugins and pools owned by a context
+#[derive(Default)]
+struct Registry {
+    /// Plugins are boxed so they aren't moved while being used from another thread
+    plugins: BTreeMap<PluginIndex, Box<PluginEntry>>,
+    reclaimed_ids: VecDeque<PluginIndex>,
+
+    /// Plugin pools, pool IDs are allocated from the same sequence as plugin IDs
+    pools: BTreeMap<PluginIndex, PluginPool>,
+}
+
+/// A plugin stored in a context, the call lock and cancel handle are kept outside of the plugin
+/// so they can be used while the plugin is borrowed by another thread
+struct PluginEntry {
+    call_lock: std::sync::Arc<Lock>,
+    cancel_handle: std::sync::Arc<CancelHandle>,
+    usage: std::sync::Arc<PluginUsage>,
+    plugin: std::cell::UnsafeCell<Plugin>,
+}
+
+/// A plugin borrowed from a context using `Context::plugin_locked`
+pub(crate) struct LockedPlugin<'a> {
+    pub(crate) plugin: &'a mut Plugin,
+
+    /// Used to mark the plugin as executing while it's being called
+    pub(crate) call_lock: std::sync::Arc<Lock>,
+
+    /// Keeps the plugin locked until it's dropped, this is only set in thread-safe contexts
+    pub(crate) guard: Option<LockGuard>,
+}
+
+const MIN_EPOCH_INTERVAL_MS: u64 = 1;
+const MAX_EPOCH_INTERVAL_MS: u64 = 60_000;
+
+/// Settings for a `Context`, WASM features that are unset use the wasmtime defaults
+#[derive(Default, Debug, Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ContextConfig {
+    #[serde(default)]
+    pub wasm_simd: Option<bool>,
+    #[serde(default)]
+    pub wasm_reference_types: Option<bool>,
+    #[serde(default)]
+    pub wasm_bulk_memory: Option<bool>,
+    #[serde(default)]
+    pub wasm_multi_value: Option<bool>,
+    /// Allow plugins to be called using `sdk::plugin_call_async`, while running they
+    /// periodically yield to the async executor
+    #[serde(default)]
+    pub async_support: Option<bool>,
+    /// The maximum number of bytes plugins can store in the context's key-value store
+    #[serde(default)]
+    pub kv_max_bytes: Option<usize>,
+    /// How often, in milliseconds, the timer checks whether a plugin's timeout has expired. Smaller
+    /// values enforce timeouts more precisely but use more CPU, defaults to 1
+    #[serde(default)]
+    pub epoch_interval_ms: Option<u64>,
+    /// Capture a WASM backtrace when a call fails, these can be read using
+    /// `extism_plugin_last_backtrace`. Capturing backtraces makes traps slower, so they can be
+    /// disabled in production
+    #[serde(default)]
+    pub wasm_backtrace: Option<bool>,
+    /// The maximum number of elements in each of a plugin's tables
+    #[serde(default)]
+    pub max_table_elements: Option<u32>,
+    /// The maximum number of module instances in a plugin
+    #[serde(default)]
+    pub max_instances: Option<usize>,
+    /// The maximum number of memories defined by a plugin's modules, the memory Extism uses for
+    /// inputs and outputs isn't created by a module so it isn't counted
+    #[serde(default)]
+    pub max_memories: Option<usize>,
+    /// The maximum number of tables defined by a plugin's modules
+    #[serde(default)]
+    pub max_tables: Option<usize>,
+    /// Meter the fuel used by every plugin, so it can be limited using `extism_plugin_set_fuel`.
+    /// Plugins that set `fuel` in their manifest are always metered, metering makes calls slower
+    #[serde(default)]
+    pub consume_fuel: Option<bool>,
+}
+
+impl ContextConfig {
+    /// Create an engine using these settings, `fuel` enables fuel metering even if it isn't
+    /// enabled by the config
+    pub(crate) fn engine(&self, fuel: bool) -> Result<Engine, Error> {
+        let mut config = plugin::engine_config();
+        config.consume_fuel(self.consume_fuel(fuel));
+        if let Some(x) = self.wasm_simd {
+            config.wasm_simd(x);
+        }
+        if let Some(x) = self.wasm_reference_types {
+            config.wasm_reference_types(x);
+        }
+        if let Some(x) = self.wasm_bulk_memory {
+            config.wasm_bulk_memory(x);
+        }
+        if let Some(x) = self.wasm_multi_value {
+            config.wasm_multi_value(x);
+        }
+        if let Some(x) = self.wasm_backtrace {
+            // Deprecated because newer versions of wasmtime always capture backtraces, but it's
+            // still the only way to avoid the cost of capturing them in this version
+            #[allow(deprecated)]
+            config.wasm_backtrace(x);
+        }
+        config.async_support(self.async_support());
+        if let Some(ms) = self.epoch_interval_ms {
+            if !(MIN_EPOCH_INTERVAL_MS..=MAX_EPOCH_INTERVAL_MS).contains(&ms) {
+                anyhow::bail!(
+                    "Invalid epoch_interval_ms: {ms}, expected a value between \
+                     {MIN_EPOCH_INTERVAL_MS} and {MAX_EPOCH_INTERVAL_MS}"
+                );
+            }
+        }
+        Engine::new(&config)
+    }
+
+    /// Describe the settings used by `engine` that change how modules are compiled, this is part
+    /// of the module cache key. wasmtime's `Config` has no getters, and its `Debug` output leaves
+    /// out settings like fuel metering, so the settings are listed explicitly
+    pub(crate) fn engine_key(&self, fuel: bool) -> String {
+        format!(
+            "simd={:?} reference_types={:?} bulk_memory={:?} multi_value={:?} backtrace={:?} \
+             consume_fuel={} async_support={}",
+            self.wasm_simd,
+            self.wasm_reference_types,
+            self.wasm_bulk_memory,
+            self.wasm_multi_value,
+            self.wasm_backtrace,
+            self.consume_fuel(fuel),
+            self.async_support(),
+        )
+    }
+
+    /// Apply the table, instance, memory and table count limits to a plugin's limiter
+    pub(crate) fn apply_limits(&self, limiter: &mut MemoryLimiter) {
+        limiter.max_table_elements = self.max_table_elements;
+        if let Some(x) = self.max_instances {
+            limiter.max_instances = x;
+        }
+        if let Some(x) = self.max_memories {
+            limiter.max_memories = x;
+        }
+        if let Some(x) = self.max_tables {
+            limiter.max_tables = x;
+        }
+    }
+
+    /// Returns the interval used to check plugin timeouts
+    pub(crate) fn epoch_interval(&self) -> std::time::Duration {
+        self.epoch_interval_ms
+            .map(std::time::Duration::from_millis)
+            .unwrap_or(DEFAULT_EPOCH_INTERVAL)
+    }
+
+    /// Returns true if async support is enabled
+    pub(crate) fn async_support(&self) -> bool {
+        self.async_support.unwrap_or(false)
+    }
+
+    /// Returns true if fuel metering is enabled, either by the config or by `manifest_fuel`, which
+    /// is set when the plugin's manifest limits fuel. Plugins with async support use fuel to
+    /// decide when to yield, so it's always enabled for them
+    pub(crate) fn consume_fuel(&self, manifest_fuel: bool) -> bool {
+        manifest_fuel || self.async_support() || self.consume_fuel.unwrap_or(false)
+    }
+
+    /// Returns true if any WASM features are disabled
+    pub(crate) fn disables_features(&self) -> bool {
+        [
+            self.wasm_simd,
+            self.wasm_reference_types,
+            self.wasm_bulk_memory,
+            self.wasm_multi_value,
+        ]
+        .contains(&Some(false))
+    }
+}
+
+/// Identifies whether a `CallObserver` is being notified before or after a call
+/// cbindgen:prefix-with-name
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallPhase {
+    Start,
+    End,
+}
+
+pub type CallObserverCallback = Option<
+    unsafe extern "C" fn(
+        plugin: PluginIndex,
+        func_name: *const std::os::raw::c_char,
+        phase: CallPhase,
+        rc: i32,
+        user_data: *mut std::ffi::c_void,
+    ),
+>;
+
+/// A callback with associated user data that is notified about plugin calls
+#[derive(Clone, Copy)]
+pub struct CallObserver {
+    pub callback: unsafe extern "C" fn(
+        PluginIndex,
+        *const std::os::raw::c_char,
+        CallPhase,
+        i32,
+        *mut std::ffi::c_void,
+    ),
+    pub user_data: *mut std::ffi::c_void,
+}
+
+// Safety: the observer is only used to call `callback`, which is called on the thread making the
+// plugin call. Hosts using a thread-safe context are told the callback can be called from any
+// thread, so `user_data` must be safe to use from those threads
+unsafe impl Send for CallObserver {}
+
+impl CallObserver {
+    pub(crate) unsafe fn notify(
+        &self,
+        plugin: PluginIndex,
+        func_name: *const std::os::raw::c_char,
+        phase: CallPhase,
+        rc: i32,
+    ) {
+        (self.callback)(plugin, func_name, phase, rc, self.user_data)
+    }
+}
+
+impl Drop for Context {
+    fn drop(&mut self) {
+        // Plugins can't be dropped while they're being called on another thread, so any running
+        // calls are cancelled and `clear` waits for them to return
+        if self.is_threadsafe() {
+            self.cancel_calls();
+        }
+
+        // Plugins use the timer when they're dropped, so they're removed before it's released
+        self.clear();
+
+        // Stop the timer thread once the last context is gone, the guard is released first
+        // because `Plugin::drop` also locks the timer
+        let timer = Timer::release(&mut Self::timer());
+        drop(timer);
+    }
 }
 
 impl Default for Context {
@@ -28,7 +295,7 @@ const START_REUSING_IDS: usize = 25;
 
 impl Context {
     pub(crate) fn timer() -> std::sync::MutexGuard<'static, Option<Timer>> {
-        match unsafe { TIMER.lock() } {
+        match TIMER.lock() {
             Ok(x) => x,
             Err(e) => e.into_inner(),
         }
@@ -43,17 +310,106 @@ impl Context {
             Some(t) => t.tx.clone(),
         };
 
+        if let Some(t) = timer {
+            t.contexts += 1;
+        }
+
         Context {
-            plugins: BTreeMap::new(),
-            error: None,
+            registry: Default::default(),
+            error: std::sync::Mutex::new(None),
             next_id: std::sync::atomic::AtomicI32::new(0),
-            reclaimed_ids: VecDeque::new(),
             epoch_timer_tx: tx,
+            call_observer: std::sync::Mutex::new(None),
+            has_call_observer: std::sync::atomic::AtomicBool::new(false),
+            module_cache: std::sync::Mutex::new(ModuleCache::default()),
+            threadsafe: false,
+            config: ContextConfig::default(),
+            kv: Default::default(),
+        }
+    }
+
+    /// Create a new context using `config` to create plugins, this fails if the config is invalid
+    pub fn new_with_config(config: ContextConfig) -> Result<Context, Error> {
+        config.engine(false)?;
+        let kv = KvStore::new(config.kv_max_bytes);
+        let mut ctx = Context::new();
+        ctx.config = config;
+        ctx.kv = std::sync::Arc::new(std::sync::Mutex::new(kv));
+        Ok(ctx)
+    }
+
+    /// Create a new context that can be shared between threads, plugins are locked while they're
+    /// being called so distinct plugins can be called in parallel
+    pub fn new_threadsafe() -> Context {
+        let mut ctx = Context::new();
+        ctx.threadsafe = true;
+        ctx
+    }
+
+    /// Create a new thread-safe context using `config` to create plugins, this fails if the
+    /// config is invalid
+    pub fn new_threadsafe_with_config(config: ContextConfig) -> Result<Context, Error> {
+        let mut ctx = Context::new_with_config(config)?;
+        ctx.threadsafe = true;
+        Ok(ctx)
+    }
+
+    /// Returns true if the context was created using `Context::new_threadsafe`
+    pub fn is_threadsafe(&self) -> bool {
+        self.threadsafe
+    }
+
+    fn registry(&self) -> std::sync::MutexGuard<'_, Registry> {
+        match self.registry.lock() {
+            Ok(x) => x,
+            Err(e) => e.into_inner(),
+        }
+    }
+
+    pub(crate) fn module_cache(&self) -> std::sync::MutexGuard<'_, ModuleCache> {
+        match self.module_cache.lock() {
+            Ok(x) => x,
+            Err(e) => e.into_inner(),
+        }
+    }
+
+    /// Get the context error, the error is locked until the guard is dropped
+    pub fn error_message(&self) -> std::sync::MutexGuard<'_, Option<std::ffi::CString>> {
+        match self.error.lock() {
+            Ok(x) => x,
+            Err(e) => e.into_inner(),
         }
     }
 
+    /// Get the call observer
+    pub fn call_observer(&self) -> Option<CallObserver> {
+        if !self
+            .has_call_observer
+            .load(std::sync::atomic::Ordering::Acquire)
+        {
+            return None;
+        }
+
+        match self.call_observer.lock() {
+            Ok(x) => *x,
+            Err(e) => *e.into_inner(),
+        }
+    }
+
+    /// Set the callback notified at the start and end of each call, `None` removes it
+    pub fn set_call_observer(&self, observer: Option<CallObserver>) {
+        let mut x = match self.call_observer.lock() {
+            Ok(x) => x,
+            Err(e) => e.into_inner(),
+        };
+        // Updated while the lock is held so the flag always matches the observer
+        self.has_call_observer
+            .store(observer.is_some(), std::sync::atomic::Ordering::Release);
+        *x = observer;
+    }
+
     /// Get the next valid plugin ID
-    pub fn next_id(&mut self) -> Result<PluginIndex, Error> {
+    fn next_id(&self, registry: &mut Registry) -> Result<PluginIndex, Error> {
         // Make sure we haven't exhausted all plugin IDs, to reach this it would require the machine
         // running this code to have a lot of memory - no computer I tested on was able to allocate
         // the max number of plugins.
@@ -63,8 +419,8 @@ impl Context {
         let exhausted = self.next_id.load(std::sync::atomic::Ordering::SeqCst) == PluginIndex::MAX;
 
         // If there are a significant number of old IDs we can start to re-use them
-        if self.reclaimed_ids.len() >= START_REUSING_IDS || exhausted {
-            if let Some(x) = self.reclaimed_ids.pop_front() {
+        if registry.reclaimed_ids.len() >= START_REUSING_IDS || exhausted {
+            if let Some(x) = registry.reclaimed_ids.pop_front() {
                 return Ok(x);
             }
 
@@ -80,22 +436,39 @@ impl Context {
             .fetch_add(1, std::sync::atomic::Ordering::SeqCst))
     }
 
-    pub fn insert(&mut self, plugin: Plugin) -> PluginIndex {
+    pub fn insert(&self, mut plugin: Plugin) -> PluginIndex {
+        plugin.kv = self.kv.clone();
+
         // Generate a new plugin ID
-        let id: i32 = match self.next_id() {
+        let id = {
+            let registry = &mut *self.registry();
+            let id = self.next_id(registry);
+            if let Ok(id) = id {
+                registry
+                    .plugins
+                    .insert(id, Box::new(PluginEntry::new(plugin)));
+            }
+            id
+        };
+        match id {
             Ok(id) => id,
             Err(e) => {
                 error!("Error creating Plugin: {:?}", e);
                 self.set_error(e);
-                return -1;
+                -1
             }
-        };
-        self.plugins.insert(id, plugin);
-        id
+        }
     }
 
-    pub fn new_plugin(&mut self, data: impl AsRef<[u8]>, with_wasi: bool) -> PluginIndex {
-        let plugin = match Plugin::new(data, with_wasi) {
+    pub fn new_plugin(&self, data: impl AsRef<[u8]>, with_wasi: bool) -> PluginIndex {
+        let plugin = Plugin::create(
+            data,
+            [],
+            with_wasi,
+            Some(&mut self.module_cache()),
+            &self.config,
+        );
+        let plugin = match plugin {
             Ok(x) => x,
             Err(e) => {
                 error!("Error creating Plugin: {:?}", e);
@@ -107,12 +480,63 @@ impl Context {
     }
 
     pub fn new_plugin_with_functions(
-        &mut self,
+        &self,
         data: impl AsRef<[u8]>,
         imports: impl IntoIterator<Item = Function>,
         with_wasi: bool,
     ) -> PluginIndex {
-        let plugin = match Plugin::new_with_functions(data, imports, with_wasi) {
+        let plugin = Plugin::create(
+            data,
+            imports,
+            with_wasi,
+            Some(&mut self.module_cache()),
+            &self.config,
+        );
+        let plugin = match plugin {
+            Ok(x) => x,
+            Err(e) => {
+                error!("Error creating Plugin: {:?}", e);
+                self.set_error(e);
+                return -1;
+            }
+        };
+        self.insert(plugin)
+    }
+
+    pub fn new_plugin_with_options(
+        &self,
+        data: impl AsRef<[u8]>,
+        options: &PluginOptions,
+    ) -> PluginIndex {
+        let plugin = Plugin::create(
+            data,
+            [],
+            options.wasi,
+            Some(&mut self.module_cache()),
+            &self.config,
+        );
+        let plugin = match plugin.and_then(|mut x| x.apply_options(options).map(|()| x)) {
+            Ok(x) => x,
+            Err(e) => {
+                error!("Error creating Plugin: {:?}", e);
+                self.set_error(e);
+                return -1;
+            }
+        };
+        self.insert(plugin)
+    }
+
+    /// Create a plugin from data created by `Plugin::serialize`
+    ///
+    /// # Safety
+    ///
+    /// `data` is loaded as native code without being verified, see `Plugin::new_precompiled`
+    pub unsafe fn new_plugin_precompiled(
+        &self,
+        data: impl AsRef<[u8]>,
+        with_wasi: bool,
+    ) -> PluginIndex {
+        let plugin = match Plugin::create_precompiled(data, [], with_wasi, &self.config) {
             Ok(x) => x,
             Err(e) => {
                 error!("Error creating Plugin: {:?}", e);
@@ -124,31 +548,313 @@ impl Context {
     }
 
     /// Set the context error
-    pub fn set_error(&mut self, e: impl std::fmt::Debug) {
+    pub fn set_error(&self, e: impl std::fmt::Debug) {
         trace!("Set context error: {:?}", e);
-        self.error = Some(error_string(e));
+        *self.error_message() = Some(error_string(e));
+    }
+
+    /// Clear the context error
+    pub fn clear_error(&self) {
+        *self.error_message() = None;
     }
 
     /// Convenience function to set error and return the value passed as the final parameter
-    pub fn error<T>(&mut self, e: impl std::fmt::Debug, x: T) -> T {
+    pub fn error<T>(&self, e: impl std::fmt::Debug, x: T) -> T {
         self.set_error(e);
         x
     }
 
-    /// Get a plugin from the context
+    /// Get a plugin from a context that isn't shared, use `PluginRef::new` to access plugins
+    /// from the C API
     pub fn plugin(&mut self, id: PluginIndex) -> Option<&mut Plugin> {
-        self.plugins.get_mut(&id)
+        let registry = self.registry.get_mut().unwrap_or_else(|e| e.into_inner());
+        registry.plugins.get_mut(&id).map(|x| x.plugin.get_mut())
+    }
+
+    /// Get a plugin from the context, in a thread-safe context this waits for any call to the
+    /// plugin on another thread to finish and the plugin stays locked until the returned value
+    /// is dropped. `None` is returned if the plugin doesn't exist or it's being called on the
+    /// current thread
+    pub(crate) fn plugin_locked(&self, id: PluginIndex) -> Option<LockedPlugin<'_>> {
+        loop {
+            let call_lock = self.registry().plugins.get(&id)?.call_lock.clone();
+
+            // Waiting for a call on the current thread would never finish, and accessing the
+            // plugin while it's running isn't safe
+            if call_lock.is_executing() {
+                return None;
+            }
+
+            let guard = if self.is_threadsafe() {
+                Some(call_lock.acquire())
+            } else {
+                None
+            };
+
+            // The plugin may have been removed or replaced while waiting for the lock
+            let registry = self.registry();
+            let entry = registry.plugins.get(&id)?;
+            if !std::sync::Arc::ptr_eq(&entry.call_lock, &call_lock) {
+                continue;
+            }
+
+            // The entry is boxed, so the plugin doesn't move when the registry changes, and it
+            // can't be removed or replaced until the call lock is released. In contexts that
+            // aren't thread-safe the context is only used by one thread and calls are marked as
+            // executing, so the plugin isn't borrowed anywhere else
+            let plugin = unsafe { &mut *entry.plugin.get() };
+            return Some(LockedPlugin {
+                plugin,
+                call_lock,
+                guard,
+            });
+        }
+    }
+
+    /// Get the handle used to cancel calls to a plugin, this doesn't wait for a running call
+    pub fn cancel_handle(&self, id: PluginIndex) -> Option<std::sync::Arc<CancelHandle>> {
+        Some(self.registry().plugins.get(&id)?.cancel_handle.clone())
+    }
+
+    /// Returns true if the plugin is being called on the current thread
+    pub(crate) fn plugin_executing(&self, id: PluginIndex) -> bool {
+        self.registry()
+            .plugins
+            .get(&id)
+            .map(|x| x.call_lock.is_executing())
+            .unwrap_or(false)
+    }
+
+    pub fn plugin_exists(&self, id: PluginIndex) -> bool {
+        self.registry().plugins.contains_key(&id)
     }
 
-    pub fn plugin_exists(&mut self, id: PluginIndex) -> bool {
-        self.plugins.contains_key(&id)
+    /// Get the IDs of all plugins in the context
+    pub fn plugin_ids(&self) -> Vec<PluginIndex> {
+        self.registry().plugins.keys().copied().collect()
+    }
+
+    /// Get plugin IDs sorted by memory usage, largest first. The memory usage is measured at the
+    /// end of each call, so plugins that are being called are included
+    pub fn plugins_by_memory(&self) -> Vec<PluginIndex> {
+        let mut plugins: Vec<_> = self
+            .registry()
+            .plugins
+            .iter()
+            .map(|(id, x)| {
+                (
+                    *id,
+                    x.usage.memory.load(std::sync::atomic::Ordering::Relaxed),
+                )
+            })
+            .collect();
+        plugins.sort_by_key(|x| std::cmp::Reverse(x.1));
+        plugins.into_iter().map(|(id, _)| id).collect()
+    }
+
+    /// Get plugin IDs sorted by the time of their last call, least recently used first. Plugins
+    /// that are being called are included
+    pub fn plugins_by_last_used(&self) -> Vec<PluginIndex> {
+        let mut plugins: Vec<_> = self
+            .registry()
+            .plugins
+            .iter()
+            .map(|(id, x)| {
+                (
+                    *id,
+                    x.usage.last_used.load(std::sync::atomic::Ordering::Relaxed),
+                )
+            })
+            .collect();
+        plugins.sort_by_key(|x| x.1);
+        plugins.into_iter().map(|(id, _)| id).collect()
+    }
+
+    /// Set the maximum number of compiled modules cached by the context, `0` disables caching
+    pub fn set_module_cache_size(&self, size: usize) {
+        self.module_cache().set_capacity(size);
+    }
+
+    /// Replace an existing plugin, keeping its ID. Returns false if the plugin doesn't exist
+    pub fn replace(&self, id: PluginIndex, mut plugin: Plugin) -> bool {
+        plugin.kv = self.kv.clone();
+
+        // Wait for any in-progress call to finish
+        let locked = match self.plugin_locked(id) {
+            None => return false,
+            Some(x) => x,
+        };
+
+        // The old plugin is dropped after the registry is unlocked
+        let old = self
+            .registry()
+            .plugins
+            .insert(id, Box::new(PluginEntry::new(plugin)));
+        drop(locked);
+        drop(old);
+        true
     }
 
     /// Remove a plugin from the context
-    pub fn remove(&mut self, id: PluginIndex) {
-        if self.plugins.remove(&id).is_some() {
-            // Collect old IDs in case we need to re-use them
-            self.reclaimed_ids.push_back(id);
+    pub fn remove(&self, id: PluginIndex) {
+        // Wait for any in-progress call to finish
+        let locked = match self.plugin_locked(id) {
+            None => return,
+            Some(x) => x,
+        };
+
+        let old = {
+            let registry = &mut *self.registry();
+            let old = registry.plugins.remove(&id);
+            if old.is_some() {
+                // Collect old IDs in case we need to re-use them
+                registry.reclaimed_ids.push_back(id);
+            }
+
+            for pool in registry.pools.values_mut() {
+                pool.remove(id);
+            }
+            old
+        };
+        drop(locked);
+        drop(old);
+    }
+
+    /// Cancel any calls that are running on other threads, each call fails with the error
+    /// `cancelled`
+    pub fn cancel_calls(&self) {
+        for entry in self.registry().plugins.values() {
+            entry.cancel_handle.cancel();
+        }
+    }
+
+    /// Remove all plugins and pools from the context
+    pub fn clear(&self) {
+        self.registry().pools.clear();
+
+        for id in self.plugin_ids() {
+            self.remove(id);
+        }
+    }
+
+    /// Create a new pool of up to `max_size` plugins, the first instance is created immediately
+    /// so any errors loading the WASM are reported here
+    pub fn new_pool(
+        &self,
+        data: impl AsRef<[u8]>,
+        with_wasi: bool,
+        max_size: usize,
+    ) -> PluginIndex {
+        let data = data.as_ref();
+        let plugin = self.new_plugin(data, with_wasi);
+        if plugin < 0 {
+            return -1;
+        }
+
+        let mut pool = PluginPool::new(data, with_wasi, max_size);
+        pool.instances.push(plugin);
+        pool.idle.push(plugin);
+
+        let id = {
+            let registry = &mut *self.registry();
+            let id = self.next_id(registry);
+            if let Ok(id) = id {
+                registry.pools.insert(id, pool);
+            }
+            id
+        };
+        match id {
+            Ok(id) => id,
+            Err(e) => {
+                error!("Error creating plugin pool: {:?}", e);
+                self.remove(plugin);
+                self.set_error(e);
+                -1
+            }
+        }
+    }
+
+    /// Check out an idle plugin from a pool, a new instance is created when none are idle and the
+    /// pool hasn't reached its maximum size. Once every instance is checked out, instances are
+    /// shared and calls to the same instance wait for each other
+    pub fn pool_checkout(&self, pool_id: PluginIndex) -> Result<PluginIndex, Error> {
+        let (wasm, with_wasi) = {
+            let registry = &mut *self.registry();
+            let pool = match registry.pools.get_mut(&pool_id) {
+                Some(x) => x,
+                None => return Err(anyhow::format_err!("Plugin pool does not exist: {pool_id}")),
+            };
+
+            if let Some(id) = pool.idle_instance() {
+                return Ok(id);
+            }
+
+            if !pool.can_grow() {
+                return pool
+                    .busy_instance()
+                    .ok_or_else(|| anyhow::format_err!("Plugin pool is empty: {pool_id}"));
+            }
+
+            pool.pending += 1;
+            (pool.wasm.clone(), pool.with_wasi)
+        };
+
+        debug!("Growing plugin pool {pool_id}");
+        let plugin = Plugin::create(
+            wasm,
+            [],
+            with_wasi,
+            Some(&mut self.module_cache()),
+            &self.config,
+        );
+        let id = plugin.map(|x| self.insert(x));
+
+        let registry = &mut *self.registry();
+        let pool = registry.pools.get_mut(&pool_id);
+        if let Some(pool) = pool {
+            pool.pending -= 1;
+            if let Ok(id) = id {
+                if id >= 0 {
+                    pool.instances.push(id);
+                    pool.checkout(id);
+                }
+            }
+        }
+
+        match id {
+            Ok(-1) => Err(anyhow::format_err!(
+                "Unable to add plugin to pool {pool_id}"
+            )),
+            x => x,
+        }
+    }
+
+    /// Return a plugin to a pool after it was checked out using `Context::pool_checkout`
+    pub fn pool_checkin(&self, pool_id: PluginIndex, plugin: PluginIndex) {
+        if let Some(pool) = self.registry().pools.get_mut(&pool_id) {
+            pool.checkin(plugin);
+        }
+    }
+
+    /// Remove a pool and all of its plugins
+    pub fn remove_pool(&self, pool_id: PluginIndex) {
+        let pool = self.registry().pools.remove(&pool_id);
+
+        if let Some(pool) = pool {
+            for id in pool.instances {
+                self.remove(id);
+            }
+        }
+    }
+}
+
+impl PluginEntry {
+    fn new(plugin: Plugin) -> PluginEntry {
+        PluginEntry {
+            call_lock: Default::default(),
+            cancel_handle: plugin.cancel_handle.clone(),
+            usage: plugin.usage.clone(),
+            plugin: std::cell::UnsafeCell::new(plugin),
         }
     }
 }
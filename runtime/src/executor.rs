@@ -0,0 +1,31 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::task::{Poll, Wake, Waker};
+
+// Wakes the thread that is waiting in `block_on`
+struct ThreadWaker(std::thread::Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+thread_local! {
+    // Created once per thread so blocking calls don't allocate a new waker each time
+    static WAKER: Waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+}
+
+/// Run a future to completion on the current thread, this is used to call plugins with async
+/// support from the blocking API
+pub(crate) fn block_on<F: Future>(f: F) -> F::Output {
+    let mut f = std::pin::pin!(f);
+    let waker = WAKER.with(|x| x.clone());
+    let mut cx = std::task::Context::from_waker(&waker);
+    loop {
+        match f.as_mut().poll(&mut cx) {
+            Poll::Ready(x) => return x,
+            Poll::Pending => std::thread::park(),
+        }
+    }
+}
@@ -1,7 +1,10 @@
 use crate::{Error, Internal};
 
 /// A list of all possible value types in WebAssembly.
-#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+///
+/// cbindgen:prefix-with-name
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
 pub enum ValType {
     // NB: the ordering here is intended to match the ordering in
     // `wasmtime_types::WasmType` to help improve codegen when converting.
@@ -52,10 +55,11 @@ impl From<ValType> for wasmtime::ValType {
 }
 
 #[allow(clippy::type_complexity)]
+#[derive(Clone)]
 pub struct Function(
     pub(crate) String,
     pub(crate) wasmtime::FuncType,
-    pub(crate)  Box<
+    pub(crate)  std::sync::Arc<
         dyn Fn(
                 wasmtime::Caller<Internal>,
                 &[wasmtime::Val],
@@ -64,6 +68,7 @@ pub struct Function(
             + Sync
             + Send,
     >,
+    pub(crate) Option<String>,
 );
 
 impl Function {
@@ -89,14 +94,25 @@ impl Function {
                 args.into_iter().map(wasmtime::ValType::from),
                 returns.into_iter().map(wasmtime::ValType::from),
             ),
-            Box::new(f),
+            std::sync::Arc::new(f),
+            None,
         )
     }
 
+    /// Set the module the function is defined in, functions are defined in `env` by default
+    pub fn with_namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.3 = Some(namespace.into());
+        self
+    }
+
     pub fn name(&self) -> &str {
         &self.0
     }
 
+    pub fn namespace(&self) -> Option<&str> {
+        self.3.as_deref()
+    }
+
     pub fn ty(&self) -> &wasmtime::FuncType {
         &self.1
     }
@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+
+use crate::*;
+
+/// A key-value store shared by all of the plugins in a `Context`, plugins can access it using
+/// the `extism_kv_get`, `extism_kv_set` and `extism_kv_delete` host functions
+#[derive(Default)]
+pub struct KvStore {
+    data: HashMap<Vec<u8>, Vec<u8>>,
+
+    /// The number of bytes used by all keys and values
+    size: usize,
+
+    /// The maximum number of bytes that can be stored, `None` means there is no limit
+    max_bytes: Option<usize>,
+}
+
+impl KvStore {
+    pub fn new(max_bytes: Option<usize>) -> KvStore {
+        KvStore {
+            max_bytes,
+            ..Default::default()
+        }
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<&[u8]> {
+        self.data.get(key).map(|x| x.as_slice())
+    }
+
+    /// Set `key` to `value`, this fails if it would make the store larger than its limit
+    pub fn set(&mut self, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        let existing = self.get(key).map(|x| key.len() + x.len()).unwrap_or(0);
+        let size = self.size - existing + key.len() + value.len();
+        if let Some(max) = self.max_bytes {
+            if size > max {
+                return Err(Error::msg(format!(
+                    "Key-value store is full, the limit is {max} bytes"
+                )));
+            }
+        }
+
+        self.data.insert(key.to_vec(), value.to_vec());
+        self.size = size;
+        Ok(())
+    }
+
+    pub fn delete(&mut self, key: &[u8]) {
+        if let Some(value) = self.data.remove(key) {
+            self.size -= key.len() + value.len();
+        }
+    }
+
+    /// The number of bytes used by all keys and values
+    pub fn size(&self) -> usize {
+        self.size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overwrite() {
+        let mut kv = KvStore::new(None);
+        kv.set(b"key", b"a").unwrap();
+        kv.set(b"key", b"abc").unwrap();
+        assert_eq!(kv.get(b"key"), Some(&b"abc"[..]));
+        assert_eq!(kv.size(), 6);
+    }
+
+    #[test]
+    fn test_delete() {
+        let mut kv = KvStore::new(None);
+        kv.set(b"key", b"value").unwrap();
+        kv.delete(b"key");
+        assert_eq!(kv.get(b"key"), None);
+        assert_eq!(kv.size(), 0);
+
+        // Deleting a missing key does nothing
+        kv.delete(b"key");
+        assert_eq!(kv.size(), 0);
+    }
+
+    #[test]
+    fn test_max_bytes() {
+        let mut kv = KvStore::new(Some(8));
+        kv.set(b"key", b"12345").unwrap();
+        assert!(kv.set(b"other", b"1").is_err());
+        assert_eq!(kv.get(b"other"), None);
+
+        // Replacing a value only counts the difference in size
+        kv.set(b"key", b"54321").unwrap();
+        assert!(kv.set(b"key", b"123456").is_err());
+        assert_eq!(kv.get(b"key"), Some(&b"54321"[..]));
+        assert_eq!(kv.size(), 8);
+
+        // Space freed by deleting a key can be reused
+        kv.delete(b"key");
+        kv.set(b"other", b"1").unwrap();
+    }
+
+    #[test]
+    fn test_host_functions() {
+        // Each function uses the key `k`, `set_a` and `set_b` set it to `a` or `b` and `get`
+        // outputs its value, or nothing when it isn't set
+        let wasm = r#"(module
+            (import "env" "extism_alloc" (func $alloc (param i64) (result i64)))
+            (import "env" "extism_store_u8" (func $store (param i64 i32)))
+            (import "env" "extism_length" (func $length (param i64) (result i64)))
+            (import "env" "extism_output_set" (func $output_set (param i64 i64)))
+            (import "env" "extism_kv_get" (func $get (param i64) (result i64)))
+            (import "env" "extism_kv_set" (func $set (param i64 i64)))
+            (import "env" "extism_kv_delete" (func $delete (param i64)))
+            (memory (export "memory") 1)
+            (func $byte (param i32) (result i64) (local $offs i64)
+                (local.set $offs (call $alloc (i64.const 1)))
+                (call $store (local.get $offs) (local.get 0))
+                (local.get $offs))
+            (func (export "set_a") (result i32)
+                (call $set (call $byte (i32.const 107)) (call $byte (i32.const 97))) i32.const 0)
+            (func (export "set_b") (result i32)
+                (call $set (call $byte (i32.const 107)) (call $byte (i32.const 98))) i32.const 0)
+            (func (export "delete") (result i32)
+                (call $delete (call $byte (i32.const 107))) i32.const 0)
+            (func (export "get") (result i32) (local $offs i64)
+                (local.set $offs (call $get (call $byte (i32.const 107))))
+                (call $output_set (local.get $offs)
+                    (if (result i64) (i64.eqz (local.get $offs))
+                        (then (i64.const 0))
+                        (else (call $length (local.get $offs)))))
+                i32.const 0))"#;
+        let ctx = Context::new();
+        let a = ctx.new_plugin(wasm, false);
+        let b = ctx.new_plugin(wasm, false);
+        let call = |id: PluginIndex, name: &[u8]| unsafe {
+            let ctx = &ctx as *const Context as *mut Context;
+            assert_eq!(
+                sdk::extism_plugin_call(ctx, id, name.as_ptr() as *const _, std::ptr::null(), 0),
+                0
+            );
+            let plugin = (*ctx).plugin_locked(id).unwrap();
+            plugin.plugin.output().unwrap().to_vec()
+        };
+
+        // The store is shared by the plugins in a context
+        call(a, b"set_a\0");
+        assert_eq!(call(b, b"get\0"), b"a");
+        call(b, b"set_b\0");
+        assert_eq!(call(a, b"get\0"), b"b");
+        call(a, b"delete\0");
+        assert_eq!(call(b, b"get\0"), b"");
+        assert_eq!(ctx.kv.lock().unwrap().size(), 0);
+    }
+}
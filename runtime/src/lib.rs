@@ -1,28 +1,50 @@
 pub use anyhow::Error;
 pub(crate) use wasmtime::*;
 
+mod clocks;
 mod context;
+mod executor;
 mod function;
+mod kv;
+mod lock;
 pub mod manifest;
+mod memfs;
 mod memory;
+mod metrics;
+mod module_cache;
 pub(crate) mod pdk;
 mod plugin;
 mod plugin_ref;
+mod pool;
+pub mod proto;
 pub mod sdk;
+mod snapshot;
 mod timer;
 
-pub use context::Context;
+pub use context::{CallObserver, CallObserverCallback, CallPhase, Context, ContextConfig};
 pub use function::{Function, ValType};
+pub use kv::KvStore;
 pub use manifest::Manifest;
+pub use memfs::MemFs;
 pub use memory::{MemoryBlock, PluginMemory};
-pub use plugin::{Internal, Plugin, Wasi};
+pub use metrics::Metrics;
+pub(crate) use plugin::PluginUsage;
+pub use plugin::{
+    set_default_timeout_ms, set_parallel_compilation, CancelHandle, CapturedOutput, EmitCallback,
+    Emitter, ErrorKind, Internal, MemoryLimiter, Plugin, PluginOptions, TimeoutHandler,
+    TimeoutHandlerCallback, Wasi,
+};
 pub use plugin_ref::PluginRef;
-pub(crate) use timer::{Timer, TimerAction};
+pub use pool::PluginPool;
+pub use snapshot::Snapshot;
+pub(crate) use timer::{Timer, TimerAction, DEFAULT_EPOCH_INTERVAL};
 
 pub type Size = u64;
 pub type PluginIndex = i32;
 
-pub(crate) use log::{debug, error, info, trace};
+pub(crate) use lock::{ExecutingGuard, Lock, LockGuard};
+pub(crate) use log::{debug, error, info, trace, warn};
+pub(crate) use module_cache::{ModuleCache, ModuleLoader};
 
 /// Converts any type implementing `std::fmt::Debug` into a suitable CString to use
 /// as an error message
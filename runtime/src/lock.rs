@@ -0,0 +1,72 @@
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::ThreadId;
+
+/// A lock that can be held without borrowing the data it protects, this is used to guard the
+/// plugin table and individual plugins in thread-safe contexts
+#[derive(Default)]
+pub(crate) struct Lock {
+    locked: Mutex<bool>,
+    cond: Condvar,
+
+    /// The thread that's currently running a call, used to detect reentrant calls
+    executing: Mutex<Option<ThreadId>>,
+}
+
+/// Releases the `Lock` it was acquired from when dropped
+pub(crate) struct LockGuard(Arc<Lock>);
+
+/// Marks a call as finished when dropped
+pub(crate) struct ExecutingGuard(Arc<Lock>);
+
+impl Lock {
+    /// Block until the lock is available
+    pub fn acquire(self: &Arc<Self>) -> LockGuard {
+        let mut locked = match self.locked.lock() {
+            Ok(x) => x,
+            Err(e) => e.into_inner(),
+        };
+        while *locked {
+            locked = match self.cond.wait(locked) {
+                Ok(x) => x,
+                Err(e) => e.into_inner(),
+            };
+        }
+        *locked = true;
+        LockGuard(self.clone())
+    }
+
+    /// Mark the current thread as running a call until the returned guard is dropped
+    pub fn enter(self: &Arc<Self>) -> ExecutingGuard {
+        *self.executing() = Some(std::thread::current().id());
+        ExecutingGuard(self.clone())
+    }
+
+    /// Returns true if a call is running on the current thread
+    pub fn is_executing(&self) -> bool {
+        *self.executing() == Some(std::thread::current().id())
+    }
+
+    fn executing(&self) -> std::sync::MutexGuard<'_, Option<ThreadId>> {
+        match self.executing.lock() {
+            Ok(x) => x,
+            Err(e) => e.into_inner(),
+        }
+    }
+}
+
+impl Drop for ExecutingGuard {
+    fn drop(&mut self) {
+        *self.0.executing() = None;
+    }
+}
+
+impl Drop for LockGuard {
+    fn drop(&mut self) {
+        let mut locked = match self.0.locked.lock() {
+            Ok(x) => x,
+            Err(e) => e.into_inner(),
+        };
+        *locked = false;
+        self.0.cond.notify_one();
+    }
+}
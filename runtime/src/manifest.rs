@@ -60,8 +60,50 @@ fn check_hash(hash: &Option<String>, data: &[u8]) -> Result<(), Error> {
     }
 }
 
+/// Returns true if `host` matches one of `allowed_hosts`, which can contain glob patterns. All
+/// hosts are allowed when `allowed_hosts` is `None`
+pub(crate) fn is_host_allowed(allowed_hosts: &Option<Vec<String>>, host: &str) -> bool {
+    match allowed_hosts {
+        None => true,
+        Some(allowed_hosts) => allowed_hosts.iter().any(|x| match glob::Pattern::new(x) {
+            Ok(pat) => pat.matches(host),
+            Err(_) => x == host,
+        }),
+    }
+}
+
+/// Check that a module can be downloaded from `url`, when the manifest sets
+/// `restrict_module_urls` modules must be downloaded using HTTPS from one of its `allowed_hosts`
+fn check_module_url(url: &str, manifest: &extism_manifest::Manifest) -> Result<url::Url, Error> {
+    let url = url::Url::parse(url)?;
+    if !manifest.restrict_module_urls {
+        return Ok(url);
+    }
+
+    if url.scheme() != "https" {
+        return Err(anyhow::format_err!(
+            "Unsupported URL scheme: {}, modules can only be downloaded using https",
+            url.scheme()
+        ));
+    }
+
+    let host = url.host_str().unwrap_or_default();
+    if !is_host_allowed(&manifest.allowed_hosts, host) {
+        return Err(anyhow::format_err!(
+            "Unable to download {url}, {host} is not in allowed_hosts"
+        ));
+    }
+
+    Ok(url)
+}
+
 /// Convert from manifest to a wasmtime Module
-fn to_module(engine: &Engine, wasm: &extism_manifest::Wasm) -> Result<(String, Module), Error> {
+fn to_module(
+    engine: &Engine,
+    wasm: &extism_manifest::Wasm,
+    manifest: &extism_manifest::Manifest,
+    loader: &mut ModuleLoader,
+) -> Result<(String, Module), Error> {
     match wasm {
         extism_manifest::Wasm::File { path, meta } => {
             if cfg!(not(feature = "register-filesystem")) {
@@ -84,13 +126,13 @@ fn to_module(engine: &Engine, wasm: &extism_manifest::Wasm) -> Result<(String, M
 
             check_hash(&meta.hash, &buf)?;
 
-            Ok((name, Module::new(engine, buf)?))
+            Ok((name, loader.compile(engine, &buf)?))
         }
         extism_manifest::Wasm::Data { meta, data } => {
             check_hash(&meta.hash, data)?;
             Ok((
                 meta.name.as_deref().unwrap_or("main").to_string(),
-                Module::new(engine, data)?,
+                loader.compile(engine, data)?,
             ))
         }
         #[allow(unused)]
@@ -104,7 +146,7 @@ fn to_module(engine: &Engine, wasm: &extism_manifest::Wasm) -> Result<(String, M
             meta,
         } => {
             // Get the file name
-            let file_name = url.split('/').last().unwrap_or_default();
+            let file_name = url.split('/').next_back().unwrap_or_default();
             let name = match &meta.name {
                 Some(name) => name.as_str(),
                 None => {
@@ -120,11 +162,16 @@ fn to_module(engine: &Engine, wasm: &extism_manifest::Wasm) -> Result<(String, M
                 }
             };
 
+            check_module_url(url, manifest)?;
+
+            // Cached files are only used if they still match the hash, otherwise the module is
+            // downloaded again
             if let Some(h) = &meta.hash {
                 if let Ok(Some(data)) = cache_get_file(h) {
-                    check_hash(&meta.hash, &data)?;
-                    let module = Module::new(engine, data)?;
-                    return Ok((name.to_string(), module));
+                    if check_hash(&meta.hash, &data).is_ok() {
+                        let module = loader.compile(engine, &data)?;
+                        return Ok((name.to_string(), module));
+                    }
                 }
             }
 
@@ -147,46 +194,271 @@ fn to_module(engine: &Engine, wasm: &extism_manifest::Wasm) -> Result<(String, M
                 let mut data = Vec::new();
                 r.read_to_end(&mut data)?;
 
-                // Try to cache file
-                if let Some(hash) = &meta.hash {
-                    cache_add_file(hash, &data);
+                if let Err(e) = check_hash(&meta.hash, &data) {
+                    return Err(e.context(format!("Downloaded module {url} failed verification")));
                 }
 
-                check_hash(&meta.hash, &data)?;
+                // Try to cache file, only verified files are cached
+                if let Some(hash) = &meta.hash {
+                    let _ = cache_add_file(hash, &data);
+                }
 
                 // Convert fetched data to module
-                let module = Module::new(engine, data)?;
+                let module = loader.compile(engine, &data)?;
                 Ok((name.to_string(), module))
             }
         }
     }
 }
 
+/// Check that a WASM module is well-formed without compiling it
+fn validate_module(engine: &Engine, data: &[u8]) -> Result<(), Error> {
+    let data = wat::parse_bytes(data)?;
+    Module::validate(engine, &data).map_err(|e| explain_wasm_error(&data, e))
+}
+
+/// Compile a module, if the WASM is invalid the error includes the section where it failed
+pub(crate) fn compile_module(engine: &Engine, data: &[u8]) -> Result<Module, Error> {
+    // Text modules are converted first so error offsets refer to the binary that was compiled
+    let data = wat::parse_bytes(data)?;
+    Module::new(engine, &data).map_err(|e| explain_wasm_error(&data, e))
+}
+
+// Rewrite parsing and validation errors to include the location of the failure
+fn explain_wasm_error(data: &[u8], e: Error) -> Error {
+    // Function bodies are validated while they're compiled, so the cause may be nested
+    let location = e.chain().find_map(|cause| {
+        if let Some(wasmtime_types::WasmError::InvalidWebAssembly { message, offset }) =
+            cause.downcast_ref()
+        {
+            return Some((*offset, message.clone()));
+        }
+        cause
+            .downcast_ref::<wasmparser::BinaryReaderError>()
+            .map(|err| (err.offset(), err.message().to_string()))
+    });
+    let (offset, message) = match location {
+        Some(x) => x,
+        None => return e,
+    };
+
+    anyhow::format_err!(
+        "Invalid WASM in {} at offset {offset:#x}: {message}",
+        wasm_location(data, offset)
+    )
+}
+
+const SECTION_NAMES: [&str; 14] = [
+    "custom",
+    "type",
+    "import",
+    "function",
+    "table",
+    "memory",
+    "global",
+    "export",
+    "start",
+    "element",
+    "code",
+    "data",
+    "data count",
+    "tag",
+];
+
+// Read an unsigned LEB128 value, returning the value and the number of bytes read
+fn read_leb128(data: &[u8]) -> Option<(usize, usize)> {
+    let mut value = 0usize;
+    for (i, byte) in data.iter().take(5).enumerate() {
+        value |= ((byte & 0x7f) as usize) << (i * 7);
+        if byte & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+    None
+}
+
+// Describe the part of the module containing `offset`, for the code section this includes the
+// index of the function body
+fn wasm_location(data: &[u8], offset: usize) -> String {
+    if offset < 8 {
+        return "the module header".to_string();
+    }
+
+    let mut pos = 8;
+    while pos < data.len() {
+        let id = data[pos] as usize;
+        let name = SECTION_NAMES.get(id).copied().unwrap_or("unknown");
+        let (size, n) = match read_leb128(&data[pos + 1..]) {
+            Some(x) => x,
+            None => return format!("the {name} section header"),
+        };
+        let start = pos + 1 + n;
+        let end = start.saturating_add(size);
+
+        // Truncated modules fail past the end of the data, which is still in the last section
+        if offset < end || end > data.len() {
+            // Offsets in the section header come before `start`, and truncated sizes can point
+            // past the end of the data, so these only use checked arithmetic
+            let section = data.get(start..).unwrap_or_default();
+            if id == 0 {
+                let custom_name =
+                    read_leb128(section).and_then(|(len, n)| section.get(n..n.checked_add(len)?));
+                if let Some(x) = custom_name {
+                    return format!("custom section {:?}", String::from_utf8_lossy(x));
+                }
+            }
+
+            if id == 10 {
+                let index = offset
+                    .checked_sub(start)
+                    .and_then(|offset| code_body_index(section, offset));
+                if let Some(index) = index {
+                    return format!("the code section (function body {index})");
+                }
+            }
+            return format!("the {name} section");
+        }
+        pos = end;
+    }
+
+    "the end of the module".to_string()
+}
+
+// Find the function body in the code section that contains `offset`
+fn code_body_index(section: &[u8], offset: usize) -> Option<usize> {
+    let (count, mut pos) = read_leb128(section)?;
+    for index in 0..count {
+        let (size, n) = read_leb128(section.get(pos..)?)?;
+        pos = pos.checked_add(n)?.checked_add(size)?;
+        if offset < pos {
+            return Some(index);
+        }
+    }
+    None
+}
+
+/// Check that a manifest WASM entry is well-formed, URLs are checked but not fetched
+fn validate_wasm(
+    engine: &Engine,
+    wasm: &extism_manifest::Wasm,
+    manifest: &extism_manifest::Manifest,
+) -> Result<(), Error> {
+    match wasm {
+        extism_manifest::Wasm::File { path, meta } => {
+            if cfg!(not(feature = "register-filesystem")) {
+                return Err(anyhow::format_err!("File-based registration is disabled"));
+            }
+
+            let buf = match std::fs::read(path) {
+                Ok(x) => x,
+                Err(e) => {
+                    return Err(Error::from(e).context(format!("Unable to read {}", path.display())))
+                }
+            };
+            check_hash(&meta.hash, &buf)?;
+            validate_module(engine, &buf)
+        }
+        extism_manifest::Wasm::Data { meta, data } => {
+            check_hash(&meta.hash, data)?;
+            validate_module(engine, data)
+        }
+        extism_manifest::Wasm::Url { req, meta } => {
+            check_module_url(&req.url, manifest)?;
+
+            if let Some(hash) = &meta.hash {
+                if hash.len() != 64 || !hash.bytes().all(|c| c.is_ascii_hexdigit()) {
+                    return Err(anyhow::format_err!(
+                        "Invalid hash {hash}, expected a hex encoded SHA-256 digest"
+                    ));
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
 const WASM_MAGIC: [u8; 4] = [0x00, 0x61, 0x73, 0x6d];
 
+// Returns true if `data` is a WASM module in binary or text format, rather than a manifest
+fn is_module(data: &[u8]) -> bool {
+    let has_magic = data.len() >= 4 && data[0..4] == WASM_MAGIC;
+    let is_wast = data.starts_with(b"(module") || data.starts_with(b";;");
+    has_magic || is_wast
+}
+
 impl Manifest {
     /// Create a new Manifest, returns the manifest and a map of modules
     pub fn new(engine: &Engine, data: &[u8]) -> Result<(Self, BTreeMap<String, Module>), Error> {
-        let has_magic = data.len() >= 4 && data[0..4] == WASM_MAGIC;
-        let is_wast = data.starts_with(b"(module") || data.starts_with(b";;");
-        if !has_magic && !is_wast {
-            if let Ok(t) = toml::from_slice::<Self>(data) {
-                let m = t.modules(engine)?;
-                return Ok((t, m));
-            }
+        Self::new_with_loader(engine, data, &mut ModuleLoader::new(None, String::new()))
+    }
+
+    /// Create a new Manifest, modules are compiled using `loader`, which loads them from the
+    /// module cache when it has one
+    pub(crate) fn new_with_loader(
+        engine: &Engine,
+        data: &[u8],
+        loader: &mut ModuleLoader,
+    ) -> Result<(Self, BTreeMap<String, Module>), Error> {
+        let manifest = Self::parse(data)?;
+        let modules = manifest.load_modules(engine, data, loader)?;
+        Ok((manifest, modules))
+    }
 
-            let t = serde_json::from_slice::<Self>(data)?;
-            let m = t.modules(engine)?;
-            return Ok((t, m));
+    /// Parse a manifest without loading its modules, WASM modules that aren't wrapped in a
+    /// manifest use the default manifest
+    pub(crate) fn parse(data: &[u8]) -> Result<Self, Error> {
+        if is_module(data) {
+            return Ok(Manifest::default());
         }
 
-        let m = Module::new(engine, data)?;
+        if let Ok(t) = toml::from_slice::<Self>(data) {
+            return Ok(t);
+        }
+
+        Ok(serde_json::from_slice::<Self>(data)?)
+    }
+
+    /// Compile the modules of a manifest returned by `Manifest::parse`, `data` is compiled
+    /// directly when it's a WASM module
+    pub(crate) fn load_modules(
+        &self,
+        engine: &Engine,
+        data: &[u8],
+        loader: &mut ModuleLoader,
+    ) -> Result<BTreeMap<String, Module>, Error> {
+        if !is_module(data) {
+            return self.modules(engine, loader);
+        }
+
+        let m = loader.compile(engine, data)?;
         let mut modules = BTreeMap::new();
         modules.insert("main".to_string(), m);
-        Ok((Manifest::default(), modules))
+        Ok(modules)
+    }
+
+    /// Check that a JSON manifest can be parsed and that the WASM modules it references are
+    /// well-formed, without creating a plugin
+    pub fn validate(data: &[u8]) -> Result<(), Error> {
+        let manifest = serde_json::from_slice::<Self>(data)?;
+        if manifest.0.wasm.is_empty() {
+            return Err(anyhow::format_err!("No wasm files specified"));
+        }
+
+        let engine = Engine::new(&crate::plugin::engine_config())?;
+        for (i, wasm) in manifest.0.wasm.iter().enumerate() {
+            if let Err(e) = validate_wasm(&engine, wasm, &manifest.0) {
+                return Err(e.context(format!("Invalid wasm entry at index {i}")));
+            }
+        }
+
+        Ok(())
     }
 
-    fn modules(&self, engine: &Engine) -> Result<BTreeMap<String, Module>, Error> {
+    fn modules(
+        &self,
+        engine: &Engine,
+        loader: &mut ModuleLoader,
+    ) -> Result<BTreeMap<String, Module>, Error> {
         if self.0.wasm.is_empty() {
             return Err(anyhow::format_err!("No wasm files specified"));
         }
@@ -195,13 +467,13 @@ impl Manifest {
 
         // If there's only one module, it should be called `main`
         if self.0.wasm.len() == 1 {
-            let (_, m) = to_module(engine, &self.0.wasm[0])?;
+            let (_, m) = to_module(engine, &self.0.wasm[0], &self.0, loader)?;
             modules.insert("main".to_string(), m);
             return Ok(modules);
         }
 
         for f in &self.0.wasm {
-            let (name, m) = to_module(engine, f)?;
+            let (name, m) = to_module(engine, f, &self.0, loader)?;
             modules.insert(name, m);
         }
 
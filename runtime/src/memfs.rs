@@ -0,0 +1,530 @@
+use std::any::Any;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{IoSlice, IoSliceMut, SeekFrom};
+use std::sync::{Arc, RwLock, RwLockWriteGuard};
+
+use wasi_common::dir::{ReaddirCursor, ReaddirEntity, WasiDir};
+use wasi_common::file::{FdFlags, FileType, Filestat, OFlags, WasiFile};
+use wasi_common::snapshots::preview_1::error::Errno;
+use wasi_common::{Error, ErrorExt};
+
+/// An in-memory filesystem that can be mounted in a WASI plugin, paths are relative to the root
+/// of the filesystem and don't include a leading `/`
+#[derive(Default)]
+pub struct MemFs {
+    files: BTreeMap<String, Vec<u8>>,
+    dirs: BTreeSet<String>,
+}
+
+pub(crate) type SharedMemFs = Arc<RwLock<MemFs>>;
+
+/// The maximum number of bytes stored in a `MemFs`, writes that would grow a file past this fail
+/// with `EFBIG` and writes that would grow the filesystem past it fail with `ENOSPC`
+pub(crate) const MEMFS_MAX_BYTES: usize = 64 * 1024 * 1024;
+
+/// Join `path` onto `base`, returns an error if the result is outside of the filesystem
+fn join(base: &str, path: &str) -> Result<String, Error> {
+    let mut components: Vec<&str> = base.split('/').filter(|x| !x.is_empty()).collect();
+    for c in path.split('/') {
+        match c {
+            "" | "." => (),
+            ".." => {
+                if components.pop().is_none() {
+                    return Err(Error::perm());
+                }
+            }
+            c => components.push(c),
+        }
+    }
+    Ok(components.join("/"))
+}
+
+fn parent(path: &str) -> &str {
+    path.rsplit_once('/').map(|x| x.0).unwrap_or_default()
+}
+
+fn name(path: &str) -> &str {
+    path.rsplit_once('/').map(|x| x.1).unwrap_or(path)
+}
+
+fn lock(fs: &SharedMemFs) -> Result<RwLockWriteGuard<'_, MemFs>, Error> {
+    fs.write().map_err(|_| Error::io())
+}
+
+fn filestat(filetype: FileType, size: usize) -> Filestat {
+    Filestat {
+        device_id: 0,
+        inode: 0,
+        filetype,
+        nlink: 1,
+        size: size as u64,
+        atim: None,
+        mtim: None,
+        ctim: None,
+    }
+}
+
+impl MemFs {
+    /// Create a filesystem containing `files`, parent directories are created automatically
+    pub fn new(files: &BTreeMap<String, Vec<u8>>) -> Result<MemFs, anyhow::Error> {
+        let mut fs = MemFs::default();
+        fs.dirs.insert(String::new());
+        for (k, v) in files {
+            let path = match join("", k) {
+                Ok(p) if !p.is_empty() => p,
+                _ => anyhow::bail!("Invalid file path in manifest: {k}"),
+            };
+
+            let mut dir = parent(&path);
+            while !dir.is_empty() {
+                fs.dirs.insert(dir.to_string());
+                dir = parent(dir);
+            }
+            fs.files.insert(path, v.clone());
+        }
+
+        if let Some(path) = fs.files.keys().find(|x| fs.dirs.contains(*x)) {
+            anyhow::bail!("Invalid file path in manifest: {path} is also a directory");
+        }
+
+        Ok(fs)
+    }
+
+    /// Get the contents of the file at `path`
+    pub fn read(&self, path: &str) -> Option<&[u8]> {
+        let path = join("", path).ok()?;
+        self.files.get(&path).map(|x| x.as_slice())
+    }
+
+    fn stat(&self, path: &str) -> Result<Filestat, Error> {
+        if self.dirs.contains(path) {
+            return Ok(filestat(FileType::Directory, 0));
+        }
+        match self.files.get(path) {
+            Some(data) => Ok(filestat(FileType::RegularFile, data.len())),
+            None => Err(Error::not_found()),
+        }
+    }
+
+    fn exists(&self, path: &str) -> bool {
+        self.dirs.contains(path) || self.files.contains_key(path)
+    }
+
+    fn is_child(path: &str, dir: &str) -> bool {
+        !path.is_empty() && path != dir && parent(path) == dir
+    }
+
+    fn is_descendant(path: &str, dir: &str) -> bool {
+        dir.is_empty() || path.starts_with(&format!("{dir}/"))
+    }
+}
+
+/// A directory in a `MemFs`
+pub(crate) struct MemDir {
+    fs: SharedMemFs,
+    path: String,
+}
+
+impl MemDir {
+    /// Get the root directory of `fs`
+    pub fn root(fs: SharedMemFs) -> MemDir {
+        MemDir {
+            fs,
+            path: String::new(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl WasiDir for MemDir {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn open_file(
+        &self,
+        _symlink_follow: bool,
+        path: &str,
+        oflags: OFlags,
+        read: bool,
+        write: bool,
+        fdflags: FdFlags,
+    ) -> Result<Box<dyn WasiFile>, Error> {
+        let path = join(&self.path, path)?;
+        let mut fs = lock(&self.fs)?;
+        if fs.dirs.contains(&path) {
+            return Err(Errno::Isdir.into());
+        }
+
+        match fs.files.get_mut(&path) {
+            Some(data) => {
+                if oflags.contains(OFlags::CREATE | OFlags::EXCLUSIVE) {
+                    return Err(Error::exist());
+                }
+                if oflags.contains(OFlags::TRUNCATE) {
+                    data.clear();
+                }
+            }
+            None => {
+                if !oflags.contains(OFlags::CREATE) || !fs.dirs.contains(parent(&path)) {
+                    return Err(Error::not_found());
+                }
+                fs.files.insert(path.clone(), Vec::new());
+            }
+        }
+
+        Ok(Box::new(MemFile {
+            fs: self.fs.clone(),
+            path,
+            position: 0,
+            read,
+            write,
+            append: fdflags.contains(FdFlags::APPEND),
+        }))
+    }
+
+    async fn open_dir(&self, _symlink_follow: bool, path: &str) -> Result<Box<dyn WasiDir>, Error> {
+        let path = join(&self.path, path)?;
+        let fs = lock(&self.fs)?;
+        if fs.files.contains_key(&path) {
+            return Err(Error::not_dir());
+        }
+        if !fs.dirs.contains(&path) {
+            return Err(Error::not_found());
+        }
+        Ok(Box::new(MemDir {
+            fs: self.fs.clone(),
+            path,
+        }))
+    }
+
+    async fn create_dir(&self, path: &str) -> Result<(), Error> {
+        let path = join(&self.path, path)?;
+        let mut fs = lock(&self.fs)?;
+        if fs.exists(&path) {
+            return Err(Error::exist());
+        }
+        if !fs.dirs.contains(parent(&path)) {
+            return Err(Error::not_found());
+        }
+        fs.dirs.insert(path);
+        Ok(())
+    }
+
+    async fn readdir(
+        &self,
+        cursor: ReaddirCursor,
+    ) -> Result<Box<dyn Iterator<Item = Result<ReaddirEntity, Error>> + Send>, Error> {
+        let fs = lock(&self.fs)?;
+        let dirs = fs
+            .dirs
+            .iter()
+            .filter(|x| MemFs::is_child(x, &self.path))
+            .map(|x| (FileType::Directory, name(x).to_string()));
+        let files = fs
+            .files
+            .keys()
+            .filter(|x| MemFs::is_child(x, &self.path))
+            .map(|x| (FileType::RegularFile, name(x).to_string()));
+        let entries: Vec<_> = [
+            (FileType::Directory, ".".to_string()),
+            (FileType::Directory, "..".to_string()),
+        ]
+        .into_iter()
+        .chain(dirs)
+        .chain(files)
+        .enumerate()
+        .map(|(ix, (filetype, name))| {
+            Ok(ReaddirEntity {
+                next: ReaddirCursor::from(ix as u64 + 1),
+                inode: 0,
+                name,
+                filetype,
+            })
+        })
+        .skip(u64::from(cursor) as usize)
+        .collect();
+        Ok(Box::new(entries.into_iter()))
+    }
+
+    async fn remove_dir(&self, path: &str) -> Result<(), Error> {
+        let path = join(&self.path, path)?;
+        let mut fs = lock(&self.fs)?;
+        if fs.files.contains_key(&path) {
+            return Err(Error::not_dir());
+        }
+        if path.is_empty() {
+            return Err(Error::perm());
+        }
+        if !fs.dirs.contains(&path) {
+            return Err(Error::not_found());
+        }
+
+        let is_empty = !fs.dirs.iter().any(|x| MemFs::is_child(x, &path))
+            && !fs.files.keys().any(|x| MemFs::is_child(x, &path));
+        if !is_empty {
+            return Err(Errno::Notempty.into());
+        }
+        fs.dirs.remove(&path);
+        Ok(())
+    }
+
+    async fn unlink_file(&self, path: &str) -> Result<(), Error> {
+        let path = join(&self.path, path)?;
+        let mut fs = lock(&self.fs)?;
+        if fs.dirs.contains(&path) {
+            return Err(Errno::Isdir.into());
+        }
+        match fs.files.remove(&path) {
+            Some(_) => Ok(()),
+            None => Err(Error::not_found()),
+        }
+    }
+
+    async fn get_filestat(&self) -> Result<Filestat, Error> {
+        Ok(filestat(FileType::Directory, 0))
+    }
+
+    async fn get_path_filestat(
+        &self,
+        path: &str,
+        _follow_symlinks: bool,
+    ) -> Result<Filestat, Error> {
+        let path = join(&self.path, path)?;
+        lock(&self.fs)?.stat(&path)
+    }
+
+    async fn rename(
+        &self,
+        path: &str,
+        dest_dir: &dyn WasiDir,
+        dest_path: &str,
+    ) -> Result<(), Error> {
+        let dest_dir = match dest_dir.as_any().downcast_ref::<MemDir>() {
+            Some(x) if Arc::ptr_eq(&x.fs, &self.fs) => x,
+            _ => return Err(Errno::Xdev.into()),
+        };
+        let src = join(&self.path, path)?;
+        let dest = join(&dest_dir.path, dest_path)?;
+        let mut fs = lock(&self.fs)?;
+        if !fs.dirs.contains(parent(&dest)) {
+            return Err(Error::not_found());
+        }
+
+        if let Some(data) = fs.files.remove(&src) {
+            if fs.dirs.contains(&dest) {
+                fs.files.insert(src, data);
+                return Err(Errno::Isdir.into());
+            }
+            fs.files.insert(dest, data);
+            return Ok(());
+        }
+
+        if !fs.dirs.contains(&src) {
+            return Err(Error::not_found());
+        }
+        if src.is_empty() || dest == src || MemFs::is_descendant(&dest, &src) {
+            return Err(Error::invalid_argument());
+        }
+        if fs.exists(&dest) {
+            return Err(Error::exist());
+        }
+
+        // Move the directory along with everything inside of it
+        let rename = |x: &String| format!("{dest}{}", &x[src.len()..]);
+        let dirs: Vec<_> = fs
+            .dirs
+            .iter()
+            .filter(|x| **x == src || MemFs::is_descendant(x, &src))
+            .cloned()
+            .collect();
+        for d in dirs {
+            fs.dirs.remove(&d);
+            fs.dirs.insert(rename(&d));
+        }
+        let files: Vec<_> = fs
+            .files
+            .keys()
+            .filter(|x| MemFs::is_descendant(x, &src))
+            .cloned()
+            .collect();
+        for f in files {
+            if let Some(data) = fs.files.remove(&f) {
+                fs.files.insert(rename(&f), data);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An open file in a `MemFs`
+pub(crate) struct MemFile {
+    fs: SharedMemFs,
+    path: String,
+    position: u64,
+    read: bool,
+    write: bool,
+    append: bool,
+}
+
+impl MemFile {
+    fn with_data<T>(&self, f: impl FnOnce(&mut Vec<u8>) -> T) -> Result<T, Error> {
+        let mut fs = lock(&self.fs)?;
+        match fs.files.get_mut(&self.path) {
+            Some(data) => Ok(f(data)),
+            None => Err(Error::badf()),
+        }
+    }
+
+    /// Grow the file to at least `len` bytes, checking that the filesystem stays within
+    /// `MEMFS_MAX_BYTES`, then call `f` with the file's data
+    fn with_data_len<T>(&self, len: u64, f: impl FnOnce(&mut Vec<u8>) -> T) -> Result<T, Error> {
+        let mut fs = lock(&self.fs)?;
+        let used: usize = fs.files.values().map(|x| x.len()).sum();
+        let data = match fs.files.get_mut(&self.path) {
+            Some(data) => data,
+            None => return Err(Error::badf()),
+        };
+
+        let len = match usize::try_from(len) {
+            Ok(x) if x <= MEMFS_MAX_BYTES => x,
+            _ => return Err(Errno::Fbig.into()),
+        };
+        if len > data.len() {
+            if used - data.len() + len > MEMFS_MAX_BYTES {
+                return Err(Errno::Nospc.into());
+            }
+            data.resize(len, 0);
+        }
+        Ok(f(data))
+    }
+
+    fn read_at(&self, bufs: &mut [IoSliceMut<'_>], offset: u64) -> Result<u64, Error> {
+        if !self.read {
+            return Err(Error::badf());
+        }
+        self.with_data(|data| {
+            let mut offset = (offset as usize).min(data.len());
+            let start = offset;
+            for buf in bufs.iter_mut() {
+                let n = buf.len().min(data.len() - offset);
+                buf[..n].copy_from_slice(&data[offset..offset + n]);
+                offset += n;
+            }
+            (offset - start) as u64
+        })
+    }
+
+    fn write_at(&self, bufs: &[IoSlice<'_>], offset: u64) -> Result<u64, Error> {
+        if !self.write {
+            return Err(Error::badf());
+        }
+        let n: usize = bufs.iter().map(|x| x.len()).sum();
+        let end = offset.checked_add(n as u64);
+        self.with_data_len(end.unwrap_or(u64::MAX), |data| {
+            let mut offset = offset as usize;
+            for buf in bufs.iter() {
+                let end = offset + buf.len();
+                data[offset..end].copy_from_slice(buf);
+                offset = end;
+            }
+            n as u64
+        })
+    }
+
+    fn len(&self) -> Result<u64, Error> {
+        self.with_data(|data| data.len() as u64)
+    }
+}
+
+#[async_trait::async_trait]
+impl WasiFile for MemFile {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn get_filetype(&mut self) -> Result<FileType, Error> {
+        Ok(FileType::RegularFile)
+    }
+
+    async fn get_fdflags(&mut self) -> Result<FdFlags, Error> {
+        if self.append {
+            Ok(FdFlags::APPEND)
+        } else {
+            Ok(FdFlags::empty())
+        }
+    }
+
+    async fn get_filestat(&mut self) -> Result<Filestat, Error> {
+        Ok(filestat(FileType::RegularFile, self.len()? as usize))
+    }
+
+    async fn set_filestat_size(&mut self, size: u64) -> Result<(), Error> {
+        if !self.write {
+            return Err(Error::badf());
+        }
+        self.with_data_len(size, |data| data.truncate(size as usize))
+    }
+
+    async fn read_vectored<'a>(&mut self, bufs: &mut [IoSliceMut<'a>]) -> Result<u64, Error> {
+        let n = self.read_at(bufs, self.position)?;
+        self.position += n;
+        Ok(n)
+    }
+
+    async fn read_vectored_at<'a>(
+        &mut self,
+        bufs: &mut [IoSliceMut<'a>],
+        offset: u64,
+    ) -> Result<u64, Error> {
+        self.read_at(bufs, offset)
+    }
+
+    async fn write_vectored<'a>(&mut self, bufs: &[IoSlice<'a>]) -> Result<u64, Error> {
+        if self.append {
+            self.position = self.len()?;
+        }
+        let n = self.write_at(bufs, self.position)?;
+        self.position += n;
+        Ok(n)
+    }
+
+    async fn write_vectored_at<'a>(
+        &mut self,
+        bufs: &[IoSlice<'a>],
+        offset: u64,
+    ) -> Result<u64, Error> {
+        self.write_at(bufs, offset)
+    }
+
+    async fn seek(&mut self, pos: SeekFrom) -> Result<u64, Error> {
+        let position = match pos {
+            SeekFrom::Start(x) => Some(x),
+            SeekFrom::Current(x) => self.position.checked_add_signed(x),
+            SeekFrom::End(x) => self.len()?.checked_add_signed(x),
+        };
+        match position {
+            Some(x) => {
+                self.position = x;
+                Ok(x)
+            }
+            None => Err(Error::invalid_argument()),
+        }
+    }
+
+    async fn peek(&mut self, buf: &mut [u8]) -> Result<u64, Error> {
+        self.read_at(&mut [IoSliceMut::new(buf)], self.position)
+    }
+
+    async fn num_ready_bytes(&self) -> Result<u64, Error> {
+        Ok(self.len()?.saturating_sub(self.position))
+    }
+
+    async fn readable(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn writable(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
@@ -11,6 +11,9 @@ pub struct PluginMemory {
     pub live_blocks: BTreeMap<usize, usize>,
     pub free: Vec<MemoryBlock>,
     pub position: usize,
+    /// Blocks allocated by the host using `alloc_host`, these are kept when memory is reset at
+    /// the start of a call
+    pub host_blocks: BTreeMap<usize, usize>,
 }
 
 pub trait ToMemoryBlock {
@@ -41,7 +44,7 @@ impl ToMemoryBlock for usize {
     }
 }
 
-const PAGE_SIZE: u32 = 65536;
+pub(crate) const PAGE_SIZE: u32 = 65536;
 
 // BLOCK_SIZE_THRESHOLD exists to ensure that free blocks are never split up any
 // smaller than this value
@@ -56,6 +59,7 @@ impl PluginMemory {
             store,
             memory,
             position: 1,
+            host_blocks: BTreeMap::new(),
         }
     }
 
@@ -175,7 +179,12 @@ impl PluginMemory {
 
             info!("Requesting {pages_needed} more pages");
             // This will fail if we've already allocated the maximum amount of memory allowed
-            self.memory.grow(&mut self.store, pages_needed)?;
+            if let Err(e) = self.memory.grow(&mut self.store, pages_needed) {
+                if self.store.data().memory_limiter.exceeded {
+                    return Err(Error::msg("memory limit exceeded"));
+                }
+                return Err(e);
+            }
         }
 
         let mem = MemoryBlock {
@@ -242,11 +251,57 @@ impl PluginMemory {
         trace!("{:?}", data[..self.position].hex_dump());
     }
 
-    /// Reset memory - clears free-list and live blocks and resets position
+    /// Reset memory - clears free-list and live blocks and resets position, blocks allocated by
+    /// the host are kept and the space between them is added to the free-list
     pub fn reset(&mut self) {
         self.free.clear();
         self.live_blocks.clear();
         self.position = 1;
+
+        for (&offset, &length) in self.host_blocks.iter() {
+            if offset > self.position {
+                self.free.push(MemoryBlock {
+                    offset: self.position,
+                    length: offset - self.position,
+                });
+            }
+            self.live_blocks.insert(offset, length);
+            self.position = offset + length;
+        }
+    }
+
+    /// Reserve `n` bytes of memory for the host, the block stays allocated across calls until
+    /// it's released using `free_host`
+    pub fn alloc_host(&mut self, n: usize) -> Result<MemoryBlock, Error> {
+        let block = self.alloc(n)?;
+        self.host_blocks.insert(block.offset, block.length);
+        Ok(block)
+    }
+
+    /// Free a block allocated using `alloc_host`, returns false if there is no host block at
+    /// `offset`
+    pub fn free_host(&mut self, offset: usize) -> bool {
+        if self.host_blocks.remove(&offset).is_none() {
+            return false;
+        }
+        self.free(offset);
+        true
+    }
+
+    /// Overwrite the entire memory with `data`, growing the memory if needed. Any bytes past the
+    /// end of `data` are zeroed
+    pub fn replace(&mut self, data: &[u8]) -> Result<(), Error> {
+        replace(&mut self.store, self.memory, data)
+    }
+
+    /// Overwrite the entire memory with zeros, this doesn't change the size of the memory
+    pub fn zero(&mut self) {
+        self.memory.data_mut(&mut self.store).fill(0)
+    }
+
+    /// Touch every page of memory so the first access during a call doesn't page fault
+    pub fn prefault(&mut self) {
+        prefault(self.memory.data_mut(&mut self.store))
     }
 
     /// Get memory as a slice of bytes
@@ -309,6 +364,48 @@ impl PluginMemory {
     }
 }
 
+// The smallest page size used by supported host platforms
+const HOST_PAGE_SIZE: usize = 4096;
+
+/// Overwrite all of `memory` with `data`, growing it if needed. Any bytes past the end of `data`
+/// are zeroed
+pub(crate) fn replace(
+    mut store: impl AsContextMut,
+    memory: Memory,
+    data: &[u8],
+) -> Result<(), Error> {
+    let size = memory.data_size(&store);
+    if data.len() > size {
+        let pages_needed = (data.len() - size).div_ceil(PAGE_SIZE as usize) as u64;
+        let total_pages = memory.size(&store) + pages_needed;
+        if let Some(max) = memory.ty(&store).maximum() {
+            if total_pages > max {
+                return Err(anyhow::format_err!(
+                    "Unable to replace memory: {} bytes requires {total_pages} pages but the limit is {max}",
+                    data.len()
+                ));
+            }
+        }
+        memory.grow(&mut store, pages_needed)?;
+    }
+
+    let mem = memory.data_mut(&mut store);
+    mem[..data.len()].copy_from_slice(data);
+    mem[data.len()..].fill(0);
+    Ok(())
+}
+
+/// Touch every host page in `data`, writing the existing value back so that pages are faulted
+/// in as writable
+pub(crate) fn prefault(data: &mut [u8]) {
+    for i in (0..data.len()).step_by(HOST_PAGE_SIZE) {
+        unsafe {
+            let p = data.as_mut_ptr().add(i);
+            std::ptr::write_volatile(p, std::ptr::read_volatile(p));
+        }
+    }
+}
+
 #[derive(Clone, Copy)]
 pub struct MemoryBlock {
     pub offset: usize,
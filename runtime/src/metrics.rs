@@ -0,0 +1,31 @@
+use crate::*;
+
+/// Counters for the calls made to a plugin, these are returned by `extism_plugin_metrics_json`
+/// and cleared using `extism_plugin_metrics_reset`
+#[derive(Default, Clone, Debug, serde::Serialize)]
+pub struct Metrics {
+    /// The number of calls, including calls that failed before the function was run
+    pub calls: u64,
+    /// The number of calls that failed, this includes timeouts
+    pub errors: u64,
+    /// The number of calls that timed out
+    pub timeouts: u64,
+    /// The total time spent running calls in nanoseconds
+    pub execution_time_ns: u64,
+}
+
+impl Metrics {
+    /// Update the counters after a call, `error` is the kind of error set by the call
+    pub(crate) fn record(&mut self, duration: std::time::Duration, error: Option<ErrorKind>) {
+        self.calls += 1;
+        self.execution_time_ns = self
+            .execution_time_ns
+            .saturating_add(duration.as_nanos() as u64);
+        if let Some(kind) = error {
+            self.errors += 1;
+            if kind == ErrorKind::Timeout {
+                self.timeouts += 1;
+            }
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use sha2::Digest;
+
+use crate::*;
+
+/// The default number of modules kept by a `ModuleCache`
+pub(crate) const DEFAULT_MODULE_CACHE_SIZE: usize = 32;
+
+/// Get the key used to cache `data` when it's compiled using an engine created with the settings
+/// described by `engine_key`, so modules compiled with different WASM features or fuel metering
+/// aren't shared. The key is a hex-encoded SHA-256 hash
+pub(crate) fn key(engine_key: &str, data: &[u8]) -> String {
+    let mut digest = sha2::Sha256::new();
+    digest.update(data);
+    digest.update(engine_key.as_bytes());
+    digest
+        .finalize()
+        .iter()
+        .map(|x| format!("{x:02x}"))
+        .collect()
+}
+
+/// Caches compiled modules by the hash of their WASM and engine config, the least recently used
+/// module is evicted once the cache is full
+///
+/// Each plugin has its own `Engine`, so modules are stored in their serialized form and
+/// deserialized into the engine of the plugin being created, which is much faster than
+/// compiling them again
+pub(crate) struct ModuleCache {
+    modules: BTreeMap<String, Vec<u8>>,
+    order: VecDeque<String>,
+    capacity: usize,
+}
+
+impl Default for ModuleCache {
+    fn default() -> Self {
+        ModuleCache::new(DEFAULT_MODULE_CACHE_SIZE)
+    }
+}
+
+impl ModuleCache {
+    pub fn new(capacity: usize) -> Self {
+        ModuleCache {
+            modules: BTreeMap::new(),
+            order: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    /// Set the maximum number of modules in the cache, `0` disables the cache
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.evict();
+    }
+
+    /// Get the module with the given hash from the cache, compiling `data` if it's not found
+    pub fn compile(&mut self, engine: &Engine, hash: &str, data: &[u8]) -> Result<Module, Error> {
+        if self.capacity == 0 {
+            return manifest::compile_module(engine, data);
+        }
+
+        if let Some(serialized) = self.modules.get(hash) {
+            // This is safe because the module was serialized by `Module::serialize`
+            match unsafe { Module::deserialize(engine, serialized) } {
+                Ok(module) => {
+                    trace!("Module cache hit: {hash}");
+                    self.touch(hash);
+                    return Ok(module);
+                }
+                Err(e) => {
+                    debug!("Unable to load cached module {hash}: {e:?}");
+                    self.remove(hash);
+                }
+            }
+        }
+
+        trace!("Module cache miss: {hash}");
+        let module = manifest::compile_module(engine, data)?;
+        match module.serialize() {
+            Ok(serialized) => {
+                self.modules.insert(hash.to_string(), serialized);
+                self.touch(hash);
+                self.evict();
+            }
+            Err(e) => debug!("Unable to cache module {hash}: {e:?}"),
+        }
+        Ok(module)
+    }
+
+    /// Remove the module with the given hash
+    pub fn remove(&mut self, hash: &str) {
+        self.modules.remove(hash);
+        self.order.retain(|x| x != hash);
+    }
+
+    // Mark a module as the most recently used
+    fn touch(&mut self, hash: &str) {
+        self.order.retain(|x| x != hash);
+        self.order.push_back(hash.to_string());
+    }
+
+    // Remove the least recently used modules until the cache is within capacity
+    fn evict(&mut self) {
+        while self.order.len() > self.capacity {
+            if let Some(hash) = self.order.pop_front() {
+                trace!("Evicting module from cache: {hash}");
+                self.modules.remove(&hash);
+            }
+        }
+    }
+}
+
+/// Compiles the modules used to create a plugin, loading them from a `ModuleCache` when one is
+/// provided. The cache key of each module is recorded so the modules can be removed from the
+/// cache when the plugin is updated
+pub(crate) struct ModuleLoader<'a> {
+    cache: Option<&'a mut ModuleCache>,
+    engine_key: String,
+    pub hashes: Vec<String>,
+}
+
+impl<'a> ModuleLoader<'a> {
+    /// `engine_key` describes the settings of the engine modules are compiled with, see
+    /// `ContextConfig::engine_key`
+    pub fn new(cache: Option<&'a mut ModuleCache>, engine_key: String) -> Self {
+        ModuleLoader {
+            cache,
+            engine_key,
+            hashes: vec![],
+        }
+    }
+
+    /// Compile `data`, each module is cached separately using the hash of its own WASM
+    pub fn compile(&mut self, engine: &Engine, data: &[u8]) -> Result<Module, Error> {
+        match &mut self.cache {
+            Some(cache) => {
+                let hash = key(&self.engine_key, data);
+                let module = cache.compile(engine, &hash, data)?;
+                self.hashes.push(hash);
+                Ok(module)
+            }
+            None => manifest::compile_module(engine, data),
+        }
+    }
+}
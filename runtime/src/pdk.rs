@@ -40,10 +40,11 @@ pub(crate) fn input_load_u8(
     output: &mut [Val],
 ) -> Result<(), Error> {
     let data: &Internal = caller.data();
-    if data.input.is_null() {
-        return Ok(());
+    let offs = args!(input, 0, i64) as usize;
+    match data.input().get(offs) {
+        Some(byte) => output[0] = Val::I32(*byte as i32),
+        None => return Err(Error::msg(format!("Invalid input offset: {offs}"))),
     }
-    output[0] = unsafe { Val::I32(*data.input.add(input[0].unwrap_i64() as usize) as i32) };
     Ok(())
 }
 
@@ -56,11 +57,11 @@ pub(crate) fn input_load_u64(
     output: &mut [Val],
 ) -> Result<(), Error> {
     let data: &Internal = caller.data();
-    if data.input.is_null() {
-        return Ok(());
-    }
     let offs = args!(input, 0, i64) as usize;
-    let slice = unsafe { std::slice::from_raw_parts(data.input.add(offs), 8) };
+    let slice = match data.input().get(offs..offs.saturating_add(8)) {
+        Some(x) => x,
+        None => return Err(Error::msg(format!("Invalid input offset: {offs}"))),
+    };
     let byte = u64::from_ne_bytes(slice.try_into().unwrap());
     output[0] = Val::I64(byte as i64);
     Ok(())
@@ -134,11 +135,72 @@ pub(crate) fn output_set(
 ) -> Result<(), Error> {
     let data: &mut Internal = caller.data_mut();
     let (offset, length) = args!(input, (0, i64), (1, i64));
-    data.output_offset = offset as usize;
-    data.output_length = length as usize;
+
+    // Offsets and lengths are 64-bit all the way to `extism_plugin_output_length`, the block is
+    // checked here so a negative or out of bounds value can't be read by the host later
+    let (offset, length) = match checked_block(data, offset, length) {
+        Some(x) => x,
+        None => {
+            return Err(anyhow::format_err!(
+                "Invalid output block: {length} bytes at offset {offset}"
+            ))
+        }
+    };
+
+    data.output_offset = offset;
+    data.output_length = length;
     Ok(())
 }
 
+/// Send part of the output to the host while the plugin is still running, this is only
+/// available when the plugin is called using `extism_plugin_call_streaming`
+/// Params: i64 (offset), i64 (length)
+/// Returns: none
+pub(crate) fn emit(
+    mut caller: Caller<Internal>,
+    input: &[Val],
+    _output: &mut [Val],
+) -> Result<(), Error> {
+    let data: &mut Internal = caller.data_mut();
+    let (offset, length) = args!(input, (0, i64), (1, i64));
+
+    let emitter = match data.emitter {
+        Some(x) => x,
+        None => {
+            return Err(anyhow::format_err!(
+                "extism_emit can only be used when the plugin is called using \
+                 extism_plugin_call_streaming"
+            ))
+        }
+    };
+
+    let (offset, length) = match checked_block(data, offset, length) {
+        Some(x) => x,
+        None => {
+            return Err(anyhow::format_err!(
+                "Invalid emit block: {length} bytes at offset {offset}"
+            ))
+        }
+    };
+
+    trace!("Emitting {length} bytes at offset {offset}");
+    let bytes = &data.memory().data()[offset..offset + length];
+    unsafe { emitter.emit(bytes) };
+    Ok(())
+}
+
+// Convert `offset` and `length` to a block that's inside the plugin's memory
+fn checked_block(data: &Internal, offset: i64, length: i64) -> Option<(usize, usize)> {
+    usize::try_from(offset)
+        .ok()
+        .zip(usize::try_from(length).ok())
+        .filter(|(offset, length)| {
+            offset
+                .checked_add(*length)
+                .is_some_and(|end| end <= data.memory().size())
+        })
+}
+
 /// Allocate bytes
 /// Params: i64 (length)
 /// Returns: i64 (offset)
@@ -282,6 +344,67 @@ pub(crate) fn var_set(
     Ok(())
 }
 
+/// Get a value from the context's key-value store
+/// Params: i64 (key offset)
+/// Returns: i64 (offset), 0 if the key doesn't exist
+pub(crate) fn kv_get(
+    mut caller: Caller<Internal>,
+    input: &[Val],
+    output: &mut [Val],
+) -> Result<(), Error> {
+    let data: &mut Internal = caller.data_mut();
+    let plugin = data.plugin_mut();
+
+    let offset = args!(input, 0, i64) as usize;
+    let key = plugin.memory.get(offset)?;
+    let value = plugin.kv().get(key).map(|x| x.to_vec());
+
+    let mem = match value {
+        Some(x) => plugin.memory.alloc_bytes(x)?,
+        None => {
+            output[0] = Val::I64(0);
+            return Ok(());
+        }
+    };
+
+    output[0] = Val::I64(mem.offset as i64);
+    Ok(())
+}
+
+/// Set a value in the context's key-value store
+/// Params: i64 (key offset), i64 (value offset)
+/// Returns: none
+pub(crate) fn kv_set(
+    mut caller: Caller<Internal>,
+    input: &[Val],
+    _output: &mut [Val],
+) -> Result<(), Error> {
+    let data: &mut Internal = caller.data_mut();
+    let plugin = data.plugin_mut();
+
+    let (key_offs, value_offs) = args!(input, (0, i64), (1, i64));
+    let key = plugin.memory.get(key_offs as usize)?;
+    let value = plugin.memory.get(value_offs as usize)?;
+    plugin.kv().set(key, value)
+}
+
+/// Remove a key from the context's key-value store
+/// Params: i64 (key offset)
+/// Returns: none
+pub(crate) fn kv_delete(
+    mut caller: Caller<Internal>,
+    input: &[Val],
+    _output: &mut [Val],
+) -> Result<(), Error> {
+    let data: &mut Internal = caller.data_mut();
+    let plugin = data.plugin_mut();
+
+    let offset = args!(input, 0, i64) as usize;
+    let key = plugin.memory.get(offset)?;
+    plugin.kv().delete(key);
+    Ok(())
+}
+
 /// Make an HTTP request
 /// Params: i64 (offset to JSON encoded HttpRequest), i64 (offset to body or 0)
 /// Returns: i64 (offset)
@@ -316,21 +439,12 @@ pub(crate) fn http_request(
         };
         let allowed_hosts = &data.plugin().manifest.as_ref().allowed_hosts;
         let host_str = url.host_str().unwrap_or_default();
-        if let Some(allowed_hosts) = allowed_hosts {
-            let host_matches_allowed = allowed_hosts.iter().any(|url| {
-                let pat = match glob::Pattern::new(url) {
-                    Ok(x) => x,
-                    Err(_) => return url == host_str,
-                };
-
-                pat.matches(host_str)
-            });
-            if !host_matches_allowed {
-                return Err(Error::msg(format!(
-                    "HTTP request to {} is not allowed",
-                    req.url
-                )));
-            }
+        if !manifest::is_host_allowed(allowed_hosts, host_str) {
+            warn!("HTTP request to {} is not allowed", req.url);
+            return Err(Error::msg(format!(
+                "HTTP request to {} is not allowed",
+                req.url
+            )));
         }
 
         let mut r = ureq::request(req.method.as_deref().unwrap_or("GET"), &req.url);
@@ -339,20 +453,26 @@ pub(crate) fn http_request(
             r = r.set(k, v);
         }
 
-        let res = if body_offset > 0 {
-            let buf = data.memory().get(body_offset)?;
-            let res = r.send_bytes(buf)?;
-            data.http_status = res.status();
+        // Waiting for the response shouldn't count towards the plugin's timeout
+        data.pause_timeout()?;
+        let res = (|| -> Result<_, Error> {
+            let res = if body_offset > 0 {
+                let buf = data.memory().get(body_offset)?;
+                r.send_bytes(buf)?
+            } else {
+                r.call()?
+            };
+            let status = res.status();
+
+            let mut buf = Vec::new();
             res.into_reader()
-        } else {
-            let res = r.call()?;
-            data.http_status = res.status();
-            res.into_reader()
-        };
-
-        let mut buf = Vec::new();
-        res.take(1024 * 1024 * 50) // TODO: make this limit configurable
-            .read_to_end(&mut buf)?;
+                .take(1024 * 1024 * 50) // TODO: make this limit configurable
+                .read_to_end(&mut buf)?;
+            Ok((status, buf))
+        })();
+        data.resume_timeout()?;
+        let (status, buf) = res?;
+        data.http_status = status;
 
         let mem = data.memory_mut().alloc_bytes(buf)?;
 
@@ -456,3 +576,43 @@ pub(crate) fn log_error(
 ) -> Result<(), Error> {
     log(log::Level::Error, caller, input, _output)
 }
+
+/// Generate a new ID, this will be random unless the host has set a seed using
+/// `extism_plugin_set_id_sequence`
+/// Params: none
+/// Returns: i64 (offset)
+pub(crate) fn generate_id(
+    mut caller: Caller<Internal>,
+    _input: &[Val],
+    output: &mut [Val],
+) -> Result<(), Error> {
+    let data: &mut Internal = caller.data_mut();
+    let plugin = data.plugin_mut();
+    let id = plugin.generate_id().to_string();
+    let mem = plugin.memory.alloc_bytes(id)?;
+    output[0] = Val::I64(mem.offset as i64);
+    Ok(())
+}
+
+/// Get a metadata value passed to `extism_plugin_call_with_meta`
+/// Params: i64 (offset)
+/// Returns: i64 (offset)
+pub(crate) fn get_meta(
+    mut caller: Caller<Internal>,
+    input: &[Val],
+    output: &mut [Val],
+) -> Result<(), Error> {
+    let data: &mut Internal = caller.data_mut();
+    let offset = args!(input, 0, i64) as usize;
+    let key = data.memory().get_str(offset)?;
+    let val = match data.meta.get(key) {
+        Some(x) => x.clone(),
+        None => {
+            output[0] = Val::I64(0);
+            return Ok(());
+        }
+    };
+    let mem = data.memory_mut().alloc_bytes(val)?;
+    output[0] = Val::I64(mem.offset as i64);
+    Ok(())
+}
@@ -8,29 +8,419 @@ pub struct Plugin {
     pub linker: Linker<Internal>,
     pub instance: Instance,
     pub last_error: std::cell::RefCell<Option<std::ffi::CString>>,
+    pub last_error_kind: std::cell::Cell<ErrorKind>,
+    pub(crate) last_error_json: std::cell::RefCell<Option<std::ffi::CString>>,
+    pub last_backtrace: std::cell::RefCell<Option<std::ffi::CString>>,
     pub memory: PluginMemory,
     pub manifest: Manifest,
     pub vars: BTreeMap<String, Vec<u8>>,
     pub should_reinstantiate: bool,
     pub timer_id: uuid::Uuid,
+    pub memory_grew: bool,
+    pub last_used: std::time::Instant,
+    pub last_call_duration: std::time::Duration,
+    pub metrics: Metrics,
+    pub(crate) metrics_json: std::cell::RefCell<Option<std::ffi::CString>>,
+    pub id_sequence: Option<u64>,
+    pub random_seed: Option<u64>,
+    pub(crate) mock_clock: Option<std::sync::Arc<clocks::MockClock>>,
+    pub test_results: Option<std::ffi::CString>,
+    pub env_locked: bool,
+    pub inherit_env: bool,
+    pub rollback_on_error: bool,
+    pub zero_memory_on_reset: bool,
+    pub timeout_handler: Option<TimeoutHandler>,
+    pub cancel_handle: std::sync::Arc<CancelHandle>,
+    // All of the plugin's modules, including `module`, by name
+    pub(crate) modules: BTreeMap<String, Module>,
+    // The host functions, these are defined again each time the plugin is reinstantiated
+    pub(crate) imports: Vec<Function>,
+    // The module cache keys of the plugin's modules
+    pub(crate) module_hashes: Vec<String>,
+    pub serialized: Option<Vec<u8>>,
+    pub args: Vec<String>,
+    pub memory_high_water: usize,
+    // Reused by `memory_usage`, which runs twice per call, so it doesn't allocate each time
+    exported_memories: Vec<wasmtime::Memory>,
+    pub last_exit_code: Option<i32>,
+    pub(crate) async_fuel: Option<AsyncFuel>,
+    pub kv: std::sync::Arc<std::sync::Mutex<KvStore>>,
+    pub(crate) function_handles: Vec<FunctionHandle>,
+    // Stored in the upper bits of function handles, so handles from before a reset are rejected
+    function_handle_generation: u16,
+    /// How often the timer checks whether the timeout has expired
+    pub epoch_interval: std::time::Duration,
+    // Shared with the context so plugins can be sorted without waiting for their calls
+    pub(crate) usage: std::sync::Arc<PluginUsage>,
+}
+
+static FUNCTION_HANDLE_GENERATION: std::sync::atomic::AtomicU16 =
+    std::sync::atomic::AtomicU16::new(0);
+
+// Function handles are limited to 15 bits of generation and 16 bits of index, so they're always
+// positive
+const FUNCTION_HANDLE_INDEX_BITS: u32 = 16;
+const FUNCTION_HANDLE_GENERATION_MASK: u16 = 0x7fff;
+
+/// Get the generation for a new set of function handles, generations are shared between plugins
+/// so handles from a plugin that was replaced using `extism_plugin_update` are also rejected
+fn next_function_handle_generation() -> u16 {
+    FUNCTION_HANDLE_GENERATION.fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+        & FUNCTION_HANDLE_GENERATION_MASK
+}
+
+static USE_SEQUENCE: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+/// Usage statistics that can be read while the plugin is being called, these are updated when
+/// a call starts and when it finishes
+#[derive(Default)]
+pub(crate) struct PluginUsage {
+    // Increases each time any plugin is used, so sorting by it orders plugins by last use
+    pub(crate) last_used: std::sync::atomic::AtomicU64,
+    // The memory usage measured at the end of the last call
+    pub(crate) memory: std::sync::atomic::AtomicUsize,
+}
+
+impl PluginUsage {
+    fn used(&self) {
+        let n = USE_SEQUENCE.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        self.last_used
+            .store(n, std::sync::atomic::Ordering::Relaxed);
+    }
+}
+
+/// A function resolved using `Plugin::resolve_function`, `func` is cleared when the plugin is
+/// reinstantiated and looked up again the next time the handle is used
+pub(crate) struct FunctionHandle {
+    name: std::ffi::CString,
+    func: Option<Func>,
+}
+
+/// The kind of error stored in `last_error`, used to classify errors returned by
+/// `extism_plugin_last_error_json` and `extism_plugin_last_error_code`. The numeric values are
+/// stable and returned by `code`, `0` is used when no error is set
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    NotFound = 1,
+    Timeout = 2,
+    Trap = 3,
+    WasiExit = 4,
+    BadUtf8 = 5,
+    Cancelled = 6,
+    MemoryLimit = 7,
+    FuelExhausted = 8,
+    InvalidOutput = 9,
+    #[default]
+    Error = 10,
+    ResourceLimit = 11,
+}
+
+impl ErrorKind {
+    /// Get the numeric error code
+    pub fn code(self) -> i32 {
+        self as i32
+    }
+}
+
+/// Find the first import of `module` that isn't defined in `linker`
+fn unresolved_import(
+    linker: &Linker<Internal>,
+    store: &mut Store<Internal>,
+    module: &Module,
+) -> Option<(String, String)> {
+    module
+        .imports()
+        .find(|import| {
+            linker
+                .get(&mut *store, import.module(), import.name())
+                .is_none()
+        })
+        .map(|import| (import.module().to_string(), import.name().to_string()))
+}
+
+pub type TimeoutHandlerCallback = Option<
+    unsafe extern "C" fn(
+        plugin: PluginIndex,
+        memory: *const u8,
+        memory_len: Size,
+        user_data: *mut std::ffi::c_void,
+    ),
+>;
+
+/// A callback with associated user data that is called when a plugin times out
+#[derive(Clone, Copy)]
+pub struct TimeoutHandler {
+    pub callback: unsafe extern "C" fn(PluginIndex, *const u8, Size, *mut std::ffi::c_void),
+    pub user_data: *mut std::ffi::c_void,
+}
+
+// Safety: the handler is only called on the thread making a call to the plugin, which can be any
+// thread in a thread-safe context, so hosts sharing a context between threads must be able to
+// use `user_data` from each of them
+unsafe impl Send for TimeoutHandler {}
+
+impl TimeoutHandler {
+    pub(crate) unsafe fn notify(&self, plugin: PluginIndex, memory: &[u8]) {
+        (self.callback)(
+            plugin,
+            memory.as_ptr(),
+            memory.len() as Size,
+            self.user_data,
+        )
+    }
+}
+
+pub type EmitCallback = Option<
+    unsafe extern "C" fn(
+        plugin: PluginIndex,
+        data: *const u8,
+        data_len: Size,
+        user_data: *mut std::ffi::c_void,
+    ),
+>;
+
+/// A callback with associated user data that receives the data a plugin passes to `extism_emit`
+/// during a streaming call
+#[derive(Clone, Copy)]
+pub struct Emitter {
+    pub plugin: PluginIndex,
+    pub callback: unsafe extern "C" fn(PluginIndex, *const u8, Size, *mut std::ffi::c_void),
+    pub user_data: *mut std::ffi::c_void,
+}
+
+// Safety: the emitter is set at the start of a streaming call and removed when the call returns,
+// it's only used by the thread making the call, see `CallPtr`
+unsafe impl Send for Emitter {}
+
+impl Emitter {
+    pub(crate) unsafe fn emit(&self, data: &[u8]) {
+        (self.callback)(
+            self.plugin,
+            data.as_ptr(),
+            data.len() as Size,
+            self.user_data,
+        )
+    }
+}
+
+/// Used to cancel a running call from another thread
+pub struct CancelHandle {
+    engine: Engine,
+    cancelled: std::sync::atomic::AtomicBool,
+}
+
+impl CancelHandle {
+    /// Interrupt the call that is currently running, this does nothing if no call is running
+    pub fn cancel(&self) {
+        self.cancelled
+            .store(true, std::sync::atomic::Ordering::SeqCst);
+        self.engine.increment_epoch();
+    }
+
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.cancelled.load(std::sync::atomic::Ordering::SeqCst)
+    }
+
+    pub(crate) fn reset(&self) {
+        self.cancelled
+            .store(false, std::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+/// Limits the size of a plugin's memories and tables, and the number of instances, memories
+/// and tables in its store
+pub struct MemoryLimiter {
+    pub max_pages: Option<u32>,
+    /// Set when growing a memory is denied, the plugin traps with `memory limit exceeded` the
+    /// next time it checks for interruption
+    pub exceeded: bool,
+    pub max_table_elements: Option<u32>,
+    /// Set when growing a table is denied, the plugin traps with `resource limit exceeded` the
+    /// next time it checks for interruption
+    pub table_exceeded: bool,
+    pub max_instances: usize,
+    pub max_memories: usize,
+    pub max_tables: usize,
+    // Used to interrupt the plugin when a limit is exceeded
+    pub(crate) engine: Option<Engine>,
+}
+
+impl Default for MemoryLimiter {
+    fn default() -> Self {
+        MemoryLimiter {
+            max_pages: None,
+            exceeded: false,
+            max_table_elements: None,
+            table_exceeded: false,
+            max_instances: DEFAULT_INSTANCE_LIMIT,
+            max_memories: DEFAULT_MEMORY_LIMIT,
+            max_tables: DEFAULT_TABLE_LIMIT,
+            engine: None,
+        }
+    }
+}
+
+impl MemoryLimiter {
+    /// Returns true if `e` was caused by one of the limits, this includes the instance, memory
+    /// and table counts, which wasmtime checks itself when a module is instantiated
+    pub(crate) fn caused(&self, e: &Error) -> bool {
+        self.exceeded
+            || self.table_exceeded
+            || e.root_cause()
+                .to_string()
+                .starts_with("resource limit exceeded")
+    }
+
+    // Make the plugin trap at its next interruption check, the epoch deadline callback reports
+    // the limit that was exceeded
+    fn interrupt(&self) {
+        if let Some(engine) = &self.engine {
+            engine.increment_epoch();
+        }
+    }
+}
+
+impl ResourceLimiter for MemoryLimiter {
+    fn memory_growing(&mut self, _current: usize, desired: usize, _maximum: Option<usize>) -> bool {
+        if let Some(max) = self.max_pages {
+            if desired > max as usize * memory::PAGE_SIZE as usize {
+                debug!("Memory limit exceeded: {desired} bytes requested, max is {max} pages");
+                self.exceeded = true;
+                self.interrupt();
+                return false;
+            }
+        }
+        true
+    }
+
+    fn table_growing(&mut self, _current: u32, desired: u32, _maximum: Option<u32>) -> bool {
+        if let Some(max) = self.max_table_elements {
+            if desired > max {
+                debug!("Table limit exceeded: {desired} elements requested, max is {max}");
+                self.table_exceeded = true;
+                self.interrupt();
+                return false;
+            }
+        }
+        true
+    }
+
+    fn instances(&self) -> usize {
+        self.max_instances
+    }
+
+    fn memories(&self) -> usize {
+        self.max_memories
+    }
+
+    fn tables(&self) -> usize {
+        self.max_tables
+    }
 }
 
 pub struct Internal {
     pub input_length: usize,
-    pub input: *const u8,
+    pub(crate) input: CallPtr<*const u8>,
+    // Offset of the input in Extism memory, this is set instead of `input` when the input was
+    // written to plugin memory by `Plugin::set_input_segments`
+    pub(crate) input_offset: Option<usize>,
     pub output_offset: usize,
     pub output_length: usize,
-    pub plugin: *mut Plugin,
+    pub(crate) plugin: CallPtr<*mut Plugin>,
     pub wasi: Option<Wasi>,
     pub http_status: u16,
+    pub meta: BTreeMap<String, String>,
+    pub memory_limiter: MemoryLimiter,
+    /// Receives data passed to `extism_emit`, this is only set during a streaming call
+    pub emitter: Option<Emitter>,
+    pub(crate) timer_tx: Option<std::sync::mpsc::SyncSender<TimerAction>>,
+    // The number of times the timeout has been paused without being resumed
+    timeout_paused: usize,
 }
 
+/// A pointer stored in `Internal` that's only used while the plugin is being called
+#[derive(Clone, Copy)]
+pub(crate) struct CallPtr<T>(T);
+
+// Safety: wasmtime requires store data to be `Send` to call plugins with async support. `plugin`
+// points to the boxed plugin that owns the store and `input` points to the input of the call in
+// progress, both are set at the start of each call and are only dereferenced during that call.
+// A plugin is only called by one thread at a time, it's locked for the whole call in thread-safe
+// contexts, so the pointers are never used from two threads at once
+unsafe impl<T> Send for CallPtr<*const T> {}
+unsafe impl<T> Send for CallPtr<*mut T> {}
+
 pub struct Wasi {
     pub ctx: wasmtime_wasi::WasiCtx,
     #[cfg(feature = "nn")]
     pub nn: wasmtime_wasi_nn::WasiNnCtx,
     #[cfg(not(feature = "nn"))]
     pub nn: (),
+    /// Data written to stdout during the last call
+    pub stdout: std::sync::Arc<std::sync::RwLock<CapturedOutput>>,
+    /// Data written to stderr during the last call
+    pub stderr: std::sync::Arc<std::sync::RwLock<CapturedOutput>>,
+    /// The in-memory filesystem mounted at `/`, this is only set when the manifest has `files`
+    pub fs: Option<std::sync::Arc<std::sync::RwLock<MemFs>>>,
+}
+
+impl Wasi {
+    /// Clear captured stdout and stderr
+    pub(crate) fn clear_output(&self) {
+        if let Ok(mut stdout) = self.stdout.write() {
+            stdout.clear();
+        }
+        if let Ok(mut stderr) = self.stderr.write() {
+            stderr.clear();
+        }
+    }
+}
+
+/// The maximum number of bytes of stdout or stderr captured during a single call
+pub(crate) const MAX_CAPTURED_OUTPUT: usize = 1024 * 1024;
+
+/// Data written to stdout or stderr by a WASI plugin, anything written after the first
+/// `MAX_CAPTURED_OUTPUT` bytes of a call is dropped
+#[derive(Default)]
+pub struct CapturedOutput {
+    data: Vec<u8>,
+    truncated: bool,
+}
+
+impl CapturedOutput {
+    /// Get the captured data
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Returns true if data was dropped because the limit was reached
+    pub fn truncated(&self) -> bool {
+        self.truncated
+    }
+
+    fn clear(&mut self) {
+        self.data.clear();
+        self.truncated = false;
+    }
+}
+
+impl std::io::Write for CapturedOutput {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = buf.len().min(MAX_CAPTURED_OUTPUT - self.data.len());
+        self.data.extend_from_slice(&buf[..n]);
+        if n < buf.len() && !self.truncated {
+            warn!("WASI output exceeded {MAX_CAPTURED_OUTPUT} bytes and was truncated");
+            self.truncated = true;
+        }
+
+        // The whole buffer is reported as written so the plugin doesn't retry or fail
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
 }
 
 impl Internal {
@@ -38,17 +428,33 @@ impl Internal {
         let wasi = if wasi {
             let auth = wasmtime_wasi::ambient_authority();
             let mut ctx = wasmtime_wasi::WasiCtxBuilder::new();
-            for (k, v) in manifest.as_ref().config.iter() {
+            for (k, v) in manifest.as_ref().public_config() {
                 ctx = ctx.env(k, v)?;
             }
 
             if let Some(a) = &manifest.as_ref().allowed_paths {
                 for (k, v) in a.iter() {
-                    let d = wasmtime_wasi::Dir::open_ambient_dir(k, auth)?;
+                    let d = match wasmtime_wasi::Dir::open_ambient_dir(k, auth) {
+                        Ok(d) => d,
+                        Err(e) => {
+                            return Err(Error::from(e)
+                                .context(format!("Unable to open allowed path {}", k.display())))
+                        }
+                    };
                     ctx = ctx.preopened_dir(d, v)?;
                 }
             }
 
+            let stdout = std::sync::Arc::new(std::sync::RwLock::new(CapturedOutput::default()));
+            let stderr = std::sync::Arc::new(std::sync::RwLock::new(CapturedOutput::default()));
+            ctx = ctx
+                .stdout(Box::new(wasi_common::pipe::WritePipe::from_shared(
+                    stdout.clone(),
+                )))
+                .stderr(Box::new(wasi_common::pipe::WritePipe::from_shared(
+                    stderr.clone(),
+                )));
+
             #[cfg(feature = "nn")]
             let nn = wasmtime_wasi_nn::WasiNnCtx::new()?;
 
@@ -56,9 +462,24 @@ impl Internal {
             #[allow(clippy::let_unit_value)]
             let nn = ();
 
+            let mut ctx = ctx.build();
+            clocks::configure(&mut ctx, manifest);
+
+            let files = &manifest.as_ref().files;
+            let fs = if files.is_empty() {
+                None
+            } else {
+                let fs = std::sync::Arc::new(std::sync::RwLock::new(MemFs::new(files)?));
+                ctx.push_preopened_dir(Box::new(memfs::MemDir::root(fs.clone())), "/")?;
+                Some(fs)
+            };
+
             Some(Wasi {
-                ctx: ctx.build(),
+                ctx,
                 nn,
+                stdout,
+                stderr,
+                fs,
             })
         } else {
             None
@@ -68,19 +489,41 @@ impl Internal {
             input_length: 0,
             output_offset: 0,
             output_length: 0,
-            input: std::ptr::null(),
+            input: CallPtr(std::ptr::null()),
+            input_offset: None,
             wasi,
-            plugin: std::ptr::null_mut(),
+            plugin: CallPtr(std::ptr::null_mut()),
             http_status: 0,
+            meta: BTreeMap::new(),
+            memory_limiter: MemoryLimiter {
+                max_pages: manifest.as_ref().memory.max_pages,
+                ..Default::default()
+            },
+            emitter: None,
+            timer_tx: None,
+            timeout_paused: 0,
         })
     }
 
     pub fn plugin(&self) -> &Plugin {
-        unsafe { &*self.plugin }
+        unsafe { &*self.plugin.0 }
+    }
+
+    /// Get the input for the current call
+    pub fn input(&self) -> &[u8] {
+        if let Some(offs) = self.input_offset {
+            return &self.memory().data()[offs..offs + self.input_length];
+        }
+
+        if self.input.0.is_null() {
+            return &[];
+        }
+
+        unsafe { std::slice::from_raw_parts(self.input.0, self.input_length) }
     }
 
     pub fn plugin_mut(&mut self) -> &mut Plugin {
-        unsafe { &mut *self.plugin }
+        unsafe { &mut *self.plugin.0 }
     }
 
     pub fn memory(&self) -> &PluginMemory {
@@ -90,10 +533,367 @@ impl Internal {
     pub fn memory_mut(&mut self) -> &mut PluginMemory {
         &mut self.plugin_mut().memory
     }
+
+    /// Stop the time spent until `resume_timeout` is called from counting towards the plugin's
+    /// timeout, pauses can be nested
+    pub fn pause_timeout(&mut self) -> Result<(), Error> {
+        self.timeout_paused += 1;
+        if self.timeout_paused > 1 || self.plugin.0.is_null() {
+            return Ok(());
+        }
+
+        if let Some(tx) = &self.timer_tx {
+            Plugin::pause_timer(tx, self.plugin().timer_id)?;
+        }
+        Ok(())
+    }
+
+    /// Resume a timeout paused using `pause_timeout`, the timeout only starts counting again
+    /// once every pause has been resumed
+    pub fn resume_timeout(&mut self) -> Result<(), Error> {
+        if self.timeout_paused == 0 {
+            return Ok(());
+        }
+
+        self.timeout_paused -= 1;
+        if self.timeout_paused > 0 || self.plugin.0.is_null() {
+            return Ok(());
+        }
+
+        if let Some(tx) = &self.timer_tx {
+            Plugin::resume_timer(tx, self.plugin().timer_id)?;
+        }
+        Ok(())
+    }
 }
 
 const EXPORT_MODULE_NAME: &str = "env";
 
+// Get the `main` module, or the last one if `main` doesn't exist
+fn main_module(modules: &BTreeMap<String, Module>) -> (&str, &Module) {
+    let (name, module) = modules
+        .get_key_value("main")
+        .or_else(|| modules.iter().last())
+        .unwrap();
+    (name.as_str(), module)
+}
+
+// Create a store for a plugin that enforces its resource limits and interrupts calls that are
+// cancelled or time out
+fn new_store(
+    engine: &Engine,
+    internal: Internal,
+    cancel_handle: &std::sync::Arc<CancelHandle>,
+) -> Store<Internal> {
+    let mut store = Store::new(engine, internal);
+    store.data_mut().memory_limiter.engine = Some(engine.clone());
+
+    // Start functions run while the plugin is instantiated, without a deadline they would be
+    // interrupted immediately
+    store.set_epoch_deadline(1);
+    store.limiter(|internal| &mut internal.memory_limiter);
+
+    let handle = cancel_handle.clone();
+    store.epoch_deadline_callback(move |internal| {
+        if internal.memory_limiter.exceeded {
+            Err(Error::msg("memory limit exceeded"))
+        } else if internal.memory_limiter.table_exceeded {
+            Err(Error::msg("resource limit exceeded"))
+        } else if handle.is_cancelled() {
+            Err(Error::msg("cancelled"))
+        } else {
+            Err(Error::msg("timeout"))
+        }
+    });
+    store
+}
+
+// Define the built-in functions, host functions and modules in a new linker and instantiate the
+// main module, all of the modules are instantiated in `store`
+fn link(
+    store: &mut Store<Internal>,
+    modules: &BTreeMap<String, Module>,
+    imports: &[Function],
+    async_support: bool,
+) -> Result<(Linker<Internal>, Instance), Error> {
+    let mut linker = Linker::new(store.engine());
+    linker.allow_shadowing(true);
+
+    if store.data().wasi.is_some() {
+        wasmtime_wasi::add_to_linker(&mut linker, |x: &mut Internal| {
+            &mut x.wasi.as_mut().unwrap().ctx
+        })?;
+
+        #[cfg(feature = "nn")]
+        wasmtime_wasi_nn::add_to_linker(&mut linker, |x: &mut Internal| {
+            &mut x.wasi.as_mut().unwrap().nn
+        })?;
+    }
+    let (main_name, main) = main_module(modules);
+
+    macro_rules! define_funcs {
+        ($m:expr, { $($name:ident($($args:expr),*) $(-> $($r:expr),*)?);* $(;)?}) => {
+            match $m {
+            $(
+                concat!("extism_", stringify!($name)) => {
+                    let t = FuncType::new([$($args),*], [$($($r),*)?]);
+                    let f = Func::new(&mut *store, t, pdk::$name);
+                    linker.define(EXPORT_MODULE_NAME, concat!("extism_", stringify!($name)), Extern::Func(f))?;
+                    continue
+                }
+            )*
+                _ => ()
+            }
+        };
+    }
+
+    // Add builtins
+    for (_name, module) in modules.iter() {
+        for import in module.imports() {
+            let module_name = import.module();
+            let name = import.name();
+            use wasmtime::ValType::*;
+
+            if module_name == EXPORT_MODULE_NAME {
+                define_funcs!(name,  {
+                    alloc(I64) -> I64;
+                    free(I64);
+                    load_u8(I64) -> I32;
+                    load_u64(I64) -> I64;
+                    store_u8(I64, I32);
+                    store_u64(I64, I64);
+                    input_length() -> I64;
+                    input_load_u8(I64) -> I32;
+                    input_load_u64(I64) -> I64;
+                    output_set(I64, I64);
+                    emit(I64, I64);
+                    error_set(I64);
+                    config_get(I64) -> I64;
+                    var_get(I64) -> I64;
+                    var_set(I64, I64);
+                    http_request(I64, I64) -> I64;
+                    http_status_code() -> I32;
+                    length(I64) -> I64;
+                    log_warn(I64);
+                    log_info(I64);
+                    log_debug(I64);
+                    log_error(I64);
+                    generate_id() -> I64;
+                    get_meta(I64) -> I64;
+                    kv_get(I64) -> I64;
+                    kv_set(I64, I64);
+                    kv_delete(I64);
+                });
+            }
+        }
+    }
+
+    // Add host functions, these are defined in the `env` module unless another namespace
+    // is provided. Imports are matched using both the namespace and the name, so host
+    // functions can't replace each other or the built-in `extism_` functions
+    let mut defined = std::collections::BTreeSet::new();
+    for f in imports {
+        let Function(name, ty, f, namespace) = f.clone();
+        let namespace = namespace.unwrap_or_else(|| EXPORT_MODULE_NAME.to_string());
+        if namespace == EXPORT_MODULE_NAME && name.starts_with("extism_") {
+            return Err(anyhow::format_err!(
+                "Host function {namespace}::{name} conflicts with a built-in function, use \
+                 another namespace"
+            ));
+        }
+        if !defined.insert((namespace.clone(), name.clone())) {
+            return Err(anyhow::format_err!(
+                "Host function {namespace}::{name} is defined more than once"
+            ));
+        }
+
+        // Time spent in host functions shouldn't count towards the timeout
+        let func = Func::new(&mut *store, ty, move |mut caller, params, results| {
+            caller.data_mut().pause_timeout()?;
+            // Safety: the store's data outlives the call, `caller` is only used to access
+            // it inside `f`, which has returned by the time this pointer is used
+            let internal: *mut Internal = caller.data_mut();
+            // A panic can't unwind into wasm, so it's converted to an error which causes the
+            // call to fail
+            let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                f(caller, params, results)
+            }))
+            .unwrap_or_else(|e| {
+                let msg = e
+                    .downcast_ref::<&str>()
+                    .map(|x| x.to_string())
+                    .or_else(|| e.downcast_ref::<String>().cloned())
+                    .unwrap_or_else(|| "unknown panic".to_string());
+                Err(Error::msg(format!("host function panicked: {msg}")))
+            });
+            unsafe { (*internal).resume_timeout()? };
+            res
+        });
+        linker.define(&namespace, &name, func)?;
+    }
+
+    // Add modules to linker, a module is only linked once all of its imports can be resolved
+    // so modules can import from each other regardless of the order they're listed in
+    let mut pending: Vec<_> = modules
+        .iter()
+        .filter(|(name, _)| name.as_str() != main_name)
+        .collect();
+    while !pending.is_empty() {
+        let next = pending
+            .iter()
+            .position(|(_, module)| unresolved_import(&linker, &mut *store, module).is_none());
+        let (name, module) = match next {
+            Some(i) => pending.remove(i),
+            None => {
+                let (name, module) = pending[0];
+                let (m, f) = unresolved_import(&linker, &mut *store, module).unwrap();
+                return Err(anyhow::format_err!(
+                    "Unable to link module {name}: unresolved import {m}::{f}"
+                ));
+            }
+        };
+        if async_support {
+            executor::block_on(linker.module_async(&mut *store, name, module))?;
+        } else {
+            linker.module(&mut *store, name, module)?;
+        }
+        linker.alias_module(name, "env")?;
+    }
+
+    if let Some((m, f)) = unresolved_import(&linker, &mut *store, main) {
+        return Err(anyhow::format_err!(
+            "Unable to link module {main_name}: unresolved import {m}::{f}"
+        ));
+    }
+
+    let instance = if async_support {
+        executor::block_on(linker.instantiate_async(&mut *store, main))?
+    } else {
+        linker.instantiate(&mut *store, main)?
+    };
+    Ok((linker, instance))
+}
+
+// Identifies data created by `Plugin::serialize`, the last byte is the format version
+const SERIALIZED_MAGIC: &[u8] = b"extism-plugin\0\0\x01";
+
+// Append a length-prefixed section to serialized plugin data
+fn write_section(out: &mut Vec<u8>, data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u64).to_le_bytes());
+    out.extend_from_slice(data);
+}
+
+// Read a little-endian `u64` from the start of `data`
+fn read_u64(data: &mut &[u8]) -> Result<u64, Error> {
+    if data.len() < 8 {
+        return Err(Error::msg("Precompiled plugin is truncated"));
+    }
+    let (n, rest) = data.split_at(8);
+    *data = rest;
+    Ok(u64::from_le_bytes(n.try_into().unwrap()))
+}
+
+// Read a length-prefixed section from the start of `data`
+fn read_section<'a>(data: &mut &'a [u8]) -> Result<&'a [u8], Error> {
+    let len = read_u64(data)?;
+    if (data.len() as u64) < len {
+        return Err(Error::msg("Precompiled plugin is truncated"));
+    }
+    let (section, rest) = data.split_at(len as usize);
+    *data = rest;
+    Ok(section)
+}
+
+// Load the manifest and modules from data created by `Plugin::serialize`, the modules are loaded
+// using `Module::deserialize` so `data` must be trusted. The engine is created once the manifest
+// has been read, since it depends on whether the manifest limits fuel
+unsafe fn deserialize(
+    config: &ContextConfig,
+    mut data: &[u8],
+) -> Result<(Engine, Manifest, BTreeMap<String, Module>), Error> {
+    data = match data.strip_prefix(SERIALIZED_MAGIC) {
+        Some(x) => x,
+        None => return Err(Error::msg("Data was not created by Plugin::serialize")),
+    };
+
+    let manifest: Manifest = serde_json::from_slice(read_section(&mut data)?)?;
+    let engine = config.engine(manifest.as_ref().fuel.is_some())?;
+    let count = read_u64(&mut data)?;
+    let mut modules = BTreeMap::new();
+    for _ in 0..count {
+        let name = std::str::from_utf8(read_section(&mut data)?)?.to_string();
+        let module = Module::deserialize(&engine, read_section(&mut data)?)?;
+        modules.insert(name, module);
+    }
+
+    if modules.is_empty() {
+        return Err(Error::msg("Precompiled plugin has no modules"));
+    }
+
+    Ok((engine, manifest, modules))
+}
+
+/// The amount of fuel a plugin with async support uses between yields
+const ASYNC_YIELD_FUEL: u64 = 10_000;
+
+static PARALLEL_COMPILATION: std::sync::atomic::AtomicBool =
+    std::sync::atomic::AtomicBool::new(true);
+
+/// Enable or disable parallel compilation for plugins created after this is called
+pub fn set_parallel_compilation(enabled: bool) {
+    PARALLEL_COMPILATION.store(enabled, std::sync::atomic::Ordering::SeqCst);
+}
+
+static DEFAULT_TIMEOUT_MS: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+// Used when neither the manifest nor `set_default_timeout_ms` sets a timeout
+const DEFAULT_MANIFEST_TIMEOUT_MS: u64 = 30000;
+
+/// Set the timeout used by plugins created after this is called when their manifest doesn't
+/// specify one, `0` restores the 30 second default
+pub fn set_default_timeout_ms(ms: u64) {
+    DEFAULT_TIMEOUT_MS.store(ms, std::sync::atomic::Ordering::SeqCst);
+}
+
+// Get the wasmtime configuration used to create new plugins
+pub(crate) fn engine_config() -> Config {
+    let mut config = Config::new();
+    config
+        .epoch_interruption(true)
+        .parallel_compilation(PARALLEL_COMPILATION.load(std::sync::atomic::Ordering::SeqCst));
+    config
+}
+
+/// Options used to create a plugin
+#[derive(Default, Debug, Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PluginOptions {
+    /// Enable WASI
+    #[serde(default)]
+    pub wasi: bool,
+    /// Override the timeout set in the manifest
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+    /// Make the IDs returned by `generate_id` deterministic, starting from this seed
+    #[serde(default)]
+    pub id_sequence: Option<u64>,
+    /// Stop config updates from changing the WASI environment
+    #[serde(default)]
+    pub lock_env: bool,
+    /// Restore the plugin's memory and exported globals when a call fails, see `Snapshot` for
+    /// what isn't restored
+    #[serde(default)]
+    pub rollback_on_error: bool,
+    /// Zero the plugin's memory when it's reset or reinstantiated
+    #[serde(default)]
+    pub zero_memory_on_reset: bool,
+    /// Copy the host's environment variables into the WASI environment, by default WASI plugins
+    /// only see variables from the plugin's config. Config values take precedence over
+    /// inherited variables with the same name
+    #[serde(default)]
+    pub inherit_env: bool,
+}
+
 impl Plugin {
     /// Create a new plugin from the given WASM code
     pub fn new(wasm: impl AsRef<[u8]>, with_wasi: bool) -> Result<Plugin, Error> {
@@ -106,132 +906,542 @@ impl Plugin {
         imports: impl IntoIterator<Item = Function>,
         with_wasi: bool,
     ) -> Result<Plugin, Error> {
-        let engine = Engine::new(Config::new().epoch_interruption(true))?;
-        let mut imports = imports.into_iter();
-        let (manifest, modules) = Manifest::new(&engine, wasm.as_ref())?;
-        let mut store = Store::new(&engine, Internal::new(&manifest, with_wasi)?);
-
-        store.epoch_deadline_callback(|_internal| Err(Error::msg("timeout")));
+        Self::create(wasm, imports, with_wasi, None, &ContextConfig::default())
+    }
 
-        let memory = Memory::new(
-            &mut store,
-            MemoryType::new(4, manifest.as_ref().memory.max_pages),
+    /// Create a new plugin, modules are loaded from `cache` when it's provided
+    pub(crate) fn create(
+        wasm: impl AsRef<[u8]>,
+        imports: impl IntoIterator<Item = Function>,
+        with_wasi: bool,
+        cache: Option<&mut ModuleCache>,
+        config: &ContextConfig,
+    ) -> Result<Plugin, Error> {
+        let manifest = Manifest::parse(wasm.as_ref())?;
+        let fuel = manifest.as_ref().fuel.is_some();
+        let engine = config.engine(fuel)?;
+        let mut loader = ModuleLoader::new(cache, config.engine_key(fuel));
+        let modules =
+            match manifest.load_modules(&engine, wasm.as_ref(), &mut loader) {
+                Ok(x) => x,
+                Err(e) if config.disables_features() => return Err(e.context(
+                    "Unable to load WASM, it may use a feature that is disabled by the context \
+                     config",
+                )),
+                Err(e) => return Err(e),
+            };
+        let mut plugin = Self::from_modules(
+            engine,
+            manifest,
+            modules,
+            imports,
+            with_wasi,
+            loader.hashes,
+            config,
         )?;
-        let mut memory = PluginMemory::new(store, memory);
-
-        let mut linker = Linker::new(&engine);
-        linker.allow_shadowing(true);
-
-        if with_wasi {
-            wasmtime_wasi::add_to_linker(&mut linker, |x: &mut Internal| {
-                &mut x.wasi.as_mut().unwrap().ctx
-            })?;
+        plugin.epoch_interval = config.epoch_interval();
+        Ok(plugin)
+    }
 
-            #[cfg(feature = "nn")]
-            wasmtime_wasi_nn::add_to_linker(&mut linker, |x: &mut Internal| {
-                &mut x.wasi.as_mut().unwrap().nn
-            })?;
-        }
-        // Get the `main` module, or the last one if `main` doesn't exist
-        let (main_name, main) = modules.get("main").map(|x| ("main", x)).unwrap_or_else(|| {
-            let entry = modules.iter().last().unwrap();
-            (entry.0.as_str(), entry.1)
-        });
+    /// Create a new plugin from data serialized using `Plugin::serialize`, the data must be
+    /// created by the same version of Extism on the same target
+    ///
+    /// # Safety
+    ///
+    /// The compiled machine code in `data` is loaded and run without being verified, so `data`
+    /// must come from a trusted source, e.g. a file written by `Plugin::serialize` that can't be
+    /// modified by anyone else. Untrusted data can run arbitrary code in the host process
+    pub unsafe fn new_precompiled(
+        data: impl AsRef<[u8]>,
+        imports: impl IntoIterator<Item = Function>,
+        with_wasi: bool,
+    ) -> Result<Plugin, Error> {
+        Self::create_precompiled(data, imports, with_wasi, &ContextConfig::default())
+    }
 
-        macro_rules! define_funcs {
-            ($m:expr, { $($name:ident($($args:expr),*) $(-> $($r:expr),*)?);* $(;)?}) => {
-                match $m {
-                $(
-                    concat!("extism_", stringify!($name)) => {
-                        let t = FuncType::new([$($args),*], [$($($r),*)?]);
-                        let f = Func::new(&mut memory.store, t, pdk::$name);
-                        linker.define(EXPORT_MODULE_NAME, concat!("extism_", stringify!($name)), Extern::Func(f))?;
-                        continue
-                    }
-                )*
-                    _ => ()
-                }
+    pub(crate) unsafe fn create_precompiled(
+        data: impl AsRef<[u8]>,
+        imports: impl IntoIterator<Item = Function>,
+        with_wasi: bool,
+        config: &ContextConfig,
+    ) -> Result<Plugin, Error> {
+        // `Module::deserialize` checks that the data was created by a compatible version of
+        // wasmtime and engine configuration before loading it
+        let (engine, manifest, modules) =
+            match deserialize(config, data.as_ref()) {
+                Ok(x) => x,
+                Err(e) => return Err(e.context(
+                    "Unable to load precompiled plugin, it may have been created by a different \
+                     version of Extism or for a different target",
+                )),
             };
-        }
+        let mut plugin = Self::from_modules(
+            engine,
+            manifest,
+            modules,
+            imports,
+            with_wasi,
+            vec![],
+            config,
+        )?;
+        plugin.epoch_interval = config.epoch_interval();
+        Ok(plugin)
+    }
 
-        // Add builtins
-        for (_name, module) in modules.iter() {
-            for import in module.imports() {
-                let module_name = import.module();
-                let name = import.name();
-                use wasmtime::ValType::*;
-
-                if module_name == EXPORT_MODULE_NAME {
-                    define_funcs!(name,  {
-                        alloc(I64) -> I64;
-                        free(I64);
-                        load_u8(I64) -> I32;
-                        load_u64(I64) -> I64;
-                        store_u8(I64, I32);
-                        store_u64(I64, I64);
-                        input_length() -> I64;
-                        input_load_u8(I64) -> I32;
-                        input_load_u64(I64) -> I64;
-                        output_set(I64, I64);
-                        error_set(I64);
-                        config_get(I64) -> I64;
-                        var_get(I64) -> I64;
-                        var_set(I64, I64);
-                        http_request(I64, I64) -> I64;
-                        http_status_code() -> I32;
-                        length(I64) -> I64;
-                        log_warn(I64);
-                        log_info(I64);
-                        log_debug(I64);
-                        log_error(I64);
-                    });
-
-                    for f in &mut imports {
-                        let name = f.name().to_string();
-                        let func = Func::new(&mut memory.store, f.ty().clone(), f.2);
-                        linker.define(EXPORT_MODULE_NAME, &name, func)?;
-                    }
-                }
-            }
-        }
+    fn from_modules(
+        engine: Engine,
+        mut manifest: Manifest,
+        modules: BTreeMap<String, Module>,
+        imports: impl IntoIterator<Item = Function>,
+        with_wasi: bool,
+        module_hashes: Vec<String>,
+        config: &ContextConfig,
+    ) -> Result<Plugin, Error> {
+        // The manifest's timeout takes precedence over the process default, after this `None`
+        // means calls have no time limit
+        let timeout_ms = match manifest.as_ref().timeout_ms {
+            Some(ms) => ms,
+            None => match DEFAULT_TIMEOUT_MS.load(std::sync::atomic::Ordering::SeqCst) {
+                0 => DEFAULT_MANIFEST_TIMEOUT_MS,
+                ms => ms,
+            },
+        };
+        manifest.as_mut().timeout_ms = (timeout_ms > 0).then_some(timeout_ms);
+        let mut internal = Internal::new(&manifest, with_wasi)?;
+        config.apply_limits(&mut internal.memory_limiter);
+        internal.timer_tx = Context::timer().as_ref().map(|t| t.tx.clone());
 
-        // Add modules to linker
-        for (name, module) in modules.iter() {
-            if name != main_name {
-                linker.module(&mut memory.store, name, module)?;
-                linker.alias_module(name, "env")?;
+        let cancel_handle = std::sync::Arc::new(CancelHandle {
+            engine: engine.clone(),
+            cancelled: std::sync::atomic::AtomicBool::new(false),
+        });
+        let mut store = new_store(&engine, internal, &cancel_handle);
+        let fuel = manifest.as_ref().fuel.unwrap_or(u64::MAX);
+        let async_fuel = if config.async_support() {
+            Some(AsyncFuel::set(&mut store, fuel)?)
+        } else {
+            if config.consume_fuel(manifest.as_ref().fuel.is_some()) {
+                store.add_fuel(fuel)?;
             }
-        }
+            None
+        };
 
-        let instance = linker.instantiate(&mut memory.store, main)?;
+        let memory = Memory::new(
+            &mut store,
+            MemoryType::new(4, manifest.as_ref().memory.max_pages),
+        )?;
+        let imports: Vec<Function> = imports.into_iter().collect();
+        let (linker, instance) = link(&mut store, &modules, &imports, async_fuel.is_some())?;
+        let memory = PluginMemory::new(store, memory);
 
+        let module = main_module(&modules).1.clone();
         let mut plugin = Plugin {
-            module: main.clone(),
+            module,
+            modules,
+            imports,
             linker,
             memory,
             instance,
             last_error: std::cell::RefCell::new(None),
+            last_error_kind: std::cell::Cell::new(ErrorKind::Error),
+            last_error_json: std::cell::RefCell::new(None),
+            last_backtrace: std::cell::RefCell::new(None),
             manifest,
             vars: BTreeMap::new(),
             should_reinstantiate: false,
             timer_id: uuid::Uuid::new_v4(),
+            memory_grew: false,
+            last_used: std::time::Instant::now(),
+            last_call_duration: std::time::Duration::ZERO,
+            metrics: Metrics::default(),
+            metrics_json: std::cell::RefCell::new(None),
+            id_sequence: None,
+            random_seed: None,
+            mock_clock: None,
+            test_results: None,
+            env_locked: false,
+            inherit_env: false,
+            rollback_on_error: false,
+            zero_memory_on_reset: false,
+            timeout_handler: None,
+            cancel_handle,
+            args: Vec::new(),
+            memory_high_water: 0,
+            exported_memories: Vec::new(),
+            last_exit_code: None,
+            async_fuel,
+            kv: Default::default(),
+            function_handles: Vec::new(),
+            function_handle_generation: next_function_handle_generation(),
+            epoch_interval: DEFAULT_EPOCH_INTERVAL,
+            module_hashes,
+            serialized: None,
+            usage: Default::default(),
         };
 
         plugin.initialize_runtime()?;
+        plugin.usage.used();
+        let used = plugin.memory_usage();
+        plugin
+            .usage
+            .memory
+            .store(used, std::sync::atomic::Ordering::Relaxed);
 
         Ok(plugin)
     }
 
+    /// Create a new plugin from the given WASM code, imported functions and options
+    pub fn new_with_options(
+        wasm: impl AsRef<[u8]>,
+        imports: impl IntoIterator<Item = Function>,
+        options: &PluginOptions,
+    ) -> Result<Plugin, Error> {
+        let mut plugin = Self::new_with_functions(wasm, imports, options.wasi)?;
+        plugin.apply_options(options)?;
+        Ok(plugin)
+    }
+
+    /// Apply the options that can be changed after a plugin has been created
+    pub(crate) fn apply_options(&mut self, options: &PluginOptions) -> Result<(), Error> {
+        if let Some(timeout) = options.timeout_ms {
+            self.manifest.as_mut().timeout_ms = Some(timeout);
+        }
+        if let Some(seed) = options.id_sequence {
+            self.set_id_sequence(seed);
+        }
+        self.env_locked = options.lock_env;
+        self.rollback_on_error = options.rollback_on_error;
+        self.zero_memory_on_reset = options.zero_memory_on_reset;
+        if options.inherit_env {
+            self.inherit_env = true;
+            self.sync_env()?;
+        }
+        Ok(())
+    }
+
+    /// Set the amount of fuel available to the plugin, replacing any remaining fuel
+    pub fn set_fuel(&mut self, fuel: u64) -> Result<(), Error> {
+        if self.async_fuel.is_some() {
+            self.async_fuel = Some(AsyncFuel::set(&mut self.memory.store, fuel)?);
+            return Ok(());
+        }
+
+        if self.memory.store.fuel_consumed().is_none() {
+            anyhow::bail!(
+                "Fuel isn't enabled for this plugin, set `fuel` in the manifest or enable \
+                 `consume_fuel` in the context config"
+            );
+        }
+
+        let remaining = self.fuel_remaining();
+        if fuel > remaining {
+            self.memory.store.add_fuel(fuel - remaining)?;
+        } else {
+            self.memory.store.consume_fuel(remaining - fuel)?;
+        }
+        Ok(())
+    }
+
+    /// Get the amount of fuel remaining
+    pub fn fuel_remaining(&mut self) -> u64 {
+        if let Some(async_fuel) = &self.async_fuel {
+            return async_fuel.remaining(&mut self.memory.store);
+        }
+
+        // Plugins without fuel metering can't run out of fuel
+        if self.memory.store.fuel_consumed().is_none() {
+            return u64::MAX;
+        }
+
+        // `consume_fuel` fails when the last call ran out of fuel
+        self.memory.store.consume_fuel(0).unwrap_or(0)
+    }
+
+    /// Call `func`, plugins with async support yield to the executor each time they use
+    /// `ASYNC_YIELD_FUEL` units of fuel
+    pub(crate) async fn call_func(
+        &mut self,
+        func: Func,
+        args: &[Val],
+        results: &mut [Val],
+    ) -> Result<(), Error> {
+        if self.async_fuel.is_some() {
+            func.call_async(&mut self.memory.store, args, results).await
+        } else {
+            func.call(&mut self.memory.store, args, results)
+        }
+    }
+
+    /// Limit the size of each of the plugin's memories to `max_pages`, `None` removes the limit
+    pub fn set_memory_limit(&mut self, max_pages: Option<u32>) {
+        self.memory.store.data_mut().memory_limiter.max_pages = max_pages;
+    }
+
+    /// Serialize the plugin's compiled modules and its manifest so the plugin can be loaded
+    /// using `Plugin::new_precompiled` without compiling it again. Secret config values aren't
+    /// included, they have to be set again after the plugin is loaded
+    pub fn serialize(&self) -> Result<Vec<u8>, Error> {
+        let mut manifest = serde_json::to_value(&self.manifest)?;
+
+        // The modules are stored compiled, so their sources aren't needed
+        manifest["wasm"] = serde_json::Value::Array(vec![]);
+        // A missing timeout would use the default when the plugin is loaded
+        manifest["timeout_ms"] = self.manifest.as_ref().timeout_ms.unwrap_or(0).into();
+        let config: BTreeMap<_, _> = self.manifest.as_ref().public_config().collect();
+        manifest["config"] = serde_json::to_value(config)?;
+
+        let mut out = SERIALIZED_MAGIC.to_vec();
+        write_section(&mut out, &serde_json::to_vec(&manifest)?);
+        out.extend_from_slice(&(self.modules.len() as u64).to_le_bytes());
+        for (name, module) in &self.modules {
+            write_section(&mut out, name.as_bytes());
+            write_section(&mut out, &module.serialize()?);
+        }
+        Ok(out)
+    }
+
+    /// Get the plugin's unique ID, a new ID is generated each time a plugin is created
+    pub fn id(&self) -> uuid::Uuid {
+        self.timer_id
+    }
+
+    /// Get the names of all functions exported by the plugin
+    pub fn function_names(&self) -> Vec<String> {
+        self.module
+            .exports()
+            .filter(|x| matches!(x.ty(), ExternType::Func(_)))
+            .map(|x| x.name().to_string())
+            .collect()
+    }
+
+    /// Get the number of functions exported by the module
+    pub fn function_count(&self) -> usize {
+        self.module
+            .exports()
+            .filter(|x| matches!(x.ty(), ExternType::Func(_)))
+            .count()
+    }
+
     /// Get a function by name
     pub fn get_func(&mut self, function: impl AsRef<str>) -> Option<Func> {
         self.instance
             .get_func(&mut self.memory.store, function.as_ref())
     }
 
+    /// Get the current value of the exported global `name`
+    pub fn get_global(&mut self, name: &str) -> Result<Val, Error> {
+        match self.instance.get_global(&mut self.memory.store, name) {
+            Some(global) => Ok(global.get(&mut self.memory.store)),
+            None => Err(anyhow::format_err!("Global not found: {name}")),
+        }
+    }
+
+    /// Set the value of the exported global `name`, this fails if the global is immutable or
+    /// `val` doesn't match its type
+    pub fn set_global(&mut self, name: &str, val: Val) -> Result<(), Error> {
+        let global = match self.instance.get_global(&mut self.memory.store, name) {
+            Some(x) => x,
+            None => return Err(anyhow::format_err!("Global not found: {name}")),
+        };
+
+        let ty = global.ty(&self.memory.store);
+        if ty.mutability() == wasmtime::Mutability::Const {
+            return Err(anyhow::format_err!("Global {name} is immutable"));
+        }
+
+        if ty.content() != &val.ty() {
+            return Err(anyhow::format_err!(
+                "Global {name} has type {}, unable to set it to a value of type {}",
+                ty.content(),
+                val.ty()
+            ));
+        }
+
+        global.set(&mut self.memory.store, val)
+    }
+
+    /// Get a handle that can be used to call the function `name` without looking it up by name
+    /// each time, handles are invalidated when the plugin is reset
+    pub fn resolve_function(&mut self, name: &str) -> Option<i32> {
+        let func = self.get_func(name)?;
+        let index = self
+            .function_handles
+            .iter()
+            .position(|x| x.name.as_bytes() == name.as_bytes());
+        let index = match index {
+            Some(index) => {
+                self.function_handles[index].func = Some(func);
+                index
+            }
+            None => {
+                let index = self.function_handles.len();
+                if index >= 1 << FUNCTION_HANDLE_INDEX_BITS {
+                    return None;
+                }
+                self.function_handles.push(FunctionHandle {
+                    name: std::ffi::CString::new(name).ok()?,
+                    func: Some(func),
+                });
+                index
+            }
+        };
+        let generation = (self.function_handle_generation as i32) << FUNCTION_HANDLE_INDEX_BITS;
+        Some(generation | index as i32)
+    }
+
+    /// Get the name and function for a handle returned by `resolve_function`, the name pointer
+    /// is valid until the plugin is reset
+    pub(crate) fn function_handle(
+        &mut self,
+        handle: i32,
+    ) -> Option<(*const std::os::raw::c_char, Func)> {
+        let handle = u32::try_from(handle).ok()?;
+        if handle >> FUNCTION_HANDLE_INDEX_BITS != self.function_handle_generation as u32 {
+            return None;
+        }
+        let index = handle & ((1 << FUNCTION_HANDLE_INDEX_BITS) - 1);
+        let entry = self.function_handles.get_mut(index as usize)?;
+        let func = match entry.func {
+            Some(f) => f,
+            None => {
+                let name = entry.name.to_str().ok()?;
+                let f = self.instance.get_func(&mut self.memory.store, name)?;
+                entry.func = Some(f);
+                f
+            }
+        };
+        Some((entry.name.as_ptr(), func))
+    }
+
+    /// Lock the key-value store shared by the plugins in this plugin's context
+    pub fn kv(&self) -> std::sync::MutexGuard<'_, KvStore> {
+        match self.kv.lock() {
+            Ok(x) => x,
+            Err(e) => e.into_inner(),
+        }
+    }
+
+    /// Get the total size in bytes of the Extism memory and all memories exported by the plugin
+    pub fn memory_usage(&mut self) -> usize {
+        let mut exports = std::mem::take(&mut self.exported_memories);
+        exports.clear();
+        exports.extend(
+            self.instance
+                .exports(&mut self.memory.store)
+                .filter_map(|x| x.into_memory()),
+        );
+        let exported: usize = exports
+            .iter()
+            .map(|x| x.data_size(&self.memory.store))
+            .sum();
+        self.exported_memories = exports;
+        exported + self.memory.size()
+    }
+
+    /// Update `memory_high_water` using the current memory usage
+    pub(crate) fn update_memory_high_water(&mut self) -> usize {
+        let used = self.memory_usage();
+        self.memory_high_water = self.memory_high_water.max(used);
+        self.usage
+            .memory
+            .store(used, std::sync::atomic::Ordering::Relaxed);
+        used
+    }
+
+    /// Mark the plugin as used by a call that's starting
+    pub(crate) fn touch(&mut self) {
+        self.last_used = std::time::Instant::now();
+        self.usage.used();
+    }
+
+    /// Do the work that would otherwise happen during the first call: finish any pending
+    /// reinstantiation, look up every exported function and fault in the plugin's memory. The
+    /// plugin is already instantiated when it's created, so there's no instantiation to do here
+    pub fn prewarm(&mut self) -> Result<(), Error> {
+        if self.should_reinstantiate {
+            self.should_reinstantiate = false;
+            self.reinstantiate()?;
+        }
+
+        for name in self.function_names() {
+            self.get_func(name);
+        }
+        self.prefault_memory();
+        Ok(())
+    }
+
+    /// Fault in every page of the Extism memory and all memories exported by the plugin
+    pub fn prefault_memory(&mut self) {
+        let exports: Vec<_> = self
+            .instance
+            .exports(&mut self.memory.store)
+            .filter_map(|x| x.into_memory())
+            .collect();
+        for mem in exports {
+            memory::prefault(mem.data_mut(&mut self.memory.store));
+        }
+        self.memory.prefault();
+    }
+
+    /// Generate a new ID, this is random unless a seed has been set using `set_id_sequence`
+    pub fn generate_id(&mut self) -> uuid::Uuid {
+        match &mut self.id_sequence {
+            None => uuid::Uuid::new_v4(),
+            Some(state) => {
+                let mut bytes = [0u8; 16];
+                bytes[..8].copy_from_slice(&splitmix64(state).to_le_bytes());
+                bytes[8..].copy_from_slice(&splitmix64(state).to_le_bytes());
+                uuid::Builder::from_random_bytes(bytes).into_uuid()
+            }
+        }
+    }
+
+    /// Make the IDs returned by `generate_id` deterministic, starting from `seed`
+    pub fn set_id_sequence(&mut self, seed: u64) {
+        self.id_sequence = Some(seed);
+    }
+
+    /// Use a PRNG seeded with `seed` as the WASI random source, the generator is reseeded each
+    /// time the plugin is reinstantiated so every instance sees the same sequence
+    pub fn set_random_seed(&mut self, seed: u64) {
+        self.random_seed = Some(seed);
+        self.apply_random_seed();
+    }
+
+    fn apply_random_seed(&mut self) {
+        use cap_rand::SeedableRng;
+        if let (Some(seed), Some(wasi)) = (self.random_seed, &mut self.memory.store.data_mut().wasi)
+        {
+            wasi.ctx.random = Box::new(cap_rand::rngs::StdRng::seed_from_u64(seed));
+        }
+    }
+
+    /// Pin the WASI clocks to `nanos` since the Unix epoch, later calls move the clocks to a new
+    /// time. This only affects the time seen by the plugin, timeouts still use the host clock
+    pub fn set_clock(&mut self, nanos: u64) {
+        if let Some(clock) = &self.mock_clock {
+            clock.set(nanos);
+            return;
+        }
+
+        if let Some(wasi) = &mut self.memory.store.data_mut().wasi {
+            let clock = std::sync::Arc::new(clocks::MockClock::default());
+            clock.set(nanos);
+            clocks::mock(&mut wasi.ctx, clock.clone());
+            self.mock_clock = Some(clock);
+        }
+    }
+
     /// Set `last_error` field
     pub fn set_error(&self, e: impl std::fmt::Debug) {
-        debug!("Set error: {:?}", e);
+        self.set_error_kind(ErrorKind::Error, e)
+    }
+
+    /// Set `last_error` field along with the kind of error
+    pub fn set_error_kind(&self, kind: ErrorKind, e: impl std::fmt::Debug) {
+        debug!("Set error ({kind:?}): {:?}", e);
         *self.last_error.borrow_mut() = Some(error_string(e));
+        self.last_error_kind.set(kind);
     }
 
     pub fn error<E>(&self, e: impl std::fmt::Debug, x: E) -> E {
@@ -239,9 +1449,47 @@ impl Plugin {
         x
     }
 
+    pub fn error_kind<E>(&self, kind: ErrorKind, e: impl std::fmt::Debug, x: E) -> E {
+        self.set_error_kind(kind, e);
+        x
+    }
+
     /// Unset `last_error` field
     pub fn clear_error(&self) {
         *self.last_error.borrow_mut() = None;
+        *self.last_backtrace.borrow_mut() = None;
+        self.last_error_kind.set(ErrorKind::Error);
+    }
+
+    /// Store the WASM backtrace attached to `e`, if there is one. Backtraces are only captured
+    /// when `wasm_backtrace` isn't disabled in the context config
+    pub(crate) fn set_backtrace(&self, e: &Error) {
+        let bt = match e.downcast_ref::<wasmtime::WasmBacktrace>() {
+            Some(x) => x,
+            None => return,
+        };
+
+        // Skip the "error while executing at wasm backtrace:" header, only the frames are kept
+        let bt = bt.to_string();
+        let frames = bt.split_once('\n').map_or("", |x| x.1);
+        *self.last_backtrace.borrow_mut() = std::ffi::CString::new(frames).ok();
+    }
+
+    /// Get `last_error` as a JSON object containing the `kind` of error, the error `message` and
+    /// the `exit_code` when the plugin exited using WASI, returns `None` if no error is set
+    pub fn last_error_json(&self) -> Option<serde_json::Value> {
+        let err = self.last_error.borrow();
+        let err = err.as_ref()?;
+        let kind = self.last_error_kind.get();
+        let mut obj = serde_json::json!({
+            "kind": kind,
+            "code": kind.code(),
+            "message": err.to_string_lossy(),
+        });
+        if let (ErrorKind::WasiExit, Some(code)) = (kind, self.last_exit_code) {
+            obj["exit_code"] = code.into();
+        }
+        Some(obj)
     }
 
     /// Store input in memory and initialize `Internal` pointer
@@ -251,24 +1499,221 @@ impl Plugin {
         }
         let ptr = self as *mut _;
         let internal = self.memory.store.data_mut();
-        internal.input = input;
+        internal.input = CallPtr(input);
         internal.input_length = len;
-        internal.plugin = ptr;
+        internal.input_offset = None;
+        internal.plugin = CallPtr(ptr);
+    }
+
+    /// Write `segments` to Extism memory, in order, and use them as the input for the next call
+    /// without concatenating them on the host first
+    pub fn set_input_segments<'a>(
+        &mut self,
+        segments: impl Iterator<Item = &'a [u8]> + Clone,
+    ) -> Result<(), Error> {
+        let len = segments.clone().map(|x| x.len()).sum();
+        self.set_input(std::ptr::null(), 0);
+        if len == 0 {
+            return Ok(());
+        }
+
+        let block = self.memory.alloc(len)?;
+        let mut offs = block.offset;
+        for segment in segments {
+            self.memory.write((offs, segment.len()), segment)?;
+            offs += segment.len();
+        }
+
+        let internal = self.memory.store.data_mut();
+        internal.input_offset = Some(block.offset);
+        internal.input_length = len;
+        Ok(())
+    }
+
+    /// Get the output data from the last call
+    pub fn output(&self) -> Result<&[u8], Error> {
+        let data = self.memory.store.data();
+        let end = data.output_offset.saturating_add(data.output_length);
+        match self.memory.data().get(data.output_offset..end) {
+            Some(x) => Ok(x),
+            None => Err(Error::msg(format!(
+                "Invalid output block: {} bytes at offset {}",
+                data.output_length, data.output_offset
+            ))),
+        }
+    }
+
+    /// Check that the output matches the format required by the manifest
+    pub fn validate_output(&self) -> Result<(), Error> {
+        use extism_manifest::OutputFormat;
+        let format = self.manifest.as_ref().output_must_be;
+        if format == OutputFormat::Any {
+            return Ok(());
+        }
+
+        let output = self.output()?;
+        match format {
+            OutputFormat::Any => (),
+            OutputFormat::Utf8 => {
+                if let Err(e) = std::str::from_utf8(output) {
+                    return Err(anyhow::format_err!("Output is not valid UTF-8: {e}"));
+                }
+            }
+            OutputFormat::Json => {
+                if let Err(e) = serde_json::from_slice::<serde::de::IgnoredAny>(output) {
+                    return Err(anyhow::format_err!("Output is not valid JSON: {e}"));
+                }
+            }
+        }
+
+        Ok(())
     }
 
     pub fn dump_memory(&self) {
         self.memory.dump();
     }
 
+    /// Reinstantiate the plugin using the existing module and reset its memory and output
+    pub fn reset(&mut self) -> Result<(), Error> {
+        self.should_reinstantiate = false;
+        self.reinstantiate()?;
+        self.function_handles.clear();
+        self.function_handle_generation = next_function_handle_generation();
+        self.memory.host_blocks.clear();
+        self.memory.reset();
+        let data = self.memory.store.data_mut();
+        data.output_offset = 0;
+        data.output_length = 0;
+        self.memory_high_water = 0;
+        self.update_memory_high_water();
+        Ok(())
+    }
+
+    /// Set the command-line arguments passed to WASI plugins, these are applied when the plugin is
+    /// reinstantiated, which will happen before the next call
+    pub fn set_args(&mut self, args: Vec<String>) {
+        self.args = args;
+        self.should_reinstantiate = true;
+    }
+
+    fn apply_args(&mut self) -> Result<(), Error> {
+        if let Some(wasi) = &mut self.memory.store.data_mut().wasi {
+            // `WasiCtx` doesn't provide a way to clear existing arguments, so they're replaced
+            // with the arguments from a newly built context
+            let mut tmp = wasmtime_wasi::WasiCtxBuilder::new()
+                .args(&self.args)?
+                .build();
+            std::mem::swap(&mut wasi.ctx.args, &mut tmp.args);
+        }
+        Ok(())
+    }
+
+    /// Replace the WASI environment with the plugin's config, this also removes variables for
+    /// keys that are no longer in the config. When `inherit_env` is set the host's environment
+    /// is included, with config values taking precedence. Secret config values are left out, so
+    /// they can only be read using `extism_config_get`
+    pub(crate) fn sync_env(&mut self) -> Result<(), Error> {
+        let mut env = BTreeMap::new();
+        if self.inherit_env {
+            // Variables that aren't valid UTF-8 can't be passed to WASI, so they're skipped
+            for (k, v) in std::env::vars_os() {
+                if let (Ok(k), Ok(v)) = (k.into_string(), v.into_string()) {
+                    env.insert(k, v);
+                }
+            }
+        }
+        for (k, v) in self.manifest.as_ref().public_config() {
+            env.insert(k.clone(), v.clone());
+        }
+
+        if let Some(wasi) = &mut self.memory.store.data_mut().wasi {
+            let mut builder = wasmtime_wasi::WasiCtxBuilder::new();
+            for (k, v) in env.iter() {
+                builder = builder.env(k, v)?;
+            }
+            let mut tmp = builder.build();
+            std::mem::swap(&mut wasi.ctx.env, &mut tmp.env);
+        }
+        Ok(())
+    }
+
     pub fn reinstantiate(&mut self) -> Result<(), Error> {
-        let instance = self
-            .linker
-            .instantiate(&mut self.memory.store, &self.module)?;
+        self.apply_args()?;
+        self.apply_random_seed();
+
+        // Instances can't be removed from a store, so the plugin is moved to a new store instead
+        // of instantiating it again in the same one, which would eventually hit the store's
+        // instance limit. The plugin's state in `Internal` moves to the new store
+        let fuel = if self.memory.store.fuel_consumed().is_some() {
+            Some(self.fuel_remaining())
+        } else {
+            None
+        };
+        let engine = self.memory.store.engine().clone();
+
+        // Limits exceeded by an earlier call don't apply to the new instance
+        let limiter = &mut self.memory.store.data_mut().memory_limiter;
+        limiter.exceeded = false;
+        limiter.table_exceeded = false;
+        let internal = std::mem::replace(
+            self.memory.store.data_mut(),
+            Internal::new(&Manifest::default(), false)?,
+        );
+        let mut store = new_store(&engine, internal, &self.cancel_handle);
+        let (memory, linker, instance, async_fuel) = match self.instantiate(&mut store, fuel) {
+            Ok(x) => x,
+            Err(e) => {
+                // Keep using the existing store
+                *self.memory.store.data_mut() = store.into_data();
+                return Err(e);
+            }
+        };
+        self.memory.store = store;
+        self.memory.memory = memory;
+        self.linker = linker;
         self.instance = instance;
+        self.async_fuel = async_fuel;
+        for handle in self.function_handles.iter_mut() {
+            handle.func = None;
+        }
         self.initialize_runtime()?;
         Ok(())
     }
 
+    // Instantiate the plugin in a store created by `reinstantiate`, `fuel` is the fuel left in
+    // the existing store or `None` if fuel isn't metered
+    #[allow(clippy::type_complexity)]
+    fn instantiate(
+        &self,
+        store: &mut Store<Internal>,
+        fuel: Option<u64>,
+    ) -> Result<(Memory, Linker<Internal>, Instance, Option<AsyncFuel>), Error> {
+        let async_fuel = if self.async_fuel.is_some() {
+            Some(AsyncFuel::set(store, fuel.unwrap_or(u64::MAX))?)
+        } else {
+            if let Some(fuel) = fuel {
+                store.add_fuel(fuel)?;
+            }
+            None
+        };
+
+        // The Extism memory keeps its size, so existing allocations stay valid, and data from
+        // earlier calls is still there unless it's cleared
+        let memory = Memory::new(
+            &mut *store,
+            MemoryType::new(4, self.manifest.as_ref().memory.max_pages),
+        )?;
+        let data = self.memory.data();
+        let pages = (data.len() - memory.data_size(&*store)) / memory::PAGE_SIZE as usize;
+        memory.grow(&mut *store, pages as u64)?;
+        if !self.zero_memory_on_reset {
+            memory.data_mut(&mut *store).copy_from_slice(data);
+        }
+
+        let (linker, instance) = link(store, &self.modules, &self.imports, async_fuel.is_some())?;
+        Ok((memory, linker, instance, async_fuel))
+    }
+
     pub fn has_wasi(&self) -> bool {
         self.memory.store.data().wasi.is_some()
     }
@@ -294,31 +1739,79 @@ impl Plugin {
         None
     }
 
+    /// Returns the `_initialize` export if the plugin is a WASI reactor module. Command modules
+    /// export `_start` instead, which is only called when requested
+    fn reactor_initialize(&mut self) -> Option<Func> {
+        if self.get_func("_start").is_some() {
+            return None;
+        }
+
+        let init = self.get_func("_initialize")?;
+        if init.typed::<(), ()>(&self.memory.store).is_err() {
+            trace!(
+                "_initialize function found with type {:?}",
+                init.ty(&self.memory.store)
+            );
+            return None;
+        }
+        Some(init)
+    }
+
+    /// Returns true if the plugin is a reactor module, `_initialize` is called automatically each
+    /// time a reactor is instantiated
+    pub fn is_reactor(&mut self) -> bool {
+        self.reactor_initialize().is_some()
+    }
+
     fn initialize_runtime(&mut self) -> Result<(), Error> {
-        if let Some(runtime) = self.detect_runtime() {
-            if let Some(timer) = Context::timer().as_ref() {
-                self.memory.store.set_epoch_deadline(1);
-                self.start_timer(&timer.tx)?;
-                let x = runtime.init(self);
-                self.stop_timer(&timer.tx)?;
-                self.memory.store.set_epoch_deadline(0);
-                return x;
+        let reactor = self.reactor_initialize();
+        let runtime = self.detect_runtime();
+        if reactor.is_none() && runtime.is_none() {
+            return Ok(());
+        }
+
+        let tx = match Context::timer().as_ref() {
+            Some(timer) => timer.tx.clone(),
+            None => return Ok(()),
+        };
+
+        let timeout_ms = self.manifest.as_ref().timeout_ms;
+        self.memory.store.set_epoch_deadline(1);
+        self.start_timer(&tx, timeout_ms)?;
+
+        // Reactors are initialized first, language runtimes may depend on the state it sets up
+        let mut x = Ok(());
+        if let Some(init) = reactor {
+            x = executor::block_on(self.call_func(init, &[], &mut []));
+            if x.is_ok() {
+                debug!("Called _initialize for reactor module");
             }
         }
+        if let (Ok(()), Some(runtime)) = (&x, runtime) {
+            x = runtime.init(self);
+        }
 
-        Ok(())
+        self.stop_timer(&tx, timeout_ms)?;
+        self.memory.store.set_epoch_deadline(0);
+        x
     }
 
+    /// Start the timeout for a call, `timeout_ms` is usually the manifest's `timeout_ms` but can
+    /// be overridden for a single call
     pub(crate) fn start_timer(
         &mut self,
         tx: &std::sync::mpsc::SyncSender<TimerAction>,
+        timeout_ms: Option<u64>,
     ) -> Result<(), Error> {
-        if let Some(duration) = self.manifest.as_ref().timeout_ms {
+        self.cancel_handle.reset();
+        self.memory.store.data_mut().timeout_paused = 0;
+        if let Some(duration) = timeout_ms {
             self.memory.store.set_epoch_deadline(1);
             let engine: Engine = self.memory.store.engine().clone();
             tx.send(TimerAction::Start {
                 id: self.timer_id,
                 duration: std::time::Duration::from_millis(duration),
+                interval: self.epoch_interval,
                 engine,
             })?;
         } else {
@@ -328,11 +1821,33 @@ impl Plugin {
         Ok(())
     }
 
+    /// Pause the timeout for the plugin with the given timer ID, the time spent paused
+    /// doesn't count towards the timeout
+    pub(crate) fn pause_timer(
+        tx: &std::sync::mpsc::SyncSender<TimerAction>,
+        id: uuid::Uuid,
+    ) -> Result<(), Error> {
+        tx.send(TimerAction::Pause { id })?;
+        Ok(())
+    }
+
+    /// Resume a timeout paused using `pause_timer`
+    pub(crate) fn resume_timer(
+        tx: &std::sync::mpsc::SyncSender<TimerAction>,
+        id: uuid::Uuid,
+    ) -> Result<(), Error> {
+        tx.send(TimerAction::Resume { id })?;
+        Ok(())
+    }
+
+    /// Stop the timeout started by `start_timer`, `timeout_ms` must be the value that was passed
+    /// to `start_timer`
     pub(crate) fn stop_timer(
         &mut self,
         tx: &std::sync::mpsc::SyncSender<TimerAction>,
+        timeout_ms: Option<u64>,
     ) -> Result<(), Error> {
-        if self.manifest.as_ref().timeout_ms.is_some() {
+        if timeout_ms.is_some() {
             tx.send(TimerAction::Stop { id: self.timer_id })?;
         }
 
@@ -340,6 +1855,64 @@ impl Plugin {
     }
 }
 
+/// Tracks the fuel of a plugin with async support. Only one chunk of `ASYNC_YIELD_FUEL` is
+/// added to the store at a time, the rest is injected by wasmtime each time the plugin runs out
+/// and yields to the executor
+#[derive(Clone, Copy)]
+pub(crate) struct AsyncFuel {
+    // Fuel that hasn't been added to the store yet
+    reserve: u64,
+    // The total amount of fuel added to the store when `reserve` was set
+    added: u64,
+}
+
+impl AsyncFuel {
+    fn set(store: &mut Store<Internal>, fuel: u64) -> Result<AsyncFuel, Error> {
+        let injections = fuel.saturating_sub(1) / ASYNC_YIELD_FUEL;
+        let chunk = fuel - injections * ASYNC_YIELD_FUEL;
+        let current = store.consume_fuel(0).unwrap_or(0);
+        if chunk > current {
+            store.add_fuel(chunk - current)?;
+        } else {
+            store.consume_fuel(current - chunk)?;
+        }
+
+        // The store can be overdrawn by a few units when a call runs out of fuel, in that case
+        // `current` was reported as 0 and the store needs to be topped up again
+        let current = store.consume_fuel(0).unwrap_or(0);
+        if chunk > current {
+            store.add_fuel(chunk - current)?;
+        }
+        store.out_of_fuel_async_yield(injections, ASYNC_YIELD_FUEL);
+
+        Ok(AsyncFuel {
+            reserve: injections * ASYNC_YIELD_FUEL,
+            added: Self::added(store),
+        })
+    }
+
+    fn remaining(&self, store: &mut Store<Internal>) -> u64 {
+        let injected = Self::added(store).saturating_sub(self.added);
+        let current = store.consume_fuel(0).unwrap_or(0);
+        current.saturating_add(self.reserve.saturating_sub(injected))
+    }
+
+    // Get the total amount of fuel that has been added to `store`
+    fn added(store: &mut Store<Internal>) -> u64 {
+        let consumed = store.fuel_consumed().unwrap_or(0);
+        consumed.saturating_add(store.consume_fuel(0).unwrap_or(0))
+    }
+}
+
+// A small, fast PRNG used to generate deterministic IDs
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e3779b97f4a7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
 // Enumerates the supported PDK language runtimes
 enum Runtime {
     Haskell { init: Func, cleanup: Func },
@@ -350,11 +1923,11 @@ impl Runtime {
         match self {
             Runtime::Haskell { init, cleanup: _ } => {
                 let mut results = vec![Val::null(); init.ty(&plugin.memory.store).results().len()];
-                init.call(
-                    &mut plugin.memory.store,
+                executor::block_on(plugin.call_func(
+                    *init,
                     &[Val::I32(0), Val::I32(0)],
                     results.as_mut_slice(),
-                )?;
+                ))?;
                 info!("Initialized Haskell language runtime");
             }
         }
@@ -368,7 +1941,7 @@ impl Runtime {
             Runtime::Haskell { init: _, cleanup } => {
                 let mut results =
                     vec![Val::null(); cleanup.ty(&plugin.memory.store).results().len()];
-                cleanup.call(&mut plugin.memory.store, &[], results.as_mut_slice())?;
+                executor::block_on(plugin.call_func(*cleanup, &[], results.as_mut_slice()))?;
                 info!("Cleaned up Haskell language runtime");
             }
         }
@@ -380,14 +1953,15 @@ impl Runtime {
 impl Drop for Plugin {
     fn drop(&mut self) {
         if let Some(runtime) = self.detect_runtime() {
+            let timeout_ms = self.manifest.as_ref().timeout_ms;
             self.memory.store.set_epoch_deadline(1);
             if let Some(timer) = Context::timer().as_ref() {
-                if self.start_timer(&timer.tx).is_ok() {
+                if self.start_timer(&timer.tx, timeout_ms).is_ok() {
                     if let Err(e) = runtime.cleanup(self) {
                         error!("Unable to cleanup runtime: {e:?}");
                     }
 
-                    if let Err(e) = self.stop_timer(&timer.tx) {
+                    if let Err(e) = self.stop_timer(&timer.tx, timeout_ms) {
                         error!("Unable to stop timer in Plugin::drop: {e:?}");
                     }
                 }
@@ -5,6 +5,9 @@ pub struct PluginRef<'a> {
     pub id: PluginIndex,
     plugin: &'a mut Plugin,
     pub(crate) epoch_timer_tx: std::sync::mpsc::SyncSender<TimerAction>,
+    call_lock: std::sync::Arc<Lock>,
+    _call_guard: Option<LockGuard>,
+    _executing: Option<ExecutingGuard>,
 }
 
 impl<'a> PluginRef<'a> {
@@ -12,18 +15,65 @@ impl<'a> PluginRef<'a> {
     ///
     /// - Resets memory offsets
     /// - Updates `input` pointer
-    pub fn init(mut self, data: *const u8, data_len: usize) -> Self {
+    /// - Marks the plugin as executing, so reentrant calls fail instead of aliasing it
+    /// - Starts recording metrics for the call, which are updated when the `PluginRef` is dropped
+    pub fn init(&mut self, data: *const u8, data_len: usize) {
         trace!("PluginRef::init: {}", self.id,);
+        self.finish();
+        self._executing = Some(self.call_lock.enter());
+        self.plugin.last_call_duration = std::time::Duration::ZERO;
         self.as_mut().memory.reset();
         self.plugin.set_input(data, data_len);
+    }
+
+    /// Finish the call started by `init`, this records the call's metrics and happens when the
+    /// `PluginRef` is dropped, or before the next call when several calls are made using the same
+    /// `PluginRef`
+    pub(crate) fn finish(&mut self) {
+        if let Some(_executing) = self._executing.take() {
+            let plugin = &mut *self.plugin;
+            let error = plugin
+                .last_error
+                .borrow()
+                .is_some()
+                .then(|| plugin.last_error_kind.get());
+            plugin.metrics.record(plugin.last_call_duration, error);
+
+            // The callback's user data may not be valid after a streaming call returns
+            plugin.memory.store.data_mut().emitter = None;
+            plugin.memory.store.data_mut().meta.clear();
+        }
+    }
 
-        self
+    /// Reinstantiate the plugin if the last call requires it, returns false and sets the plugin
+    /// error if reinstantiating fails
+    pub(crate) fn reinstantiate_if_needed(&mut self) -> bool {
+        // Reinstantiate plugin after calling _start because according to the WASI
+        // applicate ABI _start should be called "at most once":
+        // https://github.com/WebAssembly/WASI/blob/main/legacy/application-abi.md
+        if self.plugin.should_reinstantiate {
+            self.plugin.should_reinstantiate = false;
+            if let Err(e) = self.plugin.reinstantiate() {
+                error!("Failed to reinstantiate: {e:?}");
+                let kind = if self.plugin.memory.store.data().memory_limiter.caused(&e) {
+                    ErrorKind::ResourceLimit
+                } else {
+                    ErrorKind::Error
+                };
+                return self.plugin.error_kind(
+                    kind,
+                    format!("Failed to reinstantiate: {e:?}"),
+                    false,
+                );
+            }
+        }
+        true
     }
 
     /// Create a `PluginRef` from a context
     ///
     /// - Reinstantiates the plugin if `should_reinstantiate` is set to `true` and WASI is enabled
-    pub fn new(ctx: &'a mut Context, plugin_id: PluginIndex, clear_error: bool) -> Option<Self> {
+    pub fn new(ctx: &'a Context, plugin_id: PluginIndex, clear_error: bool) -> Option<Self> {
         trace!("Loading plugin {plugin_id}");
 
         let epoch_timer_tx = ctx.epoch_timer_tx.clone();
@@ -33,34 +83,42 @@ impl<'a> PluginRef<'a> {
             return ctx.error(format!("Plugin does not exist: {plugin_id}"), None);
         }
 
+        // A host function may try to call back into the plugin that's calling it
+        if ctx.plugin_executing(plugin_id) {
+            error!("Plugin {plugin_id} is already executing");
+            return ctx.error("plugin is already executing", None);
+        }
+
         if clear_error {
             trace!("Clearing context error");
-            ctx.error = None;
+            ctx.clear_error();
         }
 
-        // `unwrap` is okay here because we already checked with `ctx.plugin_exists` above
-        let plugin = ctx.plugin(plugin_id).unwrap();
+        // In thread-safe contexts this waits until the plugin isn't being called on another thread
+        let (plugin, call_lock, call_guard) = match ctx.plugin_locked(plugin_id) {
+            Some(x) => (x.plugin, x.call_lock, x.guard),
+            None => {
+                error!("Plugin does not exist: {plugin_id}");
+                return ctx.error(format!("Plugin does not exist: {plugin_id}"), None);
+            }
+        };
         if clear_error {
             trace!("Clearing plugin error: {plugin_id}");
             plugin.clear_error();
         }
 
-        // Reinstantiate plugin after calling _start because according to the WASI
-        // applicate ABI _start should be called "at most once":
-        // https://github.com/WebAssembly/WASI/blob/main/legacy/application-abi.md
-        if plugin.should_reinstantiate {
-            plugin.should_reinstantiate = false;
-            if let Err(e) = plugin.reinstantiate() {
-                error!("Failed to reinstantiate: {e:?}");
-                return plugin.error(format!("Failed to reinstantiate: {e:?}"), None);
-            }
-        }
-
-        Some(PluginRef {
+        let mut plugin = PluginRef {
             id: plugin_id,
             plugin,
             epoch_timer_tx,
-        })
+            call_lock,
+            _call_guard: call_guard,
+            _executing: None,
+        };
+        if !plugin.reinstantiate_if_needed() {
+            return None;
+        }
+        Some(plugin)
     }
 }
 
@@ -79,6 +137,6 @@ impl<'a> AsMut<Plugin> for PluginRef<'a> {
 impl<'a> Drop for PluginRef<'a> {
     fn drop(&mut self) {
         trace!("Dropping plugin {}", self.id);
-        // Cleanup?
+        self.finish();
     }
 }
@@ -0,0 +1,92 @@
+use crate::*;
+
+/// A pool of plugins created from the same WASM, instances are created lazily up to `max_size`
+/// and reused between calls
+pub struct PluginPool {
+    pub(crate) wasm: std::sync::Arc<[u8]>,
+    pub(crate) with_wasi: bool,
+    pub(crate) max_size: usize,
+
+    /// All instances owned by the pool
+    pub(crate) instances: Vec<PluginIndex>,
+
+    /// Instances that aren't checked out
+    pub(crate) idle: Vec<PluginIndex>,
+
+    /// The number of times each checked out instance has been checked out, instances are only
+    /// checked out more than once when every instance is in use
+    pub(crate) checkouts: std::collections::BTreeMap<PluginIndex, usize>,
+
+    /// Number of instances currently being created
+    pub(crate) pending: usize,
+
+    /// Used to pick an instance when all instances are checked out
+    next: usize,
+}
+
+impl PluginPool {
+    pub(crate) fn new(wasm: &[u8], with_wasi: bool, max_size: usize) -> PluginPool {
+        PluginPool {
+            wasm: wasm.into(),
+            with_wasi,
+            max_size: max_size.max(1),
+            instances: Vec::new(),
+            idle: Vec::new(),
+            checkouts: Default::default(),
+            pending: 0,
+            next: 0,
+        }
+    }
+
+    /// Returns true if another instance can be created
+    pub(crate) fn can_grow(&self) -> bool {
+        self.instances.len() + self.pending < self.max_size
+    }
+
+    /// Check out an idle instance
+    pub(crate) fn idle_instance(&mut self) -> Option<PluginIndex> {
+        let id = self.idle.pop()?;
+        self.checkout(id);
+        Some(id)
+    }
+
+    /// Get the next instance to use when all instances are checked out, the instance is shared
+    /// with the other callers that checked it out
+    pub(crate) fn busy_instance(&mut self) -> Option<PluginIndex> {
+        if self.instances.is_empty() {
+            return None;
+        }
+
+        self.next = (self.next + 1) % self.instances.len();
+        let id = self.instances[self.next];
+        self.checkout(id);
+        Some(id)
+    }
+
+    /// Mark an instance as checked out
+    pub(crate) fn checkout(&mut self, plugin: PluginIndex) {
+        *self.checkouts.entry(plugin).or_insert(0) += 1;
+    }
+
+    /// Return an instance to the pool, it becomes idle once every checkout has been returned.
+    /// Instances that aren't checked out are ignored, so returning an instance twice is harmless
+    pub(crate) fn checkin(&mut self, plugin: PluginIndex) {
+        let count = match self.checkouts.get_mut(&plugin) {
+            Some(x) => x,
+            None => return,
+        };
+
+        *count -= 1;
+        if *count == 0 {
+            self.checkouts.remove(&plugin);
+            self.idle.push(plugin);
+        }
+    }
+
+    /// Remove an instance from the pool
+    pub(crate) fn remove(&mut self, plugin: PluginIndex) {
+        self.instances.retain(|x| *x != plugin);
+        self.idle.retain(|x| *x != plugin);
+        self.checkouts.remove(&plugin);
+    }
+}
@@ -0,0 +1,44 @@
+use crate::*;
+
+/// The maximum size of a length-delimited protobuf message, including the length prefix
+pub(crate) const MAX_MESSAGE_SIZE: usize = 64 * 1024 * 1024;
+
+// Decode a base 128 varint, returning the value and the number of bytes read
+fn decode_varint(data: &[u8]) -> Option<(u64, usize)> {
+    let mut value = 0u64;
+    for (i, byte) in data.iter().take(10).enumerate() {
+        value |= ((byte & 0x7f) as u64) << (i * 7);
+        if byte & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+    None
+}
+
+/// Check that `data` is a single, non-empty length-delimited message within `MAX_MESSAGE_SIZE`
+pub fn validate_message(data: &[u8]) -> Result<(), Error> {
+    if data.is_empty() {
+        return Err(Error::msg("Protobuf message is empty"));
+    }
+
+    if data.len() > MAX_MESSAGE_SIZE {
+        return Err(anyhow::format_err!(
+            "Protobuf message is {} bytes, the limit is {MAX_MESSAGE_SIZE}",
+            data.len()
+        ));
+    }
+
+    let (length, prefix) = match decode_varint(data) {
+        Some(x) => x,
+        None => return Err(Error::msg("Invalid protobuf length prefix")),
+    };
+
+    let actual = (data.len() - prefix) as u64;
+    if length != actual {
+        return Err(anyhow::format_err!(
+            "Protobuf length prefix is {length} but the message is {actual} bytes"
+        ));
+    }
+
+    Ok(())
+}
@@ -12,7 +12,91 @@ pub unsafe extern "C" fn extism_context_new() -> *mut Context {
     Box::into_raw(Box::new(Context::new()))
 }
 
-/// Free a context
+/// Create a new context using a JSON object to configure how plugins are created
+///
+/// `json`: a NULL-terminated JSON object, the supported fields are `wasm_simd`,
+/// `wasm_reference_types`, `wasm_bulk_memory` and `wasm_multi_value`, which enable or disable
+/// the matching WASM features. Plugins that use a disabled feature will fail to load.
+/// `async_support` allows the plugins to be called from async Rust code
+/// `wasm_backtrace` enables or disables capturing backtraces when a call fails, they're enabled
+/// by default
+/// `epoch_interval_ms` sets how often plugin timeouts are checked, between 1 and 60000
+/// milliseconds
+/// `max_table_elements`, `max_instances`, `max_memories` and `max_tables` limit the resources
+/// each plugin can use, plugins that exceed them fail to load and calls fail with `resource
+/// limit exceeded` when a table can't grow. Both set the `ResourceLimit` error code when a plugin
+/// is reinstantiated or reset
+/// `consume_fuel` meters the fuel used by every plugin, otherwise only plugins that set `fuel` in
+/// their manifest are metered
+/// Returns `NULL` if the config is invalid
+#[no_mangle]
+pub unsafe extern "C" fn extism_context_new_with_config(json: *const c_char) -> *mut Context {
+    trace!("Creating new Context with config");
+
+    let config = if json.is_null() {
+        ContextConfig::default()
+    } else {
+        match serde_json::from_slice(std::ffi::CStr::from_ptr(json).to_bytes()) {
+            Ok(x) => x,
+            Err(e) => {
+                error!("Invalid context config: {e}");
+                return std::ptr::null_mut();
+            }
+        }
+    };
+
+    match Context::new_with_config(config) {
+        Ok(ctx) => Box::into_raw(Box::new(ctx)),
+        Err(e) => {
+            error!("Invalid context config: {e:?}");
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Create a new context that can be used from multiple threads
+///
+/// Access to the plugin registry is synchronized, and each plugin is locked while it's being
+/// called, so distinct plugins can be called in parallel while calls to the same plugin wait for
+/// each other. The context error is shared between threads, so plugin errors should be preferred
+/// when checking why a call failed
+#[no_mangle]
+pub unsafe extern "C" fn extism_context_new_threadsafe() -> *mut Context {
+    trace!("Creating new thread-safe Context");
+    Box::into_raw(Box::new(Context::new_threadsafe()))
+}
+
+/// Set a callback that will be called at the start and end of every call to
+/// `extism_plugin_call` on a plugin in this context, passing `NULL` removes the observer
+///
+/// The callback receives the plugin ID, the function name, the `ExtismCallPhase`, the return
+/// code of the call (or -1 when the call is starting) and `user_data`. In a thread-safe context
+/// the callback is called on whichever thread is making the call
+#[no_mangle]
+pub unsafe extern "C" fn extism_context_set_call_observer(
+    ctx: *mut Context,
+    callback: CallObserverCallback,
+    user_data: *mut std::ffi::c_void,
+) {
+    trace!("Call to extism_context_set_call_observer");
+    let ctx = &*ctx;
+    ctx.set_call_observer(callback.map(|callback| CallObserver {
+        callback,
+        user_data,
+    }));
+}
+
+/// Set the maximum number of compiled modules cached by a context, the least recently used
+/// module is evicted when the cache is full. Passing `0` disables the cache
+#[no_mangle]
+pub unsafe extern "C" fn extism_context_set_module_cache_size(ctx: *mut Context, size: Size) {
+    trace!("Call to extism_context_set_module_cache_size: {size}");
+    let ctx = &*ctx;
+    ctx.set_module_cache_size(size as usize);
+}
+
+/// Free a context, in thread-safe contexts any calls running on other threads are cancelled and
+/// the plugins are freed once those calls return
 #[no_mangle]
 pub unsafe extern "C" fn extism_context_free(ctx: *mut Context) {
     trace!("Freeing context");
@@ -35,359 +119,3737 @@ pub unsafe extern "C" fn extism_plugin_new(
     with_wasi: bool,
 ) -> PluginIndex {
     trace!("Call to extism_plugin_new with wasm pointer {:?}", wasm);
-    let ctx = &mut *ctx;
+    let ctx = &*ctx;
     let data = std::slice::from_raw_parts(wasm, wasm_size as usize);
     ctx.new_plugin(data, with_wasi)
 }
 
-/// Update a plugin, keeping the existing ID
+/// Create a new plugin from a file, the file is loaded the same way as the `wasm` argument to
+/// `extism_plugin_new`
 ///
-/// Similar to `extism_plugin_new` but takes an `index` argument to specify
-/// which plugin to update
+/// `path`: a NULL-terminated path to a WASM module (wat or wasm) or a JSON encoded manifest
+/// `with_wasi`: enables/disables WASI
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_new_from_file(
+    ctx: *mut Context,
+    path: *const c_char,
+    with_wasi: bool,
+) -> PluginIndex {
+    trace!("Call to extism_plugin_new_from_file");
+    let ctx = &*ctx;
+
+    let path = match std::ffi::CStr::from_ptr(path).to_str() {
+        Ok(x) => x,
+        Err(e) => return ctx.error(e, -1),
+    };
+
+    let data = match std::fs::read(path) {
+        Ok(x) => x,
+        Err(e) => return ctx.error(format!("Unable to read {path}: {e}"), -1),
+    };
+    ctx.new_plugin(data, with_wasi)
+}
+
+/// A union of the value types that can be passed to and returned from host functions
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub union ValUnion {
+    pub i32: i32,
+    pub i64: i64,
+    pub f32: f32,
+    pub f64: f64,
+}
+
+/// A value passed to or returned from a host function
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct ExtismVal {
+    pub t: ValType,
+    pub v: ValUnion,
+}
+
+impl ExtismVal {
+    fn from_val(val: &Val) -> Result<Self, Error> {
+        let (t, v) = match val {
+            Val::I32(x) => (ValType::I32, ValUnion { i32: *x }),
+            Val::I64(x) => (ValType::I64, ValUnion { i64: *x }),
+            Val::F32(x) => (
+                ValType::F32,
+                ValUnion {
+                    f32: f32::from_bits(*x),
+                },
+            ),
+            Val::F64(x) => (
+                ValType::F64,
+                ValUnion {
+                    f64: f64::from_bits(*x),
+                },
+            ),
+            v => {
+                return Err(Error::msg(format!(
+                    "Unsupported host function value: {v:?}"
+                )))
+            }
+        };
+        Ok(ExtismVal { t, v })
+    }
+
+    unsafe fn to_val(self, t: &ValType) -> Val {
+        match t {
+            ValType::I32 => Val::I32(self.v.i32),
+            ValType::I64 => Val::I64(self.v.i64),
+            ValType::F32 => Val::F32(self.v.f32.to_bits()),
+            ValType::F64 => Val::F64(self.v.f64.to_bits()),
+            // Other types are rejected when the function is created
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// The signature of a host function
 ///
-/// Memory for this plugin will be reset upon update
+/// `plugin`: the plugin calling the function, it can be used to access the plugin's memory
+/// `inputs`: the function's arguments
+/// `outputs`: the function's results, these should be set by the callback
+pub type FunctionCallback = Option<
+    unsafe extern "C" fn(
+        plugin: *mut Internal,
+        inputs: *const ExtismVal,
+        n_inputs: Size,
+        outputs: *mut ExtismVal,
+        n_outputs: Size,
+        user_data: *mut std::ffi::c_void,
+    ),
+>;
+
+/// Called to free the `user_data` of a host function once the function is no longer used
+pub type FreeUserDataCallback = Option<unsafe extern "C" fn(user_data: *mut std::ffi::c_void)>;
+
+/// A host function that can be imported by a plugin
+///
+/// `free_user_data` was added after `user_data`, which changes the size of this struct: code
+/// built against an older `extism.h` must be rebuilt and initialize the new field
+#[repr(C)]
+pub struct FunctionDefinition {
+    /// The name of the function
+    pub name: *const c_char,
+    /// The module the function is defined in, `NULL` uses `env`
+    pub namespace: *const c_char,
+    pub params: *const ValType,
+    pub n_params: Size,
+    pub results: *const ValType,
+    pub n_results: Size,
+    pub func: FunctionCallback,
+    /// Passed to `func` each time it is called
+    pub user_data: *mut std::ffi::c_void,
+    /// Called with `user_data` when the plugin is freed, this can be `NULL`. When functions
+    /// passed to the same `extism_plugin_new_with_functions` call share `user_data` it's only
+    /// freed once, sharing `user_data` between plugins is the responsibility of the host
+    pub free_user_data: FreeUserDataCallback,
+}
+
+#[derive(Debug)]
+struct UserData(*mut std::ffi::c_void);
+unsafe impl Send for UserData {}
+unsafe impl Sync for UserData {}
+
+impl UserData {
+    fn get(&self) -> *mut std::ffi::c_void {
+        self.0
+    }
+}
+
+/// The `user_data` of a host function, `free` is called once all of the functions using it have
+/// been dropped
+#[derive(Debug)]
+struct OwnedUserData {
+    ptr: UserData,
+    free: FreeUserDataCallback,
+}
+
+impl Drop for OwnedUserData {
+    fn drop(&mut self) {
+        if let Some(free) = self.free {
+            trace!("Freeing host function user data {:?}", self.ptr);
+            unsafe { free(self.ptr.get()) }
+        }
+    }
+}
+
+impl FunctionDefinition {
+    unsafe fn to_function(
+        &self,
+        user_data: std::sync::Arc<OwnedUserData>,
+    ) -> Result<Function, Error> {
+        if self.name.is_null() {
+            return Err(Error::msg("Host function name is NULL"));
+        }
+
+        let name = std::ffi::CStr::from_ptr(self.name).to_str()?;
+        let types = |ptr: *const ValType, len: Size| -> Result<Vec<ValType>, Error> {
+            if len == 0 {
+                return Ok(vec![]);
+            }
+            if ptr.is_null() {
+                return Err(Error::msg(format!(
+                    "Host function {name} has {len} params or results but the array is NULL"
+                )));
+            }
+            let types = std::slice::from_raw_parts(ptr, len as usize).to_vec();
+            if let Some(t) = types
+                .iter()
+                .find(|t| !matches!(t, ValType::I32 | ValType::I64 | ValType::F32 | ValType::F64))
+            {
+                return Err(Error::msg(format!(
+                    "Host function {name} uses unsupported type {t:?}"
+                )));
+            }
+            Ok(types)
+        };
+        let params = types(self.params, self.n_params)?;
+        let results = types(self.results, self.n_results)?;
+
+        let func = match self.func {
+            Some(f) => f,
+            None => return Err(Error::msg(format!("Host function {name} callback is NULL"))),
+        };
+        let result_types = results.clone();
+        let f = Function::new(name, params, results, move |mut caller, inputs, outputs| {
+            let inputs = inputs
+                .iter()
+                .map(ExtismVal::from_val)
+                .collect::<Result<Vec<_>, _>>()?;
+            let mut c_outputs: Vec<_> = result_types
+                .iter()
+                .map(|t| ExtismVal {
+                    t: *t,
+                    v: ValUnion { i64: 0 },
+                })
+                .collect();
+
+            func(
+                caller.data_mut(),
+                inputs.as_ptr(),
+                inputs.len() as Size,
+                c_outputs.as_mut_ptr(),
+                c_outputs.len() as Size,
+                user_data.ptr.get(),
+            );
+
+            for ((output, val), t) in outputs.iter_mut().zip(c_outputs).zip(&result_types) {
+                *output = val.to_val(t);
+            }
+            Ok(())
+        });
+
+        if self.namespace.is_null() {
+            Ok(f)
+        } else {
+            let namespace = std::ffi::CStr::from_ptr(self.namespace).to_str()?;
+            Ok(f.with_namespace(namespace))
+        }
+    }
+}
+
+/// Create a new plugin with host functions
+///
+/// `wasm`: is a WASM module (wat or wasm) or a JSON encoded manifest
+/// `wasm_size`: the length of the `wasm` parameter
+/// `with_wasi`: enables/disables WASI
+/// `functions`: an array of `n_functions` host functions that the plugin can import
+///
+/// Functions that share `user_data` must use the same `free_user_data` callback (or `NULL`),
+/// otherwise this fails. If this fails every `user_data` with a `free_user_data` callback has
+/// been freed once, using the first callback given for it
 #[no_mangle]
-pub unsafe extern "C" fn extism_plugin_update(
+pub unsafe extern "C" fn extism_plugin_new_with_functions(
     ctx: *mut Context,
-    index: PluginIndex,
     wasm: *const u8,
     wasm_size: Size,
     with_wasi: bool,
-) -> bool {
-    trace!("Call to extism_plugin_update with wasm pointer {:?}", wasm);
-    let ctx = &mut *ctx;
+    functions: *const FunctionDefinition,
+    n_functions: Size,
+) -> PluginIndex {
+    trace!(
+        "Call to extism_plugin_new_with_functions with wasm pointer {:?}",
+        wasm
+    );
+    let ctx = &*ctx;
+
+    // Nothing has been passed in that could be freed
+    let defs = if n_functions == 0 {
+        &[]
+    } else if functions.is_null() {
+        return ctx.error("functions is NULL", -1);
+    } else {
+        std::slice::from_raw_parts(functions, n_functions as usize)
+    };
+
+    // Functions that share `user_data` share ownership of it, so it's only freed once. Every
+    // error after this point drops the owners, which frees `user_data`
+    let mut owners: Vec<std::sync::Arc<OwnedUserData>> = Vec::with_capacity(defs.len());
+    for def in defs {
+        let shared = owners
+            .iter()
+            .find(|x| x.ptr.get() == def.user_data)
+            .cloned();
+        owners.push(shared.unwrap_or_else(|| {
+            let free = defs
+                .iter()
+                .filter(|x| x.user_data == def.user_data)
+                .find_map(|x| x.free_user_data);
+            std::sync::Arc::new(OwnedUserData {
+                ptr: UserData(def.user_data),
+                free,
+            })
+        }));
+    }
+
+    if wasm.is_null() {
+        return ctx.error("wasm is NULL", -1);
+    }
+
+    for (i, a) in defs.iter().enumerate() {
+        let conflict = defs[..i]
+            .iter()
+            .any(|b| match (a.free_user_data, b.free_user_data) {
+                (Some(x), Some(y)) => b.user_data == a.user_data && x as usize != y as usize,
+                _ => false,
+            });
+        if conflict {
+            return ctx.error(
+                "Host functions that share user_data have different free_user_data callbacks",
+                -1,
+            );
+        }
+    }
+
+    let mut imports = vec![];
+    for (def, user_data) in defs.iter().zip(owners) {
+        match def.to_function(user_data) {
+            Ok(f) => imports.push(f),
+            Err(e) => return ctx.error(e, -1),
+        }
+    }
 
     let data = std::slice::from_raw_parts(wasm, wasm_size as usize);
-    let plugin = match Plugin::new(data, with_wasi) {
-        Ok(x) => x,
+    ctx.new_plugin_with_functions(data, imports, with_wasi)
+}
+
+/// Get a pointer to the start of the memory of the plugin calling a host function, offsets
+/// passed to host functions are relative to this pointer
+#[no_mangle]
+pub unsafe extern "C" fn extism_current_plugin_memory(plugin: *mut Internal) -> *mut u8 {
+    if plugin.is_null() {
+        return std::ptr::null_mut();
+    }
+
+    let plugin = &mut *plugin;
+    plugin
+        .memory()
+        .ptr(MemoryBlock::new(0, 0))
+        .unwrap_or(std::ptr::null_mut())
+}
+
+/// Allocate a block of memory in the plugin calling a host function
+///
+/// Returns the offset of the new block, or 0 if the allocation failed
+#[no_mangle]
+pub unsafe extern "C" fn extism_current_plugin_memory_alloc(plugin: *mut Internal, n: Size) -> u64 {
+    if plugin.is_null() {
+        return 0;
+    }
+
+    let plugin = &mut *plugin;
+    match plugin.memory_mut().alloc(n as usize) {
+        Ok(x) => x.offset as u64,
         Err(e) => {
-            error!("Error creating Plugin: {:?}", e);
-            ctx.set_error(e);
-            return false;
+            error!("Unable to allocate memory in host function: {e:?}");
+            0
         }
-    };
-
-    if !ctx.plugins.contains_key(&index) {
-        ctx.set_error("Plugin index does not exist");
-        return false;
     }
+}
 
-    ctx.plugins.insert(index, plugin);
+/// Get the length of a block of memory allocated in the plugin calling a host function
+#[no_mangle]
+pub unsafe extern "C" fn extism_current_plugin_memory_length(
+    plugin: *mut Internal,
+    offset: u64,
+) -> Size {
+    if plugin.is_null() {
+        return 0;
+    }
 
-    info!("Plugin updated: {index}");
-    true
+    let plugin = &mut *plugin;
+    plugin.memory().block_length(offset as usize).unwrap_or(0) as Size
 }
 
-/// Remove a plugin from the registry and free associated memory
+/// Free a block of memory allocated in the plugin calling a host function
 #[no_mangle]
-pub unsafe extern "C" fn extism_plugin_free(ctx: *mut Context, plugin: PluginIndex) {
-    if plugin < 0 || ctx.is_null() {
+pub unsafe extern "C" fn extism_current_plugin_memory_free(plugin: *mut Internal, offset: u64) {
+    if plugin.is_null() {
         return;
     }
 
-    trace!("Freeing plugin {plugin}");
+    let plugin = &mut *plugin;
+    plugin.memory_mut().free(offset as usize);
+}
 
-    let ctx = &mut *ctx;
-    ctx.remove(plugin);
+/// Stop time from counting towards the timeout of the plugin calling a host function until
+/// `extism_resume_timeout` is called. Host functions are already called with the timeout paused,
+/// pauses can be nested and the timeout only resumes once each one has been resumed
+#[no_mangle]
+pub unsafe extern "C" fn extism_pause_timeout(plugin: *mut Internal) -> bool {
+    if plugin.is_null() {
+        return false;
+    }
+
+    let plugin = &mut *plugin;
+    match plugin.pause_timeout() {
+        Ok(()) => true,
+        Err(e) => {
+            error!("Unable to pause timeout: {e:?}");
+            false
+        }
+    }
 }
 
-/// Remove all plugins from the registry
+/// Resume a timeout paused using `extism_pause_timeout`
 #[no_mangle]
-pub unsafe extern "C" fn extism_context_reset(ctx: *mut Context) {
-    let ctx = &mut *ctx;
+pub unsafe extern "C" fn extism_resume_timeout(plugin: *mut Internal) -> bool {
+    if plugin.is_null() {
+        return false;
+    }
+
+    let plugin = &mut *plugin;
+    match plugin.resume_timeout() {
+        Ok(()) => true,
+        Err(e) => {
+            error!("Unable to resume timeout: {e:?}");
+            false
+        }
+    }
+}
 
+/// Create a new plugin using a JSON object to specify creation options
+///
+/// `wasm`: is a WASM module (wat or wasm) or a JSON encoded manifest
+/// `wasm_size`: the length of the `wasm` parameter
+/// `opts_json`: a NULL-terminated JSON object, the supported fields are `wasi`, `timeout_ms`,
+/// `id_sequence`, `lock_env`, `rollback_on_error`, `zero_memory_on_reset` and `inherit_env`.
+/// Passing `NULL` uses the default options, WASI plugins only see environment variables from
+/// their config unless `inherit_env` is set
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_new_opts(
+    ctx: *mut Context,
+    wasm: *const u8,
+    wasm_size: Size,
+    opts_json: *const c_char,
+) -> PluginIndex {
     trace!(
-        "Resetting context, plugins cleared: {:?}",
-        ctx.plugins.keys().collect::<Vec<&i32>>()
+        "Call to extism_plugin_new_opts with wasm pointer {:?}",
+        wasm
     );
+    let ctx = &*ctx;
+
+    let options = if opts_json.is_null() {
+        PluginOptions::default()
+    } else {
+        let json = std::ffi::CStr::from_ptr(opts_json);
+        match serde_json::from_slice(json.to_bytes()) {
+            Ok(x) => x,
+            Err(e) => return ctx.error(format!("Invalid plugin options: {e}"), -1),
+        }
+    };
+
+    let data = std::slice::from_raw_parts(wasm, wasm_size as usize);
+    ctx.new_plugin_with_options(data, &options)
+}
 
-    ctx.plugins.clear();
+thread_local! {
+    static MANIFEST_VALIDATE_ERROR: std::cell::RefCell<Option<std::ffi::CString>> =
+        const { std::cell::RefCell::new(None) };
 }
 
-/// Update plugin config values, this will merge with the existing values
+/// Check that a JSON manifest is valid without creating a plugin, this parses the manifest and
+/// validates any WASM modules it references. URLs are checked but not fetched
+///
+/// `err_out`: if validation fails, receives a NUL-terminated error message, which is valid until
+/// the next call to `extism_manifest_validate` on the same thread. This may be `NULL`
 #[no_mangle]
-pub unsafe extern "C" fn extism_plugin_config(
-    ctx: *mut Context,
-    plugin: PluginIndex,
+pub unsafe extern "C" fn extism_manifest_validate(
     json: *const u8,
     json_size: Size,
+    err_out: *mut *const c_char,
 ) -> bool {
-    let ctx = &mut *ctx;
-    let mut plugin = match PluginRef::new(ctx, plugin, true) {
-        None => return false,
-        Some(p) => p,
-    };
-
-    trace!(
-        "Call to extism_plugin_config for {} with json pointer {:?}",
-        plugin.id,
-        json
-    );
+    trace!("Call to extism_manifest_validate");
 
     let data = std::slice::from_raw_parts(json, json_size as usize);
-    let json: std::collections::BTreeMap<String, Option<String>> =
-        match serde_json::from_slice(data) {
-            Ok(x) => x,
-            Err(e) => {
-                return plugin.as_mut().error(e, false);
-            }
-        };
-
-    let plugin = plugin.as_mut();
+    let err = match Manifest::validate(data) {
+        Ok(()) => None,
+        Err(e) => Some(format!("{e:#}")),
+    };
 
-    let wasi = &mut plugin.memory.store.data_mut().wasi;
-    let config = &mut plugin.manifest.as_mut().config;
-    for (k, v) in json.into_iter() {
-        match v {
-            Some(v) => {
-                trace!("Config, adding {k}");
-                if let Some(Wasi { ctx, .. }) = wasi {
-                    let _ = ctx.push_env(&k, &v);
-                }
-                config.insert(k, v);
-            }
-            None => {
-                trace!("Config, removing {k}");
-                if let Some(Wasi { ctx, .. }) = wasi {
-                    let _ = ctx.push_env(&k, "");
-                }
-                config.remove(&k);
-            }
+    MANIFEST_VALIDATE_ERROR.with(|cell| {
+        let mut cell = cell.borrow_mut();
+        *cell = err.map(|e| error_string(e.as_str()));
+        if !err_out.is_null() {
+            *err_out = match cell.as_ref() {
+                Some(e) => e.as_ptr(),
+                None => std::ptr::null(),
+            };
         }
+        cell.is_none()
+    })
+}
+
+/// Create a new plugin from data serialized using `extism_plugin_serialize`, this skips
+/// compilation
+///
+/// The serialized data is tied to the version of Extism and the target it was created on,
+/// loading it anywhere else will fail with an error
+///
+/// `data` contains native code that is loaded without being verified, it must come from a
+/// trusted source. Loading untrusted data can run arbitrary code in the host process
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_new_precompiled(
+    ctx: *mut Context,
+    data: *const u8,
+    data_size: Size,
+    with_wasi: bool,
+) -> PluginIndex {
+    trace!(
+        "Call to extism_plugin_new_precompiled with data pointer {:?}",
+        data
+    );
+    let ctx = &*ctx;
+    if data.is_null() {
+        return ctx.error("data is NULL", -1);
     }
 
-    true
+    let data = std::slice::from_raw_parts(data, data_size as usize);
+    ctx.new_plugin_precompiled(data, with_wasi)
 }
 
-/// Returns true if `func_name` exists
+/// Serialize a plugin's compiled modules and manifest so the plugin can be loaded using
+/// `extism_plugin_new_precompiled`, secret config values are left out
+///
+/// `out`: receives a pointer to the serialized plugin, which is valid until the next call to
+/// `extism_plugin_serialize` or until the plugin is freed
+/// `out_len`: receives the length of the serialized plugin
 #[no_mangle]
-pub unsafe extern "C" fn extism_plugin_function_exists(
+pub unsafe extern "C" fn extism_plugin_serialize(
     ctx: *mut Context,
     plugin: PluginIndex,
-    func_name: *const c_char,
+    out: *mut *const u8,
+    out_len: *mut Size,
 ) -> bool {
-    let ctx = &mut *ctx;
+    trace!("Call to extism_plugin_serialize for plugin {plugin}");
+
+    let ctx = &*ctx;
     let mut plugin = match PluginRef::new(ctx, plugin, true) {
         None => return false,
         Some(p) => p,
     };
 
-    let name = std::ffi::CStr::from_ptr(func_name);
-    trace!("Call to extism_plugin_function_exists for: {:?}", name);
+    let data = match plugin.as_ref().serialize() {
+        Ok(x) => x,
+        Err(e) => return plugin.as_ref().error(e, false),
+    };
+
+    let data = plugin.as_mut().serialized.insert(data);
+    if !out.is_null() {
+        *out = data.as_ptr();
+    }
+    if !out_len.is_null() {
+        *out_len = data.len() as Size;
+    }
+    true
+}
+
+/// Update a plugin, keeping the existing ID
+///
+/// Similar to `extism_plugin_new` but takes an `index` argument to specify
+/// which plugin to update
+///
+/// Memory for this plugin will be reset upon update
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_update(
+    ctx: *mut Context,
+    index: PluginIndex,
+    wasm: *const u8,
+    wasm_size: Size,
+    with_wasi: bool,
+) -> bool {
+    trace!("Call to extism_plugin_update with wasm pointer {:?}", wasm);
+    plugin_update(&*ctx, index, wasm, wasm_size, with_wasi, false)
+}
+
+/// Update a plugin, keeping the existing ID
+///
+/// Similar to `extism_plugin_update` but takes a `bypass_cache` argument, when it's set the
+/// module is always compiled again, even if a module with the same WASM is in the context's
+/// module cache, and the result isn't added to the cache
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_update_with_cache(
+    ctx: *mut Context,
+    index: PluginIndex,
+    wasm: *const u8,
+    wasm_size: Size,
+    with_wasi: bool,
+    bypass_cache: bool,
+) -> bool {
+    trace!(
+        "Call to extism_plugin_update_with_cache with wasm pointer {:?}, bypass_cache: {}",
+        wasm,
+        bypass_cache
+    );
+    plugin_update(&*ctx, index, wasm, wasm_size, with_wasi, bypass_cache)
+}
+
+unsafe fn plugin_update(
+    ctx: &Context,
+    index: PluginIndex,
+    wasm: *const u8,
+    wasm_size: Size,
+    with_wasi: bool,
+    bypass_cache: bool,
+) -> bool {
+    let old_hashes = match ctx.plugin_locked(index) {
+        Some(p) => p.plugin.module_hashes.clone(),
+        None => {
+            ctx.set_error("Plugin index does not exist");
+            return false;
+        }
+    };
+
+    let data = std::slice::from_raw_parts(wasm, wasm_size as usize);
+    let plugin = if bypass_cache {
+        Plugin::create(data, [], with_wasi, None, &ctx.config)
+    } else {
+        Plugin::create(
+            data,
+            [],
+            with_wasi,
+            Some(&mut ctx.module_cache()),
+            &ctx.config,
+        )
+    };
+    let plugin = match plugin {
+        Ok(x) => x,
+        Err(e) => {
+            error!("Error creating Plugin: {:?}", e);
+            ctx.set_error(e);
+            return false;
+        }
+    };
+
+    let new_hashes = plugin.module_hashes.clone();
+    if !ctx.replace(index, plugin) {
+        ctx.set_error("Plugin index does not exist");
+        return false;
+    }
+
+    // The modules that were replaced shouldn't be loaded from the cache again, this only happens
+    // once the update has succeeded so a failed update leaves the cache unchanged
+    for hash in old_hashes.iter().filter(|x| !new_hashes.contains(x)) {
+        ctx.module_cache().remove(hash);
+    }
+
+    info!("Plugin updated: {index}");
+    true
+}
+
+/// Reset a plugin to a fresh state without recompiling it, this reinstantiates the module and
+/// clears the plugin's memory and output. The contents of the memory are only overwritten if
+/// enabled using `extism_plugin_set_zero_memory`
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_reset(ctx: *mut Context, plugin: PluginIndex) -> bool {
+    trace!("Call to extism_plugin_reset for plugin {plugin}");
+
+    let ctx = &*ctx;
+    let mut plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return false,
+        Some(p) => p,
+    };
+
+    match plugin.as_mut().reset() {
+        Ok(()) => true,
+        Err(e)
+            if plugin
+                .as_ref()
+                .memory
+                .store
+                .data()
+                .memory_limiter
+                .caused(&e) =>
+        {
+            plugin
+                .as_ref()
+                .error_kind(ErrorKind::ResourceLimit, e, false)
+        }
+        Err(e) => plugin.as_ref().error(e, false),
+    }
+}
+
+/// Get the time spent running the plugin's most recent call in nanoseconds, this only includes
+/// the time spent executing WASM and host functions. Returns 0 if the plugin hasn't been called
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_last_call_duration_ns(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+) -> u64 {
+    trace!("Call to extism_plugin_last_call_duration_ns for plugin {plugin}");
+
+    let ctx = &*ctx;
+    let plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return 0,
+        Some(p) => p,
+    };
+
+    plugin.as_ref().last_call_duration.as_nanos() as u64
+}
+
+/// Get the WASM backtrace from the plugin's last failed call, formatted with one frame per line
+///
+/// Returns NULL if the last call didn't fail with a backtrace, or backtraces are disabled using
+/// the `wasm_backtrace` context config. The returned string is valid until the plugin is called
+/// again
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_last_backtrace(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+) -> *const c_char {
+    trace!("Call to extism_plugin_last_backtrace for plugin {plugin}");
+
+    let ctx = &*ctx;
+    let plugin = match PluginRef::new(ctx, plugin, false) {
+        None => return std::ptr::null(),
+        Some(p) => p,
+    };
+
+    let bt = plugin.as_ref().last_backtrace.borrow();
+    match bt.as_ref() {
+        Some(s) => s.as_ptr(),
+        None => std::ptr::null(),
+    }
+}
+
+/// Get the plugin's call metrics as a JSON object with the fields:
+/// - `calls`: the number of calls
+/// - `errors`: the number of calls that failed
+/// - `timeouts`: the number of calls that timed out
+/// - `execution_time_ns`: the total time spent running calls in nanoseconds
+///
+/// The returned string is valid until the next call to this function for the same plugin
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_metrics_json(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+) -> *const c_char {
+    trace!("Call to extism_plugin_metrics_json for plugin {plugin}");
+
+    let ctx = &*ctx;
+    let plugin = match PluginRef::new(ctx, plugin, false) {
+        None => return std::ptr::null(),
+        Some(p) => p,
+    };
+
+    let plugin = plugin.as_ref();
+    let json = match serde_json::to_string(&plugin.metrics) {
+        Ok(x) => x,
+        Err(e) => return plugin.error(e, std::ptr::null()),
+    };
+    // Serialized JSON never contains NUL bytes, they're escaped
+    let s = std::ffi::CString::new(json).unwrap_or_default();
+    let mut dest = plugin.metrics_json.borrow_mut();
+    *dest = Some(s);
+    match dest.as_ref() {
+        Some(s) => s.as_ptr(),
+        None => std::ptr::null(),
+    }
+}
+
+/// Set all of the plugin's call metrics to zero
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_metrics_reset(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+) -> bool {
+    trace!("Call to extism_plugin_metrics_reset for plugin {plugin}");
+
+    let ctx = &*ctx;
+    let mut plugin = match PluginRef::new(ctx, plugin, false) {
+        None => return false,
+        Some(p) => p,
+    };
+
+    plugin.as_mut().metrics = Metrics::default();
+    true
+}
+
+/// Allocate `size` bytes in a plugin's memory, the offset can be passed to the plugin as a
+/// function argument. The block stays allocated between calls until it's released using
+/// `extism_plugin_free_block` or the plugin is reset
+///
+/// Returns the offset of the block, or 0 if the memory couldn't be allocated
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_alloc(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+    size: Size,
+) -> Size {
+    trace!("Call to extism_plugin_alloc for plugin {plugin}: {size}");
+
+    let ctx = &*ctx;
+    let mut plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return 0,
+        Some(p) => p,
+    };
+
+    match plugin.as_mut().memory.alloc_host(size as usize) {
+        Ok(block) => block.offset as Size,
+        Err(e) => plugin.as_ref().error(e, 0),
+    }
+}
+
+/// Copy `data` into a block allocated using `extism_plugin_alloc`
+///
+/// `offset`: the offset of the block
+/// `data_len`: the number of bytes to write, this can't be larger than the block
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_write(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+    offset: Size,
+    data: *const u8,
+    data_len: Size,
+) -> bool {
+    trace!("Call to extism_plugin_write for plugin {plugin}: {data_len} bytes at {offset}");
+
+    let ctx = &*ctx;
+    let mut plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return false,
+        Some(p) => p,
+    };
+
+    let memory = &mut plugin.as_mut().memory;
+    let block = match memory.host_blocks.get(&(offset as usize)) {
+        Some(&length) => MemoryBlock {
+            offset: offset as usize,
+            length,
+        },
+        None => {
+            return plugin
+                .as_ref()
+                .error(format!("Invalid memory block: {offset}"), false)
+        }
+    };
+
+    if data_len as usize > block.length {
+        return plugin.as_ref().error(
+            format!(
+                "Unable to write {data_len} bytes to a block of {} bytes",
+                block.length
+            ),
+            false,
+        );
+    }
+
+    let data = if data.is_null() {
+        &[]
+    } else {
+        std::slice::from_raw_parts(data, data_len as usize)
+    };
+    match memory.write(block, data) {
+        Ok(()) => true,
+        Err(e) => plugin.as_ref().error(e, false),
+    }
+}
+
+/// Free a block allocated using `extism_plugin_alloc`, returns false if there is no block at
+/// `offset`
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_free_block(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+    offset: Size,
+) -> bool {
+    trace!("Call to extism_plugin_free_block for plugin {plugin}: {offset}");
+
+    let ctx = &*ctx;
+    let mut plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return false,
+        Some(p) => p,
+    };
+
+    if !plugin.as_mut().memory.free_host(offset as usize) {
+        return plugin
+            .as_ref()
+            .error(format!("Invalid memory block: {offset}"), false);
+    }
+    true
+}
+
+/// Get the number of bytes currently used by a plugin's memory, this includes the Extism memory
+/// and all memories exported by the plugin
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_memory_used(ctx: *mut Context, plugin: PluginIndex) -> Size {
+    trace!("Call to extism_plugin_memory_used for plugin {plugin}");
+
+    let ctx = &*ctx;
+    let mut plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return 0,
+        Some(p) => p,
+    };
+
+    plugin.as_mut().update_memory_high_water() as Size
+}
+
+/// Get the largest number of bytes used by a plugin's memory since it was created or last reset
+/// using `extism_plugin_reset`
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_memory_high_water(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+) -> Size {
+    trace!("Call to extism_plugin_memory_high_water for plugin {plugin}");
+
+    let ctx = &*ctx;
+    let mut plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return 0,
+        Some(p) => p,
+    };
+
+    let plugin = plugin.as_mut();
+    plugin.update_memory_high_water();
+    plugin.memory_high_water as Size
+}
+
+/// Remove a plugin from the registry and free associated memory
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_free(ctx: *mut Context, plugin: PluginIndex) {
+    if plugin < 0 || ctx.is_null() {
+        return;
+    }
+
+    trace!("Freeing plugin {plugin}");
+
+    let ctx = &*ctx;
+    ctx.remove(plugin);
+}
+
+/// Create a pool of plugins that can be used to call the same WASM without waiting for other
+/// calls to finish, instances are created when they're needed up to `pool_size` and reused
+/// between calls
+///
+/// `wasm`: is a WASM module (wat or wasm) or a JSON encoded manifest
+/// `wasm_size`: the length of the `wasm` parameter
+/// `with_wasi`: enables/disables WASI
+/// Returns the pool ID, or -1 if the first instance couldn't be created
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_pool_new(
+    ctx: *mut Context,
+    wasm: *const u8,
+    wasm_size: Size,
+    with_wasi: bool,
+    pool_size: Size,
+) -> PluginIndex {
+    trace!(
+        "Call to extism_plugin_pool_new with wasm pointer {:?}",
+        wasm
+    );
+    let ctx = &*ctx;
+    let data = std::slice::from_raw_parts(wasm, wasm_size as usize);
+    ctx.new_pool(data, with_wasi, pool_size as usize)
+}
+
+/// Check out an idle plugin from a pool, the plugin can be used with any `extism_plugin_*`
+/// function and should be returned using `extism_plugin_pool_checkin`
+///
+/// Returns the plugin ID, or -1 if no plugin could be checked out
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_pool_checkout(
+    ctx: *mut Context,
+    pool: PluginIndex,
+) -> PluginIndex {
+    trace!("Call to extism_plugin_pool_checkout for pool {pool}");
+    let ctx = &*ctx;
+    match ctx.pool_checkout(pool) {
+        Ok(x) => x,
+        Err(e) => ctx.error(e, -1),
+    }
+}
+
+/// Return a plugin to a pool after calling `extism_plugin_pool_checkout`
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_pool_checkin(
+    ctx: *mut Context,
+    pool: PluginIndex,
+    plugin: PluginIndex,
+) {
+    trace!("Call to extism_plugin_pool_checkin for pool {pool}");
+    let ctx = &*ctx;
+    ctx.pool_checkin(pool, plugin);
+}
+
+/// Call a function using a plugin from a pool, the plugin is checked out for the duration of the
+/// call
+///
+/// `plugin_out`: receives the ID of the plugin that was called, which can be used to read the
+/// output or error. The plugin is returned to the pool when the call finishes, so when the
+/// context is shared between threads `extism_plugin_pool_checkout` should be used instead to
+/// make sure the output isn't replaced by another call
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_pool_call(
+    ctx: *mut Context,
+    pool: PluginIndex,
+    func_name: *const c_char,
+    data: *const u8,
+    data_len: Size,
+    plugin_out: *mut PluginIndex,
+) -> i32 {
+    trace!("Call to extism_plugin_pool_call for pool {pool}");
+    let plugin = extism_plugin_pool_checkout(ctx, pool);
+    if !plugin_out.is_null() {
+        *plugin_out = plugin;
+    }
+    if plugin < 0 {
+        return -1;
+    }
+
+    let rc = extism_plugin_call(ctx, plugin, func_name, data, data_len);
+    extism_plugin_pool_checkin(ctx, pool, plugin);
+    rc
+}
+
+/// Remove a pool and all of its plugins
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_pool_free(ctx: *mut Context, pool: PluginIndex) {
+    if pool < 0 || ctx.is_null() {
+        return;
+    }
+
+    trace!("Freeing pool {pool}");
+
+    let ctx = &*ctx;
+    ctx.remove_pool(pool);
+}
+
+/// Remove all plugins from the registry
+#[no_mangle]
+pub unsafe extern "C" fn extism_context_reset(ctx: *mut Context) {
+    let ctx = &*ctx;
+
+    trace!("Resetting context, plugins cleared: {:?}", ctx.plugin_ids());
+
+    ctx.clear();
+}
+
+unsafe fn copy_plugin_ids(ids: &[PluginIndex], out: *mut PluginIndex, out_len: Size) -> Size {
+    if !out.is_null() {
+        let n = ids.len().min(out_len as usize);
+        std::ptr::copy_nonoverlapping(ids.as_ptr(), out, n);
+    }
+    ids.len() as Size
+}
+
+/// Get the number of plugins in a context
+#[no_mangle]
+pub unsafe extern "C" fn extism_context_plugin_count(ctx: *mut Context) -> Size {
+    trace!("Call to extism_context_plugin_count");
+    let ctx = &*ctx;
+    ctx.plugin_ids().len() as Size
+}
+
+/// Get the IDs of all plugins in a context
+///
+/// `out`: a buffer that receives up to `out_len` plugin IDs
+/// Returns the total number of plugins, which may be larger than `out_len`
+#[no_mangle]
+pub unsafe extern "C" fn extism_context_plugin_ids(
+    ctx: *mut Context,
+    out: *mut PluginIndex,
+    out_len: Size,
+) -> Size {
+    trace!("Call to extism_context_plugin_ids");
+    let ctx = &*ctx;
+    let ids = ctx.plugin_ids();
+    copy_plugin_ids(&ids, out, out_len)
+}
+
+/// Get the IDs of all plugins in a context, sorted by memory usage with the largest first
+///
+/// `out`: a buffer that receives up to `out_len` plugin IDs
+/// Returns the total number of plugins, which may be larger than `out_len`
+#[no_mangle]
+pub unsafe extern "C" fn extism_context_plugins_by_memory(
+    ctx: *mut Context,
+    out: *mut PluginIndex,
+    out_len: Size,
+) -> Size {
+    trace!("Call to extism_context_plugins_by_memory");
+    let ctx = &*ctx;
+    let ids = ctx.plugins_by_memory();
+    copy_plugin_ids(&ids, out, out_len)
+}
+
+/// Get the IDs of all plugins in a context, sorted by the time of their last call with the
+/// least recently used first
+///
+/// `out`: a buffer that receives up to `out_len` plugin IDs
+/// Returns the total number of plugins, which may be larger than `out_len`
+#[no_mangle]
+pub unsafe extern "C" fn extism_context_plugins_by_last_used(
+    ctx: *mut Context,
+    out: *mut PluginIndex,
+    out_len: Size,
+) -> Size {
+    trace!("Call to extism_context_plugins_by_last_used");
+    let ctx = &*ctx;
+    let ids = ctx.plugins_by_last_used();
+    copy_plugin_ids(&ids, out, out_len)
+}
+
+/// Update plugin config values, this will merge with the existing values. Keys listed in the
+/// manifest's `secret_config` are never logged or added to the WASI environment
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_config(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+    json: *const u8,
+    json_size: Size,
+) -> bool {
+    let ctx = &*ctx;
+    let mut plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return false,
+        Some(p) => p,
+    };
+
+    trace!(
+        "Call to extism_plugin_config for {} with json pointer {:?}",
+        plugin.id,
+        json
+    );
+
+    let data = std::slice::from_raw_parts(json, json_size as usize);
+    let json: std::collections::BTreeMap<String, Option<String>> =
+        match serde_json::from_slice(data) {
+            Ok(x) => x,
+            Err(e) => {
+                return plugin.as_mut().error(e, false);
+            }
+        };
+
+    let plugin = plugin.as_mut();
+
+    let manifest = plugin.manifest.as_mut();
+    for (k, v) in json.into_iter() {
+        // Secret keys are updated the same way, they're just left out of the logs
+        let secret = manifest.is_secret_config(&k);
+        match v {
+            Some(v) => {
+                if !secret {
+                    trace!("Config, adding {k}");
+                }
+                manifest.config.insert(k, v);
+            }
+            None => {
+                if !secret {
+                    trace!("Config, removing {k}");
+                }
+                manifest.config.remove(&k);
+            }
+        }
+    }
+
+    // Once the environment is locked, config changes are no longer reflected in WASI
+    if !plugin.env_locked {
+        if let Err(e) = plugin.sync_env() {
+            return plugin.error(e, false);
+        }
+    }
+
+    true
+}
+
+/// Make the WASI random source deterministic by replacing it with a PRNG seeded with `seed`, the
+/// PRNG is reseeded whenever the plugin is reinstantiated. Returns false if WASI isn't enabled
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_set_random_seed(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+    seed: u64,
+) -> bool {
+    trace!("Call to extism_plugin_set_random_seed for plugin {plugin}");
+
+    let ctx = &*ctx;
+    let mut plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return false,
+        Some(p) => p,
+    };
+
+    if !plugin.as_ref().has_wasi() {
+        return plugin.as_ref().error("WASI is not enabled", false);
+    }
+
+    plugin.as_mut().set_random_seed(seed);
+    true
+}
+
+/// Set the time reported by the WASI clocks to `nanos` since the Unix epoch, the clocks stay at
+/// this time until it's set again. The monotonic clock never moves backwards, and timeouts are
+/// still measured using the host clock. Returns false if WASI isn't enabled
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_set_clock(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+    nanos: u64,
+) -> bool {
+    trace!("Call to extism_plugin_set_clock for plugin {plugin}: {nanos}");
+
+    let ctx = &*ctx;
+    let mut plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return false,
+        Some(p) => p,
+    };
+
+    if !plugin.as_ref().has_wasi() {
+        return plugin.as_ref().error("WASI is not enabled", false);
+    }
+
+    plugin.as_mut().set_clock(nanos);
+    true
+}
+
+/// Make the IDs returned by the `extism_generate_id` host function deterministic, starting
+/// from `seed`
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_set_id_sequence(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+    seed: u64,
+) -> bool {
+    trace!("Call to extism_plugin_set_id_sequence for plugin {plugin}");
+
+    let ctx = &*ctx;
+    let mut plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return false,
+        Some(p) => p,
+    };
+
+    plugin.as_mut().set_id_sequence(seed);
+    true
+}
+
+/// Stop `extism_plugin_config` from updating the plugin's WASI environment, config values will
+/// still be updated
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_lock_env(ctx: *mut Context, plugin: PluginIndex) -> bool {
+    trace!("Call to extism_plugin_lock_env for plugin {plugin}");
+
+    let ctx = &*ctx;
+    let mut plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return false,
+        Some(p) => p,
+    };
+
+    plugin.as_mut().env_locked = true;
+    true
+}
+
+/// When enabled, the plugin's memory is snapshotted before each call and restored if the call
+/// fails, so a failed call doesn't affect later calls. This makes calls slower because the memory
+/// is copied every time
+///
+/// The rollback isn't transactional: globals that aren't exported, like `__stack_pointer`, and
+/// tables can't be restored, see `extism_plugin_snapshot`
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_set_rollback(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+    enabled: bool,
+) -> bool {
+    trace!("Call to extism_plugin_set_rollback for plugin {plugin}: {enabled}");
+
+    let ctx = &*ctx;
+    let mut plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return false,
+        Some(p) => p,
+    };
+
+    plugin.as_mut().rollback_on_error = enabled;
+    true
+}
+
+/// Enable or disable zeroing the plugin's memory when it's reset or reinstantiated
+///
+/// By default only the allocator is reset, so data from earlier calls stays in memory until it's
+/// overwritten. When enabled, the entire memory is zeroed so one input can't be read while
+/// handling the next. This makes resets slower for plugins with large memories
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_set_zero_memory(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+    enabled: bool,
+) -> bool {
+    trace!("Call to extism_plugin_set_zero_memory for plugin {plugin}: {enabled}");
+
+    let ctx = &*ctx;
+    let mut plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return false,
+        Some(p) => p,
+    };
+
+    plugin.as_mut().zero_memory_on_reset = enabled;
+    true
+}
+
+/// Get a plugin config value, returns false if `key` isn't set
+///
+/// `out`: receives a pointer to the value, which is valid until the plugin's config is updated
+/// `out_len`: receives the length of the value
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_config_get(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+    key: *const c_char,
+    out: *mut *const u8,
+    out_len: *mut Size,
+) -> bool {
+    trace!("Call to extism_plugin_config_get for plugin {plugin}");
+
+    let ctx = &*ctx;
+    let plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return false,
+        Some(p) => p,
+    };
+
+    let key = match std::ffi::CStr::from_ptr(key).to_str() {
+        Ok(x) => x,
+        Err(e) => return plugin.as_ref().error(e, false),
+    };
+
+    let value = match plugin.as_ref().manifest.as_ref().config.get(key) {
+        Some(x) => x,
+        None => return false,
+    };
+
+    if !out.is_null() {
+        *out = value.as_ptr();
+    }
+    if !out_len.is_null() {
+        *out_len = value.len() as Size;
+    }
+    true
+}
+
+/// Set the command-line arguments passed to a WASI plugin, `argv` should contain `argc`
+/// NUL-terminated strings
+///
+/// Changing the arguments requires the plugin to be reinstantiated, this happens automatically
+/// before the next call, so any state in the plugin's memory will be reset
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_set_args(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+    argv: *const *const c_char,
+    argc: Size,
+) -> bool {
+    trace!("Call to extism_plugin_set_args for plugin {plugin}");
+
+    let ctx = &*ctx;
+    let mut plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return false,
+        Some(p) => p,
+    };
+
+    let mut args = Vec::with_capacity(argc as usize);
+    if argc > 0 {
+        for arg in std::slice::from_raw_parts(argv, argc as usize) {
+            if arg.is_null() {
+                return plugin.as_ref().error("Argument is NULL", false);
+            }
+
+            match std::ffi::CStr::from_ptr(*arg).to_str() {
+                Ok(x) => args.push(x.to_string()),
+                Err(e) => return plugin.as_ref().error(e, false),
+            }
+        }
+    }
+
+    plugin.as_mut().set_args(args);
+    true
+}
+
+/// Set a callback that is called when a call to the plugin times out, passing `NULL` removes
+/// the handler
+///
+/// The callback runs on the calling thread before `extism_plugin_call` returns, it receives the
+/// plugin ID, a pointer to the plugin's memory and its length, and `user_data`
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_set_timeout_handler(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+    callback: TimeoutHandlerCallback,
+    user_data: *mut std::ffi::c_void,
+) -> bool {
+    trace!("Call to extism_plugin_set_timeout_handler for plugin {plugin}");
+
+    let ctx = &*ctx;
+    let mut plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return false,
+        Some(p) => p,
+    };
+
+    plugin.as_mut().timeout_handler = callback.map(|callback| TimeoutHandler {
+        callback,
+        user_data,
+    });
+    true
+}
+
+/// Get a handle that can be used to cancel calls to a plugin from another thread, this doesn't
+/// wait for a call that's running. The handle stays valid after the plugin is freed or updated,
+/// but it only cancels calls to the plugin as it was when the handle was created. It must be
+/// released using `extism_plugin_cancel_handle_free`
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_cancel_handle(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+) -> *const CancelHandle {
+    trace!("Call to extism_plugin_cancel_handle for plugin {plugin}");
+
+    let ctx = &*ctx;
+    match ctx.cancel_handle(plugin) {
+        Some(handle) => std::sync::Arc::into_raw(handle),
+        None => ctx.error(format!("Plugin does not exist: {plugin}"), std::ptr::null()),
+    }
+}
+
+/// Free a handle returned by `extism_plugin_cancel_handle`
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_cancel_handle_free(handle: *const CancelHandle) {
+    if handle.is_null() {
+        return;
+    }
+
+    trace!("Freeing cancel handle");
+    drop(std::sync::Arc::from_raw(handle));
+}
+
+/// Cancel the call that is currently running on the plugin associated with `handle`, the call
+/// will fail with the error `cancelled`. This can be called from any thread
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_cancel(handle: *const CancelHandle) -> bool {
+    if handle.is_null() {
+        return false;
+    }
+
+    trace!("Cancelling plugin call");
+    (*handle).cancel();
+    true
+}
+
+/// Limit the size of each of a plugin's memories to `max_pages` 64KiB pages, `0` removes the
+/// limit. Calls fail with `memory limit exceeded` when a memory tries to grow beyond the limit
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_set_memory_limit(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+    max_pages: u32,
+) -> bool {
+    trace!("Call to extism_plugin_set_memory_limit for plugin {plugin}: {max_pages}");
+
+    let ctx = &*ctx;
+    let mut plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return false,
+        Some(p) => p,
+    };
+
+    let max_pages = if max_pages == 0 {
+        None
+    } else {
+        Some(max_pages)
+    };
+    plugin.as_mut().set_memory_limit(max_pages);
+    true
+}
+
+/// Set the amount of fuel available to a plugin, replacing any remaining fuel. Most WASM
+/// instructions consume one unit of fuel and calls fail with `fuel exhausted` once it runs out
+///
+/// This fails unless the plugin's manifest sets `fuel` or the context config enables
+/// `consume_fuel`
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_set_fuel(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+    fuel: u64,
+) -> bool {
+    trace!("Call to extism_plugin_set_fuel for plugin {plugin}: {fuel}");
+
+    let ctx = &*ctx;
+    let mut plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return false,
+        Some(p) => p,
+    };
+
+    match plugin.as_mut().set_fuel(fuel) {
+        Ok(()) => true,
+        Err(e) => plugin.as_ref().error(e, false),
+    }
+}
+
+/// Get the amount of fuel remaining for a plugin, or -1 on error. Plugins that aren't metered
+/// always report `INT64_MAX`
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_fuel_remaining(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+) -> i64 {
+    trace!("Call to extism_plugin_fuel_remaining for plugin {plugin}");
+
+    let ctx = &*ctx;
+    let mut plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return -1,
+        Some(p) => p,
+    };
+
+    plugin.as_mut().fuel_remaining().min(i64::MAX as u64) as i64
+}
+
+/// Returns true if `func_name` exists
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_function_exists(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+    func_name: *const c_char,
+) -> bool {
+    let ctx = &*ctx;
+    let mut plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return false,
+        Some(p) => p,
+    };
+
+    let name = std::ffi::CStr::from_ptr(func_name);
+    trace!("Call to extism_plugin_function_exists for: {:?}", name);
+
+    let name = match name.to_str() {
+        Ok(x) => x,
+        Err(e) => {
+            return plugin.as_mut().error(e, false);
+        }
+    };
+
+    plugin.as_mut().get_func(name).is_some()
+}
+
+/// Get the parameter and result types of the function named `func_name`
+///
+/// `params_out`: a buffer that receives up to `*n_params` parameter types
+/// `n_params`: the length of `params_out`, this is set to the number of parameters
+/// `results_out`: a buffer that receives up to `*n_results` result types
+/// `n_results`: the length of `results_out`, this is set to the number of results
+/// Returns false if the function doesn't exist
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_function_signature(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+    func_name: *const c_char,
+    params_out: *mut ValType,
+    n_params: *mut Size,
+    results_out: *mut ValType,
+    n_results: *mut Size,
+) -> bool {
+    let ctx = &*ctx;
+    let mut plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return false,
+        Some(p) => p,
+    };
+
+    let name = std::ffi::CStr::from_ptr(func_name);
+    trace!("Call to extism_plugin_function_signature for: {:?}", name);
+
+    let name = match name.to_str() {
+        Ok(x) => x,
+        Err(e) => {
+            return plugin.as_mut().error(e, false);
+        }
+    };
+
+    let ty = match plugin.as_mut().get_func(name) {
+        Some(f) => f.ty(&plugin.as_ref().memory.store),
+        None => {
+            return plugin.as_ref().error_kind(
+                ErrorKind::NotFound,
+                format!("Function not found: {name}"),
+                false,
+            )
+        }
+    };
+
+    // Copy as many types as will fit and report the actual count
+    unsafe fn write_types(
+        types: impl ExactSizeIterator<Item = wasmtime::ValType>,
+        out: *mut ValType,
+        n: *mut Size,
+    ) {
+        let count = types.len();
+        if !n.is_null() {
+            if !out.is_null() {
+                for (i, t) in types.take(*n as usize).enumerate() {
+                    *out.add(i) = t.into();
+                }
+            }
+            *n = count as Size;
+        }
+    }
+
+    write_types(ty.params(), params_out, n_params);
+    write_types(ty.results(), results_out, n_results);
+    true
+}
+
+/// Get the number of results returned by the function named `func_name`, this can be used to
+/// size the buffer passed to `extism_plugin_call_multi`
+///
+/// Returns -1 if the function doesn't exist
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_function_result_count(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+    func_name: *const c_char,
+) -> i32 {
+    let ctx = &*ctx;
+    let mut plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return -1,
+        Some(p) => p,
+    };
+
+    let name = std::ffi::CStr::from_ptr(func_name);
+    trace!(
+        "Call to extism_plugin_function_result_count for: {:?}",
+        name
+    );
+
+    let name = match name.to_str() {
+        Ok(x) => x,
+        Err(e) => {
+            return plugin.as_ref().error_kind(ErrorKind::BadUtf8, e, -1);
+        }
+    };
+
+    match plugin.as_mut().get_func(name) {
+        Some(f) => f.ty(&plugin.as_ref().memory.store).results().len() as i32,
+        None => plugin.as_ref().error_kind(
+            ErrorKind::NotFound,
+            format!("Function not found: {name}"),
+            -1,
+        ),
+    }
+}
+
+/// Copy the 16 byte UUID of a plugin into `out`, unlike the plugin index the UUID is never
+/// reused. It stays the same when the config is updated but changes when the plugin is updated
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_id(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+    out: *mut u8,
+) -> bool {
+    trace!("Call to extism_plugin_id for plugin {plugin}");
+
+    let ctx = &*ctx;
+    let plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return false,
+        Some(p) => p,
+    };
+
+    if out.is_null() {
+        return plugin.as_ref().error("Output buffer is NULL", false);
+    }
+
+    let id = plugin.as_ref().id();
+    std::ptr::copy_nonoverlapping(id.as_bytes().as_ptr(), out, 16);
+    true
+}
+
+/// Get the number of functions exported by a plugin
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_function_count(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+) -> Size {
+    trace!("Call to extism_plugin_function_count for plugin {plugin}");
+
+    let ctx = &*ctx;
+    let plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return 0,
+        Some(p) => p,
+    };
+
+    plugin.as_ref().function_count() as Size
+}
+
+/// Call a function
+///
+/// `func_name`: is the function to call
+/// `data`: is the input data
+/// `data_len`: is the length of `data`
+///
+/// Calling a plugin from one of its own host functions fails with the error
+/// `plugin is already executing`
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_call(
+    ctx: *mut Context,
+    plugin_id: PluginIndex,
+    func_name: *const c_char,
+    data: *const u8,
+    data_len: Size,
+) -> i32 {
+    executor::block_on(plugin_call_async(
+        &*ctx, plugin_id, func_name, data, data_len,
+    ))
+}
+
+/// The async version of `extism_plugin_call`, this is only available from Rust. Plugins created
+/// in a context with `async_support` enabled yield to the executor while they run, other plugins
+/// run to completion the first time the returned future is polled
+pub async unsafe fn plugin_call_async(
+    ctx: &Context,
+    plugin_id: PluginIndex,
+    func_name: *const c_char,
+    data: *const u8,
+    data_len: Size,
+) -> i32 {
+    observed_call(
+        ctx,
+        plugin_id,
+        func_name,
+        CallTarget::Name(func_name),
+        CallInput::Bytes(data, data_len),
+        CallMode::Default,
+        CallOptions::default(),
+    )
+    .await
+}
+
+/// Call a function and notify the context's call observer, `func_name` is passed to the observer
+async unsafe fn observed_call(
+    ctx: &Context,
+    plugin_id: PluginIndex,
+    func_name: *const c_char,
+    target: CallTarget,
+    input: CallInput<'_>,
+    mode: CallMode<'_>,
+    options: CallOptions,
+) -> i32 {
+    let observer = ctx.call_observer();
+    if let Some(observer) = observer {
+        observer.notify(plugin_id, func_name, CallPhase::Start, -1);
+    }
+
+    let rc = call_function(ctx, plugin_id, target, input, mode, options).await;
+
+    if let Some(observer) = observer {
+        observer.notify(plugin_id, func_name, CallPhase::End, rc);
+    }
+
+    rc
+}
+
+/// Look up a function so it can be called using `extism_plugin_call_by_handle`, which avoids
+/// looking up the function by name for each call. Handles are invalidated when the plugin is
+/// reset or updated
+///
+/// Returns the handle, or -1 if the function doesn't exist
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_function_resolve(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+    func_name: *const c_char,
+) -> i32 {
+    trace!("Call to extism_plugin_function_resolve for plugin {plugin}");
+
+    let ctx = &*ctx;
+    let mut plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return -1,
+        Some(p) => p,
+    };
+
+    let name = std::ffi::CStr::from_ptr(func_name);
+    let name = match name.to_str() {
+        Ok(name) => name,
+        Err(e) => return plugin.as_ref().error(e, -1),
+    };
+
+    match plugin.as_mut().resolve_function(name) {
+        Some(handle) => handle,
+        None => plugin.as_ref().error_kind(
+            ErrorKind::NotFound,
+            format!("Function not found: {name}"),
+            -1,
+        ),
+    }
+}
+
+/// Call a function using a handle returned by `extism_plugin_function_resolve`, the call fails
+/// if the handle was resolved before the plugin was last reset or updated. The call observer
+/// receives `NULL` as the function name when the handle is invalid
+///
+/// `handle`: the function handle
+/// `data`: is the input data
+/// `data_len`: is the length of `data`
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_call_by_handle(
+    ctx: *mut Context,
+    plugin_id: PluginIndex,
+    handle: i32,
+    data: *const u8,
+    data_len: Size,
+) -> i32 {
+    trace!("Call to extism_plugin_call_by_handle for plugin {plugin_id}");
+
+    let ctx = &*ctx;
+
+    // The function is looked up when the call starts, the name only needs to be found first when
+    // it's passed to the call observer
+    let func_name = match ctx.call_observer() {
+        None => None,
+        Some(_) => match PluginRef::new(ctx, plugin_id, true) {
+            None => return -1,
+            Some(mut p) => p
+                .as_mut()
+                .function_handle(handle)
+                .map(|(func_name, _)| std::ffi::CStr::from_ptr(func_name).to_owned()),
+        },
+    };
+
+    executor::block_on(observed_call(
+        ctx,
+        plugin_id,
+        func_name.as_ref().map_or(std::ptr::null(), |x| x.as_ptr()),
+        CallTarget::Handle(handle),
+        CallInput::Bytes(data, data_len),
+        CallMode::Default,
+        CallOptions::default(),
+    ))
+}
+
+/// Call a function that returns multiple results
+///
+/// `results`: a buffer that receives up to `results_len` of the function's results, all results
+/// must be `i32` or `i64`
+/// Returns the number of results returned by the function, which may be larger than
+/// `results_len`, or -1 on error
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_call_multi(
+    ctx: *mut Context,
+    plugin_id: PluginIndex,
+    func_name: *const c_char,
+    data: *const u8,
+    data_len: Size,
+    results: *mut i64,
+    results_len: Size,
+) -> i64 {
+    trace!("Call to extism_plugin_call_multi for plugin {plugin_id}");
+
+    let ctx = &*ctx;
+
+    if results.is_null() && results_len > 0 {
+        return match PluginRef::new(ctx, plugin_id, true) {
+            None => -1,
+            Some(plugin) => plugin.as_ref().error("results is NULL", -1),
+        };
+    }
+
+    let mut values = vec![];
+    let rc = plugin_call(
+        ctx,
+        plugin_id,
+        func_name,
+        data,
+        data_len,
+        CallMode::Multi(&mut values),
+    );
+
+    if rc != 0 {
+        return -1;
+    }
+
+    if results_len > 0 {
+        let n = values.len().min(results_len as usize);
+        std::ptr::copy_nonoverlapping(values.as_ptr(), results, n);
+    }
+
+    values.len() as i64
+}
+
+/// Get the value of a global exported by a plugin
+///
+/// `name`: the name of the global
+/// `out`: receives the value, only numeric globals are supported
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_get_global(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+    name: *const c_char,
+    out: *mut ExtismVal,
+) -> bool {
+    trace!("Call to extism_plugin_get_global for plugin {plugin}");
+
+    let ctx = &*ctx;
+    let mut plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return false,
+        Some(p) => p,
+    };
+
+    let name = match std::ffi::CStr::from_ptr(name).to_str() {
+        Ok(x) => x,
+        Err(e) => return plugin.as_ref().error_kind(ErrorKind::BadUtf8, e, false),
+    };
+
+    let val = match plugin.as_mut().get_global(name) {
+        Ok(x) => x,
+        Err(e) => return plugin.as_ref().error_kind(ErrorKind::NotFound, e, false),
+    };
+
+    match ExtismVal::from_val(&val) {
+        Ok(x) => {
+            if !out.is_null() {
+                *out = x;
+            }
+            true
+        }
+        Err(e) => plugin.as_ref().error(e, false),
+    }
+}
+
+/// Set the value of a mutable global exported by a plugin, this fails if the global is immutable
+/// or `val` doesn't match the global's type
+///
+/// `name`: the name of the global
+/// `val`: the new value
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_set_global(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+    name: *const c_char,
+    val: ExtismVal,
+) -> bool {
+    trace!("Call to extism_plugin_set_global for plugin {plugin}");
+
+    let ctx = &*ctx;
+    let mut plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return false,
+        Some(p) => p,
+    };
+
+    let name = match std::ffi::CStr::from_ptr(name).to_str() {
+        Ok(x) => x,
+        Err(e) => return plugin.as_ref().error_kind(ErrorKind::BadUtf8, e, false),
+    };
+
+    if !matches!(
+        val.t,
+        ValType::I32 | ValType::I64 | ValType::F32 | ValType::F64
+    ) {
+        return plugin
+            .as_ref()
+            .error(format!("Unsupported global value: {:?}", val.t), false);
+    }
+
+    match plugin.as_mut().set_global(name, val.to_val(&val.t)) {
+        Ok(()) => true,
+        Err(e) => plugin.as_ref().error(e, false),
+    }
+}
+
+/// Call a function with typed arguments instead of input data
+///
+/// `args`: the `n_args` arguments to pass to the function, these must match the function's
+/// parameters
+/// `results`: a buffer that receives the function's results, `n_results` must match the number
+/// of results returned by the function
+/// Returns 0 on success or -1 on error
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_call_typed(
+    ctx: *mut Context,
+    plugin_id: PluginIndex,
+    func_name: *const c_char,
+    args: *const ExtismVal,
+    n_args: Size,
+    results: *mut ExtismVal,
+    n_results: Size,
+) -> i32 {
+    trace!("Call to extism_plugin_call_typed for plugin {plugin_id}");
+
+    let ctx = &*ctx;
+
+    if (args.is_null() && n_args > 0) || (results.is_null() && n_results > 0) {
+        return match PluginRef::new(ctx, plugin_id, true) {
+            None => -1,
+            Some(plugin) => plugin.as_ref().error("args or results is NULL", -1),
+        };
+    }
+
+    let args = if n_args == 0 {
+        &[]
+    } else {
+        std::slice::from_raw_parts(args, n_args as usize)
+    };
+
+    if let Some(arg) = args.iter().find(|x| {
+        !matches!(
+            x.t,
+            ValType::I32 | ValType::I64 | ValType::F32 | ValType::F64
+        )
+    }) {
+        return ctx.error(format!("Unsupported argument type: {:?}", arg.t), -1);
+    }
+    let args: Vec<_> = args.iter().map(|x| x.to_val(&x.t)).collect();
+
+    let mut values = vec![];
+    let rc = plugin_call(
+        ctx,
+        plugin_id,
+        func_name,
+        std::ptr::null(),
+        0,
+        CallMode::Typed(&args, &mut values),
+    );
+
+    if rc != 0 {
+        return -1;
+    }
+
+    if values.len() != n_results as usize {
+        let e = format!(
+            "Function returned {} results, expected {n_results}",
+            values.len()
+        );
+        return match ctx.plugin_locked(plugin_id) {
+            Some(p) => p.plugin.error(e, -1),
+            None => ctx.error(format!("Plugin does not exist: {plugin_id}"), -1),
+        };
+    }
+
+    for (i, val) in values.iter().enumerate() {
+        match ExtismVal::from_val(val) {
+            Ok(x) => *results.add(i) = x,
+            Err(e) => {
+                return match ctx.plugin_locked(plugin_id) {
+                    Some(p) => p.plugin.error(e, -1),
+                    None => ctx.error(format!("Plugin does not exist: {plugin_id}"), -1),
+                }
+            }
+        }
+    }
+
+    0
+}
+
+/// Call a function with metadata that can be read by the plugin using the `extism_get_meta`
+/// host function
+///
+/// `meta_json`: a NUL-terminated JSON object, non-string values are converted to their JSON
+/// representation
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_call_with_meta(
+    ctx: *mut Context,
+    plugin_id: PluginIndex,
+    func_name: *const c_char,
+    data: *const u8,
+    data_len: Size,
+    meta_json: *const c_char,
+) -> i32 {
+    trace!("Call to extism_plugin_call_with_meta for plugin {plugin_id}");
+    let ctx = &*ctx;
+
+    let meta: serde_json::Map<String, serde_json::Value> = if meta_json.is_null() {
+        serde_json::Map::new()
+    } else {
+        match serde_json::from_slice(std::ffi::CStr::from_ptr(meta_json).to_bytes()) {
+            Ok(x) => x,
+            Err(e) => {
+                return match PluginRef::new(ctx, plugin_id, true) {
+                    None => -1,
+                    Some(plugin) => plugin.as_ref().error(e, -1),
+                }
+            }
+        }
+    };
+
+    let options = CallOptions {
+        meta: meta
+            .into_iter()
+            .map(|(k, v)| match v {
+                serde_json::Value::String(s) => (k, s),
+                v => (k, v.to_string()),
+            })
+            .collect(),
+        ..Default::default()
+    };
+
+    executor::block_on(observed_call(
+        ctx,
+        plugin_id,
+        func_name,
+        CallTarget::Name(func_name),
+        CallInput::Bytes(data, data_len),
+        CallMode::Default,
+        options,
+    ))
+}
+
+/// Call a function with a timeout that only applies to this call, the manifest's timeout is
+/// used by other calls
+///
+/// `timeout_ms`: the timeout in milliseconds, `0` means the call has no time limit
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_call_with_timeout(
+    ctx: *mut Context,
+    plugin_id: PluginIndex,
+    func_name: *const c_char,
+    data: *const u8,
+    data_len: Size,
+    timeout_ms: u64,
+) -> i32 {
+    trace!("Call to extism_plugin_call_with_timeout for plugin {plugin_id}");
+
+    let options = CallOptions {
+        timeout_ms: Some(if timeout_ms == 0 {
+            None
+        } else {
+            Some(timeout_ms)
+        }),
+        ..Default::default()
+    };
+
+    executor::block_on(observed_call(
+        &*ctx,
+        plugin_id,
+        func_name,
+        CallTarget::Name(func_name),
+        CallInput::Bytes(data, data_len),
+        CallMode::Default,
+        options,
+    ))
+}
+
+/// A single segment of input for `extism_plugin_call_vectored`
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct IoVec {
+    pub ptr: *const u8,
+    pub len: Size,
+}
+
+/// Call a function with input made up of multiple buffers, the buffers are concatenated in
+/// order to form the input
+///
+/// `iovecs`: an array of `n_iovecs` input segments
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_call_vectored(
+    ctx: *mut Context,
+    plugin_id: PluginIndex,
+    func_name: *const c_char,
+    iovecs: *const IoVec,
+    n_iovecs: Size,
+) -> i32 {
+    trace!("Call to extism_plugin_call_vectored for plugin {plugin_id}");
+
+    let iovecs = if iovecs.is_null() {
+        &[]
+    } else {
+        std::slice::from_raw_parts(iovecs, n_iovecs as usize)
+    };
+
+    executor::block_on(observed_call(
+        &*ctx,
+        plugin_id,
+        func_name,
+        CallTarget::Name(func_name),
+        CallInput::Segments(iovecs),
+        CallMode::Default,
+        CallOptions::default(),
+    ))
+}
+
+/// Call a function, data the plugin passes to `extism_emit` while it's running is sent to
+/// `callback` immediately, so partial results can be handled before the function returns. The
+/// output set using `extism_output_set` is still available after the call
+///
+/// `callback`: called with the plugin ID, the emitted data and `user_data`, the data is only
+/// valid until the callback returns
+/// `user_data`: passed to `callback`, it isn't used after this function returns
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_call_streaming(
+    ctx: *mut Context,
+    plugin_id: PluginIndex,
+    func_name: *const c_char,
+    data: *const u8,
+    data_len: Size,
+    callback: EmitCallback,
+    user_data: *mut std::ffi::c_void,
+) -> i32 {
+    trace!("Call to extism_plugin_call_streaming for plugin {plugin_id}");
+
+    let ctx = &*ctx;
+    let callback = match callback {
+        Some(x) => x,
+        None => {
+            return match PluginRef::new(ctx, plugin_id, true) {
+                None => -1,
+                Some(p) => p.as_ref().error("Emit callback is NULL", -1),
+            }
+        }
+    };
+    let emitter = Emitter {
+        plugin: plugin_id,
+        callback,
+        user_data,
+    };
+
+    plugin_call(
+        ctx,
+        plugin_id,
+        func_name,
+        data,
+        data_len,
+        CallMode::Streaming(emitter),
+    )
+}
+
+/// A single call for `extism_plugin_call_batch`
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct BatchCall {
+    pub func_name: *const c_char,
+    pub data: *const u8,
+    pub data_len: Size,
+}
+
+/// Called by `extism_plugin_call_batch` after each call with the index of the call, its return
+/// code and its output, which is only valid until the callback returns
+pub type BatchOutputCallback = Option<
+    unsafe extern "C" fn(
+        index: Size,
+        rc: i32,
+        output: *const u8,
+        output_len: Size,
+        user_data: *mut std::ffi::c_void,
+    ),
+>;
+
+/// Call several functions in order against the same plugin instance using a single FFI call, the
+/// plugin stays locked until every call has been made
+///
+/// `calls`: an array of `n_calls` calls
+/// `results_out`: an array of `n_calls` return codes, the return code of each call that was made
+/// is written to the matching index
+/// `continue_on_error`: when false, no more calls are made after the first call that fails
+/// `output`: called after each call that was made with the call's output, this can be `NULL`.
+/// The output of a call that failed is empty, and calls to the plugin from `output` fail
+/// `user_data`: passed to `output`
+///
+/// Returns the index of the first call that failed, or -1 if every call succeeded. The output
+/// and error are those of the last call that was made
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_call_batch(
+    ctx: *mut Context,
+    plugin_id: PluginIndex,
+    calls: *const BatchCall,
+    n_calls: Size,
+    results_out: *mut i32,
+    continue_on_error: bool,
+    output: BatchOutputCallback,
+    user_data: *mut std::ffi::c_void,
+) -> i64 {
+    trace!("Call to extism_plugin_call_batch for plugin {plugin_id} with {n_calls} calls");
+
+    let ctx = &*ctx;
+    let calls = if calls.is_null() || n_calls == 0 {
+        &[]
+    } else {
+        std::slice::from_raw_parts(calls, n_calls as usize)
+    };
+    let results = if results_out.is_null() || n_calls == 0 {
+        &mut []
+    } else {
+        std::slice::from_raw_parts_mut(results_out, n_calls as usize)
+    };
+    if calls.is_empty() {
+        return -1;
+    }
+
+    let observer = ctx.call_observer();
+    let mut plugin_ref = None;
+    let mut failed = -1;
+    for (index, call) in calls.iter().enumerate() {
+        if let Some(observer) = observer {
+            observer.notify(plugin_id, call.func_name, CallPhase::Start, -1);
+        }
+
+        // The plugin is loaded once, so calls from other threads can't run between the batch's
+        // calls. After the first call it stays marked as executing, so callbacks can't call it
+        if index == 0 {
+            plugin_ref = PluginRef::new(ctx, plugin_id, true);
+        }
+
+        let rc = match &mut plugin_ref {
+            None => -1,
+            Some(p) => {
+                // Each call starts without the previous call's error, like separate calls
+                if index > 0 {
+                    p.finish();
+                    p.as_ref().clear_error();
+                }
+                if p.reinstantiate_if_needed() {
+                    executor::block_on(call_plugin_ref(
+                        p,
+                        CallTarget::Name(call.func_name),
+                        CallInput::Bytes(call.data, call.data_len),
+                        CallMode::Default,
+                        CallOptions::default(),
+                    ))
+                } else {
+                    -1
+                }
+            }
+        };
+
+        if let Some(observer) = observer {
+            observer.notify(plugin_id, call.func_name, CallPhase::End, rc);
+        }
+        if let Some(result) = results.get_mut(index) {
+            *result = rc;
+        }
+
+        if let (Some(callback), Some(p)) = (output, &plugin_ref) {
+            let data = if rc == 0 {
+                p.as_ref().output().unwrap_or_default()
+            } else {
+                &[]
+            };
+            callback(
+                index as Size,
+                rc,
+                data.as_ptr(),
+                data.len() as Size,
+                user_data,
+            );
+        }
+
+        if rc != 0 {
+            debug!("Call {index} in batch for plugin {plugin_id} failed with {rc}");
+            if failed < 0 {
+                failed = index as i64;
+            }
+
+            if !continue_on_error {
+                break;
+            }
+        }
+    }
+
+    failed
+}
+
+// The function being called, either by name or using a handle returned by
+// `extism_plugin_function_resolve`
+#[derive(Clone, Copy)]
+enum CallTarget {
+    Name(*const c_char),
+    Handle(i32),
+}
+
+// The input for a call, either a single buffer or the segments passed to
+// `extism_plugin_call_vectored`, which are written to plugin memory without being concatenated
+#[derive(Clone, Copy)]
+enum CallInput<'a> {
+    Bytes(*const u8, Size),
+    Segments(&'a [IoVec]),
+}
+
+// Determines how the arguments and results of a call are handled
+enum CallMode<'a> {
+    // The function takes no arguments and the first result is the return code
+    Default,
+    // The function takes no arguments and all results are returned as `i64`
+    Multi(&'a mut Vec<i64>),
+    // The function is called with the given arguments and all results are returned
+    Typed(&'a [Val], &'a mut Vec<Val>),
+    // The same as `Default`, data passed to `extism_emit` is sent to the emitter during the call
+    Streaming(Emitter),
+}
+
+// Settings that only apply to a single call, they're set after `init` and cleared by
+// `PluginRef::finish` so they never outlive the call
+#[derive(Default)]
+struct CallOptions {
+    // Returned by `extism_get_meta` while the call is running
+    meta: std::collections::BTreeMap<String, String>,
+    // Replaces the manifest's `timeout_ms` for this call when set, `Some(None)` means no limit
+    timeout_ms: Option<Option<u64>>,
+}
+
+// Call a function by name and notify the call observer, used by the calls that take a `CallMode`
+unsafe fn plugin_call(
+    ctx: &Context,
+    plugin_id: PluginIndex,
+    func_name: *const c_char,
+    data: *const u8,
+    data_len: Size,
+    mode: CallMode,
+) -> i32 {
+    executor::block_on(observed_call(
+        ctx,
+        plugin_id,
+        func_name,
+        CallTarget::Name(func_name),
+        CallInput::Bytes(data, data_len),
+        mode,
+        CallOptions::default(),
+    ))
+}
+
+async unsafe fn call_function(
+    ctx: &Context,
+    plugin_id: PluginIndex,
+    target: CallTarget,
+    input: CallInput<'_>,
+    mode: CallMode<'_>,
+    options: CallOptions,
+) -> i32 {
+    let mut plugin_ref = match PluginRef::new(ctx, plugin_id, true) {
+        None => return -1,
+        Some(p) => p,
+    };
+    call_plugin_ref(&mut plugin_ref, target, input, mode, options).await
+}
+
+// Make a call using a plugin that's already loaded, `extism_plugin_call_batch` uses this to make
+// several calls without releasing the plugin
+async unsafe fn call_plugin_ref(
+    plugin_ref: &mut PluginRef<'_>,
+    target: CallTarget,
+    input: CallInput<'_>,
+    mode: CallMode<'_>,
+    options: CallOptions,
+) -> i32 {
+    let plugin_id = plugin_ref.id;
+
+    // Call `init` to set up the plugin input and memory, this is only needed before a new call
+    match input {
+        CallInput::Bytes(data, data_len) => plugin_ref.init(data, data_len as usize),
+        CallInput::Segments(iovecs) => {
+            plugin_ref.init(std::ptr::null(), 0);
+            let segments = iovecs.iter().filter(|x| !x.ptr.is_null()).map(|x| {
+                // Safety: the caller guarantees each non-null segment points to `len` bytes
+                std::slice::from_raw_parts(x.ptr, x.len as usize)
+            });
+            if let Err(e) = plugin_ref.as_mut().set_input_segments(segments) {
+                return plugin_ref.as_ref().error(e, -1);
+            }
+        }
+    }
+    plugin_ref.as_mut().memory.store.data_mut().meta = options.meta;
+
+    // Find function, functions called using a handle have already been looked up
+    let (func_name, func) = match target {
+        CallTarget::Name(func_name) => (func_name, None),
+        CallTarget::Handle(handle) => match plugin_ref.as_mut().function_handle(handle) {
+            Some((func_name, func)) => (func_name, Some(func)),
+            None => {
+                return plugin_ref.as_ref().error_kind(
+                    ErrorKind::NotFound,
+                    format!("Invalid function handle: {handle}"),
+                    -1,
+                )
+            }
+        },
+    };
+    let name = std::ffi::CStr::from_ptr(func_name);
+    let name = match name.to_str() {
+        Ok(name) => name,
+        Err(e) => return plugin_ref.as_ref().error_kind(ErrorKind::BadUtf8, e, -1),
+    };
+
+    // Reactors are initialized when they're instantiated, calling `_initialize` again would run
+    // the module's constructors twice
+    if name == "_initialize" && plugin_ref.as_mut().is_reactor() {
+        debug!("Skipping call to _initialize in plugin {plugin_id}, it's already initialized");
+        let data = plugin_ref.as_mut().memory.store.data_mut();
+        data.output_offset = 0;
+        data.output_length = 0;
+        return 0;
+    }
+
+    debug!("Calling function: {name} in plugin {plugin_id}");
+    plugin_ref.as_mut().touch();
+    if let CallMode::Streaming(emitter) = mode {
+        plugin_ref.as_mut().memory.store.data_mut().emitter = Some(emitter);
+    }
+
+    let mut args = vec![];
+    let typed = matches!(mode, CallMode::Typed(..));
+    let func = match func.or_else(|| plugin_ref.as_mut().get_func(name)) {
+        Some(x) => x,
+        None => {
+            let dispatch = if !typed && plugin_ref.as_ref().manifest.as_ref().dispatch_fallback {
+                plugin_ref.as_mut().get_func("_dispatch")
+            } else {
+                None
+            };
+
+            let dispatch = match dispatch {
+                Some(x) => x,
+                None => {
+                    return plugin_ref.as_ref().error_kind(
+                        ErrorKind::NotFound,
+                        format!("Function not found: {name}"),
+                        -1,
+                    )
+                }
+            };
+
+            // `_dispatch` receives the offset of the requested function name, the input is
+            // still available using the regular input functions
+            let params: Vec<_> = dispatch
+                .ty(&plugin_ref.as_ref().memory.store)
+                .params()
+                .collect();
+            if params != [wasmtime::ValType::I64] {
+                return plugin_ref.as_ref().error(
+                    format!("Function _dispatch has invalid params {params:?}, expected [I64]"),
+                    -1,
+                );
+            }
+
+            debug!("Function {name} not found, calling _dispatch");
+            let block = match plugin_ref.as_mut().memory.alloc_bytes(name) {
+                Ok(x) => x,
+                Err(e) => return plugin_ref.as_ref().error(e, -1),
+            };
+            args.push(Val::I64(block.offset as i64));
+            dispatch
+        }
+    };
+
+    // Check the arguments when they're provided by the caller
+    if let CallMode::Typed(typed_args, _) = &mode {
+        let params: Vec<_> = func
+            .ty(&plugin_ref.as_ref().memory.store)
+            .params()
+            .collect();
+        let arg_types: Vec<_> = typed_args.iter().map(|x| x.ty()).collect();
+        if params != arg_types {
+            return plugin_ref.as_ref().error(
+                format!("Function {name} expects arguments {params:?}, got {arg_types:?}"),
+                -1,
+            );
+        }
+        args = typed_args.to_vec();
+    }
+
+    // Check the number of results, reject functions with more than 1 result unless all of the
+    // results were requested
+    let func_ty = func.ty(&plugin_ref.as_ref().memory.store);
+    let n_results = func_ty.results().len();
+    if matches!(mode, CallMode::Default | CallMode::Streaming(_)) && n_results > 1 {
+        return plugin_ref.as_ref().error(
+            format!("Function {name} has {n_results} results, expected 0 or 1"),
+            -1,
+        );
+    }
+
+    if matches!(mode, CallMode::Multi(_)) {
+        if let Some(t) = func_ty
+            .results()
+            .find(|t| !matches!(t, wasmtime::ValType::I32 | wasmtime::ValType::I64))
+        {
+            return plugin_ref.as_ref().error(
+                format!("Function {name} has a result of type {t}, expected i32 or i64"),
+                -1,
+            );
+        }
+    }
+
+    // Start timer
+    let tx = plugin_ref.epoch_timer_tx.clone();
+    let timeout_ms = options
+        .timeout_ms
+        .unwrap_or(plugin_ref.as_ref().manifest.as_ref().timeout_ms);
+    if let Err(e) = plugin_ref.as_mut().start_timer(&tx, timeout_ms) {
+        let id = plugin_ref.as_ref().timer_id;
+        return plugin_ref.as_ref().error(
+            format!("Unable to start timeout manager for {id}: {e:?}"),
+            -1,
+        );
+    }
+
+    // Call the function
+    plugin_ref
+        .as_mut()
+        .memory
+        .store
+        .data_mut()
+        .memory_limiter
+        .exceeded = false;
+    plugin_ref
+        .as_mut()
+        .memory
+        .store
+        .data_mut()
+        .memory_limiter
+        .table_exceeded = false;
+    if let Some(wasi) = &plugin_ref.as_ref().memory.store.data().wasi {
+        wasi.clear_output();
+    }
+    plugin_ref.as_mut().last_exit_code = None;
+    // Memories can't shrink, so any growth changes the total size
+    let memory_size = plugin_ref.as_mut().memory_usage();
+    let snapshot = if plugin_ref.as_ref().rollback_on_error {
+        Some(plugin_ref.as_mut().snapshot())
+    } else {
+        None
+    };
+    // Most functions have at most one result, which is kept on the stack to avoid allocating
+    let mut single = [Val::null()];
+    let mut many = vec![];
+    let results: &mut [Val] = if n_results <= 1 {
+        &mut single[..n_results]
+    } else {
+        many.resize(n_results, Val::null());
+        &mut many
+    };
+    let start = std::time::Instant::now();
+    let mut res = plugin_ref.as_mut().call_func(func, &args, results).await;
+    plugin_ref.as_mut().last_call_duration = start.elapsed();
+
+    // A denied `memory.grow` or `table.grow` interrupts the plugin, but a function can return
+    // before it checks for the interruption
+    if res.is_ok() {
+        let limiter = &plugin_ref.as_ref().memory.store.data().memory_limiter;
+        if limiter.exceeded {
+            res = Err(Error::msg("memory limit exceeded"));
+        } else if limiter.table_exceeded {
+            res = Err(Error::msg("resource limit exceeded"));
+        }
+    }
+
+    // Undo any changes made by a call that trapped, exiting using WASI isn't a failure
+    if let (Err(e), Some(snapshot)) = (&res, &snapshot) {
+        if e.downcast_ref::<wasmtime_wasi::I32Exit>().is_none() {
+            if let Err(e) = plugin_ref.as_mut().restore(snapshot) {
+                error!("Unable to roll back memory after failed call: {e:?}");
+            }
+        }
+    }
+    let used = plugin_ref.as_mut().update_memory_high_water();
+    plugin_ref.as_mut().memory_grew = used != memory_size;
+
+    plugin_ref.as_ref().dump_memory();
+
+    let manifest = plugin_ref.as_ref().manifest.as_ref();
+    if plugin_ref.as_ref().has_wasi() && name == "_start" {
+        let reinstantiate = manifest.reinstantiate_after_start.unwrap_or(true);
+        plugin_ref.as_mut().should_reinstantiate = reinstantiate;
+    } else if manifest.reinstantiate_after.iter().any(|x| x == name) {
+        plugin_ref.as_mut().should_reinstantiate = true;
+    }
+
+    // Stop timer
+    if let Err(e) = plugin_ref.as_mut().stop_timer(&tx, timeout_ms) {
+        let id = plugin_ref.as_ref().timer_id;
+        return plugin_ref.as_ref().error(
+            format!("Failed to stop timeout manager for {id}: {e:?}"),
+            -1,
+        );
+    }
+
+    match res {
+        Ok(()) => (),
+        Err(e) => {
+            plugin_ref.as_ref().set_backtrace(&e);
+
+            if let Some(exit) = e.downcast_ref::<wasmtime_wasi::I32Exit>() {
+                trace!("WASI return code: {}", exit.0);
+                plugin_ref.as_mut().last_exit_code = Some(exit.0);
+                if exit.0 != 0 {
+                    return plugin_ref
+                        .as_ref()
+                        .error_kind(ErrorKind::WasiExit, &e, exit.0);
+                }
+                return exit.0;
+            }
+
+            let plugin = plugin_ref.as_ref();
+
+            if e.root_cause().to_string() == "timeout" {
+                if let Some(handler) = plugin.timeout_handler {
+                    handler.notify(plugin_id, plugin.memory.data());
+                }
+                return plugin.error_kind(ErrorKind::Timeout, "timeout", -1);
+            }
+
+            if e.root_cause().to_string() == "memory limit exceeded" {
+                return plugin.error_kind(ErrorKind::MemoryLimit, "memory limit exceeded", -1);
+            }
+
+            if e.root_cause().to_string() == "resource limit exceeded" {
+                return plugin.error_kind(ErrorKind::ResourceLimit, "resource limit exceeded", -1);
+            }
+
+            if let Some(Trap::OutOfFuel) = e.downcast_ref::<Trap>() {
+                return plugin.error_kind(ErrorKind::FuelExhausted, "fuel exhausted", -1);
+            }
+
+            if e.root_cause().to_string() == "cancelled" {
+                return plugin.error_kind(ErrorKind::Cancelled, "cancelled", -1);
+            }
+
+            error!("Call: {e:?}");
+            let kind = if e.downcast_ref::<Trap>().is_some() {
+                ErrorKind::Trap
+            } else {
+                ErrorKind::Error
+            };
+            return plugin.error_kind(kind, e.context("Call failed"), -1);
+        }
+    };
+
+    // This should never happen, but make sure the results weren't resized before indexing
+    if results.len() != n_results {
+        return plugin_ref.as_ref().error(
+            format!(
+                "Internal error: function {name} returned {} results, expected {n_results}",
+                results.len()
+            ),
+            -1,
+        );
+    }
+
+    // If `results` is empty and the return value wasn't a WASI exit code then
+    // the call succeeded, when all of the results are requested they're returned
+    // to the caller instead
+    let rc = match mode {
+        CallMode::Multi(out) => {
+            *out = results
+                .iter()
+                .map(|x| match x {
+                    Val::I32(x) => *x as i64,
+                    Val::I64(x) => *x,
+                    _ => unreachable!(),
+                })
+                .collect();
+            0
+        }
+        CallMode::Typed(_, out) => {
+            *out = results.to_vec();
+            0
+        }
+        CallMode::Default | CallMode::Streaming(_) if results.is_empty() => 0,
+        CallMode::Default | CallMode::Streaming(_) => results[0].unwrap_i32(),
+    };
+
+    if rc == 0 {
+        if let Err(e) = plugin_ref.as_ref().validate_output() {
+            return plugin_ref
+                .as_ref()
+                .error_kind(ErrorKind::InvalidOutput, e, -1);
+        }
+    }
+
+    // Return result to caller
+    rc
+}
+
+/// Call a function using length-delimited protobuf messages for input and output
+///
+/// The request and response are checked to be non-empty, correctly framed and no larger than
+/// 64MiB but are otherwise passed through unchanged
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_call_proto(
+    ctx: *mut Context,
+    plugin_id: PluginIndex,
+    func_name: *const c_char,
+    request: *const u8,
+    request_len: Size,
+) -> i32 {
+    let ctx = &*ctx;
+
+    // Validate the request before calling the plugin
+    {
+        let plugin = match PluginRef::new(ctx, plugin_id, true) {
+            None => return -1,
+            Some(p) => p,
+        };
+
+        let data = if request.is_null() {
+            &[]
+        } else {
+            std::slice::from_raw_parts(request, request_len as usize)
+        };
+        if let Err(e) = proto::validate_message(data) {
+            return plugin.as_ref().error(e.context("Invalid request"), -1);
+        }
+    }
+
+    let rc = executor::block_on(plugin_call_async(
+        ctx,
+        plugin_id,
+        func_name,
+        request,
+        request_len,
+    ));
+    if rc != 0 {
+        return rc;
+    }
+
+    // The plugin may have been removed by another thread since the call returned
+    let plugin = match ctx.plugin_locked(plugin_id) {
+        None => return ctx.error(format!("Plugin does not exist: {plugin_id}"), -1),
+        Some(p) => p,
+    };
+    if let Err(e) = plugin.plugin.output().and_then(proto::validate_message) {
+        return plugin.plugin.error(e.context("Invalid response"), -1);
+    }
+
+    0
+}
+
+const TEST_PREFIX: &str = "test_";
+
+/// Call every function exported by the plugin that starts with `test_` using empty input
+///
+/// Returns a JSON object mapping each test name to `{"passed": bool, "error": string | null}`,
+/// the returned string is valid until the next call to `extism_plugin_run_tests`
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_run_tests(
+    ctx: *mut Context,
+    plugin_id: PluginIndex,
+) -> *const c_char {
+    trace!("Call to extism_plugin_run_tests for plugin {plugin_id}");
+
+    let ctx = &*ctx;
+    let names = match PluginRef::new(ctx, plugin_id, true) {
+        None => return std::ptr::null(),
+        Some(p) => p.as_ref().function_names(),
+    };
+
+    let mut results = serde_json::Map::new();
+    for name in names.into_iter().filter(|x| x.starts_with(TEST_PREFIX)) {
+        debug!("Running test {name} in plugin {plugin_id}");
+        let func_name = match std::ffi::CString::new(name.as_str()) {
+            Ok(x) => x,
+            Err(_) => continue,
+        };
+
+        let rc = executor::block_on(plugin_call_async(
+            ctx,
+            plugin_id,
+            func_name.as_ptr(),
+            std::ptr::null(),
+            0,
+        ));
+        let error = if rc == 0 {
+            None
+        } else {
+            let err = match ctx.plugin_locked(plugin_id) {
+                Some(p) => p.plugin.last_error.borrow().clone(),
+                None => ctx.error_message().clone(),
+            };
+            match err {
+                Some(e) => Some(e.to_string_lossy().into_owned()),
+                None => Some(format!("Test returned {rc}")),
+            }
+        };
+
+        results.insert(name, serde_json::json!({"passed": rc == 0, "error": error}));
+    }
+
+    // The plugin may have been removed by another thread while the tests were running
+    let plugin = match ctx.plugin_locked(plugin_id) {
+        None => {
+            return ctx.error(
+                format!("Plugin does not exist: {plugin_id}"),
+                std::ptr::null(),
+            )
+        }
+        Some(p) => p.plugin,
+    };
+    let json = serde_json::Value::Object(results).to_string();
+    plugin.test_results = std::ffi::CString::new(json).ok();
+    match &plugin.test_results {
+        Some(x) => x.as_ptr(),
+        None => std::ptr::null(),
+    }
+}
+
+pub fn get_context_error(ctx: &Context) -> *const c_char {
+    match &*ctx.error_message() {
+        Some(e) => e.as_ptr() as *const _,
+        None => {
+            trace!("Context error is NULL");
+            std::ptr::null()
+        }
+    }
+}
+
+/// Get the error associated with a `Context` or `Plugin`, if `plugin` is `-1` then the context
+/// error will be returned
+#[no_mangle]
+pub unsafe extern "C" fn extism_error(ctx: *mut Context, plugin: PluginIndex) -> *const c_char {
+    trace!("Call to extism_error for plugin {plugin}");
+
+    let ctx = &*ctx;
+
+    if !ctx.plugin_exists(plugin) {
+        return get_context_error(ctx);
+    }
+
+    let plugin = match PluginRef::new(ctx, plugin, false) {
+        None => return std::ptr::null(),
+        Some(p) => p,
+    };
+
+    let err = plugin.as_ref().last_error.borrow();
+    match err.as_ref() {
+        Some(e) => e.as_ptr() as *const _,
+        None => {
+            trace!("Error is NULL");
+            std::ptr::null()
+        }
+    }
+}
+
+/// Get the length in bytes of the error associated with a `Context` or `Plugin`, not including
+/// the trailing NUL byte. If `plugin` is `-1` then the length of the context error will be
+/// returned. Returns 0 if no error is set.
+#[no_mangle]
+pub unsafe extern "C" fn extism_error_length(ctx: *mut Context, plugin: PluginIndex) -> Size {
+    trace!("Call to extism_error_length for plugin {plugin}");
+
+    let ctx = &*ctx;
+
+    if !ctx.plugin_exists(plugin) {
+        return ctx
+            .error_message()
+            .as_ref()
+            .map(|e| e.as_bytes().len() as Size)
+            .unwrap_or(0);
+    }
+
+    let plugin = match PluginRef::new(ctx, plugin, false) {
+        None => return 0,
+        Some(p) => p,
+    };
+
+    let err = plugin.as_ref().last_error.borrow();
+    err.as_ref()
+        .map(|e| e.as_bytes().len() as Size)
+        .unwrap_or(0)
+}
+
+/// Get the error associated with a `Plugin` as a JSON object with the following fields:
+/// - `kind`: one of `error`, `timeout`, `cancelled`, `trap`, `wasi_exit`, `not_found`,
+///   `bad_utf8`, `memory_limit`, `resource_limit`, `fuel_exhausted` or `invalid_output`
+/// - `code`: the code returned by `extism_plugin_last_error_code`
+/// - `message`: the same message returned by `extism_error`
+/// - `exit_code`: the WASI exit code, only set when `kind` is `wasi_exit`
+///
+/// Returns NULL if no error is set, the returned string is valid until the next call to this
+/// function for the same plugin
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_last_error_json(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+) -> *const c_char {
+    trace!("Call to extism_plugin_last_error_json for plugin {plugin}");
+
+    let ctx = &*ctx;
+    let plugin = match PluginRef::new(ctx, plugin, false) {
+        None => return std::ptr::null(),
+        Some(p) => p,
+    };
+
+    let plugin = plugin.as_ref();
+    let json = match plugin.last_error_json() {
+        Some(x) => x,
+        None => return std::ptr::null(),
+    };
+    // Serialized JSON never contains NUL bytes, they're escaped
+    let s = std::ffi::CString::new(json.to_string()).unwrap_or_default();
+    let mut dest = plugin.last_error_json.borrow_mut();
+    *dest = Some(s);
+    match dest.as_ref() {
+        Some(s) => s.as_ptr(),
+        None => std::ptr::null(),
+    }
+}
+
+/// Get a numeric code for the error associated with a `Plugin`, these values are stable and can
+/// be used instead of matching the error message:
+/// - `0`: no error
+/// - `1`: function not found
+/// - `2`: timeout
+/// - `3`: trap
+/// - `4`: WASI exit with a non-zero exit code
+/// - `5`: invalid UTF-8
+/// - `6`: cancelled
+/// - `7`: memory limit exceeded
+/// - `8`: fuel exhausted
+/// - `9`: output doesn't match `output_must_be`
+/// - `10`: any other error
+/// - `11`: resource limit exceeded
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_last_error_code(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+) -> i32 {
+    trace!("Call to extism_plugin_last_error_code for plugin {plugin}");
+
+    let ctx = &*ctx;
+    let plugin = match PluginRef::new(ctx, plugin, false) {
+        None => return 0,
+        Some(p) => p,
+    };
+
+    let plugin = plugin.as_ref();
+    if plugin.last_error.borrow().is_none() {
+        return 0;
+    }
+    plugin.last_error_kind.get().code()
+}
+
+/// Get the exit code passed to WASI `proc_exit` during the last call, this can be used to tell a
+/// plugin that exited with a non-zero code apart from a plugin that failed
+///
+/// `code`: receives the exit code
+/// Returns false if the last call didn't exit using `proc_exit`
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_last_exit_code(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+    code: *mut i32,
+) -> bool {
+    trace!("Call to extism_plugin_last_exit_code for plugin {plugin}");
+
+    let ctx = &*ctx;
+    let plugin = match PluginRef::new(ctx, plugin, false) {
+        None => return false,
+        Some(p) => p,
+    };
+
+    match plugin.as_ref().last_exit_code {
+        Some(x) => {
+            if !code.is_null() {
+                *code = x;
+            }
+            true
+        }
+        None => false,
+    }
+}
+
+/// Returns true if the plugin has an error set
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_has_error(ctx: *mut Context, plugin: PluginIndex) -> bool {
+    let ctx = &*ctx;
+    match ctx.plugin_locked(plugin) {
+        Some(p) => p.plugin.last_error.borrow().is_some(),
+        None => false,
+    }
+}
+
+/// Returns true if the context has an error set
+#[no_mangle]
+pub unsafe extern "C" fn extism_context_has_error(ctx: *mut Context) -> bool {
+    let ctx = &*ctx;
+    ctx.error_message().is_some()
+}
+
+/// Get the length of a plugin's output data
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_output_length(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+) -> Size {
+    trace!("Call to extism_plugin_output_length for plugin {plugin}");
+
+    let ctx = &*ctx;
+    let plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return 0,
+        Some(p) => p,
+    };
+
+    // The length is only returned if the output is inside the plugin's memory, so it can always
+    // be read using the pointer returned by `extism_plugin_output_data`
+    let len = plugin.as_ref().output().map_or(0, |x| x.len()) as Size;
+    trace!("Output length: {len}");
+    len
+}
+
+/// Get the length of a plugin's output data
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_output_data(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+) -> *const u8 {
+    trace!("Call to extism_plugin_output_data for plugin {plugin}");
+
+    let ctx = &*ctx;
+    let plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return std::ptr::null(),
+        Some(p) => p,
+    };
+
+    plugin
+        .as_ref()
+        .output()
+        .map(|x| x.as_ptr())
+        .unwrap_or(std::ptr::null())
+}
+
+/// Copy part of a plugin's output data into `dst`
+///
+/// `offset`: the position in the output to start reading from
+/// `len`: the size of `dst`
+/// Returns the number of bytes copied, which is 0 once `offset` reaches the end of the output
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_output_read(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+    offset: Size,
+    dst: *mut u8,
+    len: Size,
+) -> Size {
+    trace!("Call to extism_plugin_output_read for plugin {plugin}");
+
+    let ctx = &*ctx;
+    let plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return 0,
+        Some(p) => p,
+    };
+
+    let output = match plugin.as_ref().output() {
+        Ok(x) => x,
+        Err(e) => return plugin.as_ref().error(e, 0),
+    };
+
+    if dst.is_null() {
+        return 0;
+    }
+
+    let start = (offset as usize).min(output.len());
+    let n = (len as usize).min(output.len() - start);
+    std::ptr::copy_nonoverlapping(output[start..].as_ptr(), dst, n);
+    n as Size
+}
+
+/// Copy a plugin's output into `dst`, at most `dst_len` bytes are copied
+///
+/// Returns the length of the output, if this is larger than `dst_len` then the output was
+/// truncated and a buffer of the returned length is required to read all of it
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_output_copy(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+    dst: *mut u8,
+    dst_len: Size,
+) -> Size {
+    trace!("Call to extism_plugin_output_copy for plugin {plugin}");
+
+    let ctx = &*ctx;
+    let plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return 0,
+        Some(p) => p,
+    };
+
+    let output = match plugin.as_ref().output() {
+        Ok(x) => x,
+        Err(e) => return plugin.as_ref().error(e, 0),
+    };
+
+    if !dst.is_null() {
+        let n = output.len().min(dst_len as usize);
+        std::ptr::copy_nonoverlapping(output.as_ptr(), dst, n);
+    }
+    output.len() as Size
+}
+
+unsafe fn wasi_output(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+    stderr: bool,
+    out: *mut *const u8,
+    out_len: *mut Size,
+) -> bool {
+    let ctx = &*ctx;
+    let plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return false,
+        Some(p) => p,
+    };
+
+    let (ptr, len) = match &plugin.as_ref().memory.store.data().wasi {
+        Some(wasi) => {
+            let buf = if stderr { &wasi.stderr } else { &wasi.stdout };
+            match buf.read() {
+                Ok(data) => (data.data().as_ptr(), data.data().len()),
+                Err(e) => return plugin.as_ref().error(e.to_string(), false),
+            }
+        }
+        None => (std::ptr::null(), 0),
+    };
+
+    if !out.is_null() {
+        *out = ptr;
+    }
+    if !out_len.is_null() {
+        *out_len = len as Size;
+    }
+    true
+}
+
+/// Get the data written to stdout by a WASI plugin during the last call, at most 1MiB is
+/// captured and anything written after that is dropped
+///
+/// `out`: receives a pointer to the data, which is valid until the next call to the plugin
+/// `out_len`: receives the length of the data, this will be 0 if WASI is not enabled
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_wasi_stdout(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+    out: *mut *const u8,
+    out_len: *mut Size,
+) -> bool {
+    trace!("Call to extism_plugin_wasi_stdout for plugin {plugin}");
+    wasi_output(ctx, plugin, false, out, out_len)
+}
+
+/// Get the data written to stderr by a WASI plugin during the last call, at most 1MiB is
+/// captured and anything written after that is dropped
+///
+/// `out`: receives a pointer to the data, which is valid until the next call to the plugin
+/// `out_len`: receives the length of the data, this will be 0 if WASI is not enabled
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_wasi_stderr(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+    out: *mut *const u8,
+    out_len: *mut Size,
+) -> bool {
+    trace!("Call to extism_plugin_wasi_stderr for plugin {plugin}");
+    wasi_output(ctx, plugin, true, out, out_len)
+}
+
+/// Read a file from the in-memory filesystem created from the manifest's `files`, this can be
+/// used to get files written by the plugin
+///
+/// `path`: the path of the file in the guest
+/// `buf`: receives up to `buf_len` bytes of the file, this can be NULL to only get the length
+/// Returns the length of the file, or -1 if the file doesn't exist
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_wasi_read_file(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+    path: *const c_char,
+    buf: *mut u8,
+    buf_len: Size,
+) -> i64 {
+    trace!("Call to extism_plugin_wasi_read_file for plugin {plugin}");
+
+    let ctx = &*ctx;
+    let plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return -1,
+        Some(p) => p,
+    };
+
+    let path = match std::ffi::CStr::from_ptr(path).to_str() {
+        Ok(x) => x,
+        Err(e) => return plugin.as_ref().error(e, -1),
+    };
+
+    let fs = match &plugin.as_ref().memory.store.data().wasi {
+        Some(Wasi { fs: Some(fs), .. }) => fs,
+        _ => {
+            return plugin
+                .as_ref()
+                .error("Plugin doesn't have an in-memory filesystem", -1)
+        }
+    };
+
+    let fs = match fs.read() {
+        Ok(x) => x,
+        Err(e) => return plugin.as_ref().error(e.to_string(), -1),
+    };
+
+    let data = match fs.read(path) {
+        Some(x) => x,
+        None => return plugin.as_ref().error(format!("File not found: {path}"), -1),
+    };
+
+    if !buf.is_null() {
+        let n = data.len().min(buf_len as usize);
+        std::ptr::copy_nonoverlapping(data.as_ptr(), buf, n);
+    }
+    data.len() as i64
+}
+
+/// Write a plugin's output data directly to the file descriptor `fd`
+///
+/// Returns the number of bytes written, or -1 if the output couldn't be written completely, in
+/// which case the error message contains the number of bytes that were written
+#[cfg(unix)]
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_output_to_fd(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+    fd: i32,
+) -> i64 {
+    trace!("Call to extism_plugin_output_to_fd for plugin {plugin}");
+
+    let ctx = &*ctx;
+    let plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return -1,
+        Some(p) => p,
+    };
+
+    let output = match plugin.as_ref().output() {
+        Ok(x) => x,
+        Err(e) => return plugin.as_ref().error(e, -1),
+    };
+
+    let mut written = 0;
+    while written < output.len() {
+        let rest = &output[written..];
+        let n = libc::write(fd, rest.as_ptr() as *const _, rest.len());
+        if n < 0 {
+            let err = std::io::Error::last_os_error();
+            if err.kind() == std::io::ErrorKind::Interrupted {
+                continue;
+            }
+            return plugin.as_ref().error(
+                format!(
+                    "Unable to write output to fd {fd}, {written} of {} bytes written: {err}",
+                    output.len()
+                ),
+                -1,
+            );
+        }
+        written += n as usize;
+    }
+
+    written as i64
+}
+
+/// Touch every page of the plugin's memory ahead of time, this avoids paying the cost of
+/// page faults during latency-sensitive calls
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_prefault_memory(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+) -> bool {
+    trace!("Call to extism_plugin_prefault_memory for plugin {plugin}");
+
+    let ctx = &*ctx;
+    let mut plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return false,
+        Some(p) => p,
+    };
+
+    plugin.as_mut().prefault_memory();
+    true
+}
+
+/// Prepare a plugin so the first call doesn't pay any one-time costs, this finishes any pending
+/// reinstantiation, looks up the plugin's exports and faults in its memory
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_prewarm(ctx: *mut Context, plugin: PluginIndex) -> bool {
+    trace!("Call to extism_plugin_prewarm for plugin {plugin}");
+
+    let ctx = &*ctx;
+    let mut plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return false,
+        Some(p) => p,
+    };
+
+    match plugin.as_mut().prewarm() {
+        Ok(()) => true,
+        Err(e) => plugin.as_ref().error(e, false),
+    }
+}
+
+/// Get the input the plugin sees through `extism_input_load_u8`/`extism_input_load_u64`,
+/// `len` is set to the input length
+///
+/// Input isn't copied into plugin memory, so this points to the `data` that was passed to the
+/// most recent call and is only valid as long as that buffer is. Input passed to
+/// `extism_plugin_call_vectored` is written to plugin memory, in that case the pointer is valid
+/// until the next call
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_debug_input(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+    len: *mut Size,
+) -> *const u8 {
+    trace!("Call to extism_plugin_debug_input for plugin {plugin}");
+
+    let ctx = &*ctx;
+    let plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return std::ptr::null(),
+        Some(p) => p,
+    };
+
+    let input = plugin.as_ref().memory.store.data().input();
+    if !len.is_null() {
+        *len = input.len() as Size;
+    }
+    input.as_ptr()
+}
+
+/// Returns true if the Extism memory or any memory exported by the plugin grew during the last
+/// call, when this happens pointers into plugin memory, like the one returned by
+/// `extism_plugin_output_data`, should be fetched again
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_memory_grew(ctx: *mut Context, plugin: PluginIndex) -> bool {
+    trace!("Call to extism_plugin_memory_grew for plugin {plugin}");
+
+    let ctx = &*ctx;
+    let plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return false,
+        Some(p) => p,
+    };
+
+    plugin.as_ref().memory_grew
+}
+
+/// Get a pointer to the plugin's entire memory, `out_len` is set to the size of the memory in bytes
+///
+/// This pointer is only valid until the next call to the plugin
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_memory_export(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+    out_len: *mut Size,
+) -> *const u8 {
+    trace!("Call to extism_plugin_memory_export for plugin {plugin}");
+
+    let ctx = &*ctx;
+    let plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return std::ptr::null(),
+        Some(p) => p,
+    };
+
+    let data = plugin.as_ref().memory.data();
+    if !out_len.is_null() {
+        *out_len = data.len() as Size;
+    }
+    data.as_ptr()
+}
+
+/// Overwrite the plugin's entire memory with `data`, the memory will be grown if needed and
+/// any remaining bytes will be zeroed
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_memory_import(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+    data: *const u8,
+    data_len: Size,
+) -> bool {
+    trace!("Call to extism_plugin_memory_import for plugin {plugin}");
+
+    let ctx = &*ctx;
+    let mut plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return false,
+        Some(p) => p,
+    };
+
+    let data = if data.is_null() {
+        &[]
+    } else {
+        std::slice::from_raw_parts(data, data_len as usize)
+    };
+
+    if let Err(e) = plugin.as_mut().memory.replace(data) {
+        return plugin.as_ref().error(e, false);
+    }
+
+    true
+}
+
+/// Copy the plugin's memory so it can be restored later using `extism_plugin_restore`. This
+/// includes the Extism memory, every memory and mutable global exported by the plugin and the
+/// location of the output, but not tables or globals that aren't exported
+///
+/// The snapshot must be freed using `extism_plugin_snapshot_free`, returns `NULL` on error
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_snapshot(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+) -> *mut Snapshot {
+    trace!("Call to extism_plugin_snapshot for plugin {plugin}");
+
+    let ctx = &*ctx;
+    let mut plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return std::ptr::null_mut(),
+        Some(p) => p,
+    };
+
+    Box::into_raw(Box::new(plugin.as_mut().snapshot()))
+}
+
+/// Overwrite the plugin's memory with a snapshot created by `extism_plugin_snapshot`, the
+/// snapshot can be restored more than once
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_restore(
+    ctx: *mut Context,
+    plugin: PluginIndex,
+    snapshot: *const Snapshot,
+) -> bool {
+    trace!("Call to extism_plugin_restore for plugin {plugin}");
+
+    let ctx = &*ctx;
+    let mut plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return false,
+        Some(p) => p,
+    };
+
+    if snapshot.is_null() {
+        return plugin.as_ref().error("Snapshot is NULL", false);
+    }
+
+    if let Err(e) = plugin.as_mut().restore(&*snapshot) {
+        return plugin.as_ref().error(e, false);
+    }
+
+    true
+}
 
-    let name = match name.to_str() {
-        Ok(x) => x,
-        Err(e) => {
-            return plugin.as_mut().error(e, false);
-        }
-    };
+/// Free a snapshot created by `extism_plugin_snapshot`
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_snapshot_free(snapshot: *mut Snapshot) {
+    trace!("Call to extism_plugin_snapshot_free");
 
-    plugin.as_mut().get_func(name).is_some()
+    if snapshot.is_null() {
+        return;
+    }
+
+    drop(Box::from_raw(snapshot))
 }
 
-/// Call a function
+/// A copy of a plugin's output that stays valid across calls, see `extism_plugin_output_retain`
+pub struct RetainedOutput(Vec<u8>);
+
+/// Copy a plugin's output into a buffer owned by the runtime, this buffer is not affected by
+/// subsequent calls and must be freed using `extism_plugin_output_release`
 ///
-/// `func_name`: is the function to call
-/// `data`: is the input data
-/// `data_len`: is the length of `data`
+/// `data`: is set to a pointer to the output data
+/// `data_len`: is set to the length of the output data
 #[no_mangle]
-pub unsafe extern "C" fn extism_plugin_call(
+pub unsafe extern "C" fn extism_plugin_output_retain(
     ctx: *mut Context,
-    plugin_id: PluginIndex,
-    func_name: *const c_char,
-    data: *const u8,
-    data_len: Size,
-) -> i32 {
-    let ctx = &mut *ctx;
+    plugin: PluginIndex,
+    data: *mut *const u8,
+    data_len: *mut Size,
+) -> *mut RetainedOutput {
+    trace!("Call to extism_plugin_output_retain for plugin {plugin}");
 
-    // Get a `PluginRef` and call `init` to set up the plugin input and memory, this is only
-    // needed before a new call
-    let mut plugin_ref = match PluginRef::new(ctx, plugin_id, true) {
-        None => return -1,
-        Some(p) => p.init(data, data_len as usize),
+    let ctx = &*ctx;
+    let plugin = match PluginRef::new(ctx, plugin, true) {
+        None => return std::ptr::null_mut(),
+        Some(p) => p,
     };
 
-    // Find function
-    let name = std::ffi::CStr::from_ptr(func_name);
-    let name = match name.to_str() {
-        Ok(name) => name,
-        Err(e) => return plugin_ref.as_ref().error(e, -1),
+    let output = match plugin.as_ref().output() {
+        Ok(x) => RetainedOutput(x.to_vec()),
+        Err(e) => return plugin.as_ref().error(e, std::ptr::null_mut()),
     };
 
-    debug!("Calling function: {name} in plugin {plugin_id}");
-
-    let func = match plugin_ref.as_mut().get_func(name) {
-        Some(x) => x,
-        None => {
-            return plugin_ref
-                .as_ref()
-                .error(format!("Function not found: {name}"), -1)
-        }
-    };
+    if !data.is_null() {
+        *data = output.0.as_ptr();
+    }
 
-    // Check the number of results, reject functions with more than 1 result
-    let n_results = func.ty(&plugin_ref.as_ref().memory.store).results().len();
-    if n_results > 1 {
-        return plugin_ref.as_ref().error(
-            format!("Function {name} has {n_results} results, expected 0 or 1"),
-            -1,
-        );
+    if !data_len.is_null() {
+        *data_len = output.0.len() as Size;
     }
 
-    // Start timer
-    let tx = plugin_ref.epoch_timer_tx.clone();
-    if let Err(e) = plugin_ref.as_mut().start_timer(&tx) {
-        let id = plugin_ref.as_ref().timer_id;
-        return plugin_ref.as_ref().error(
-            format!("Unable to start timeout manager for {id}: {e:?}"),
-            -1,
-        );
+    Box::into_raw(Box::new(output))
+}
+
+/// Free output returned by `extism_plugin_output_retain`
+#[no_mangle]
+pub unsafe extern "C" fn extism_plugin_output_release(output: *mut RetainedOutput) {
+    trace!("Call to extism_plugin_output_release");
+    if output.is_null() {
+        return;
     }
+    drop(Box::from_raw(output))
+}
 
-    // Call the function
-    let mut results = vec![Val::null(); n_results];
-    let res = func.call(
-        &mut plugin_ref.as_mut().memory.store,
-        &[],
-        results.as_mut_slice(),
-    );
+// Parse a log level, `NULL` defaults to `error`
+unsafe fn parse_log_level(log_level: *const c_char) -> Option<log::LevelFilter> {
+    let level = if log_level.is_null() {
+        "error"
+    } else {
+        let level = std::ffi::CStr::from_ptr(log_level);
+        level.to_str().ok()?
+    };
 
-    plugin_ref.as_ref().dump_memory();
+    log::LevelFilter::from_str(level).ok()
+}
 
-    if plugin_ref.as_ref().has_wasi() && name == "_start" {
-        plugin_ref.as_mut().should_reinstantiate = true;
-    }
+// The format used by `extism_log_file`
+const LOG_PATTERN: &str = "{t} {l} {d} - {m}\n";
 
-    // Stop timer
-    if let Err(e) = plugin_ref.as_mut().stop_timer(&tx) {
-        let id = plugin_ref.as_ref().timer_id;
-        return plugin_ref.as_ref().error(
-            format!("Failed to stop timeout manager for {id}: {e:?}"),
-            -1,
-        );
-    }
+// The active logger, this is kept so logging can be reconfigured after `log4rs` is initialized
+struct LoggerState {
+    handle: log4rs::Handle,
+    appender: std::sync::Arc<dyn log4rs::append::Append>,
+    // The file logs are written to, if any
+    path: Option<std::path::PathBuf>,
+}
 
-    match res {
-        Ok(()) => (),
-        Err(e) => {
-            let plugin = plugin_ref.as_ref();
-            if let Some(exit) = e.downcast_ref::<wasmtime_wasi::I32Exit>() {
-                trace!("WASI return code: {}", exit.0);
-                if exit.0 != 0 {
-                    return plugin.error(&e, exit.0);
-                }
-                return exit.0;
-            }
+static LOGGER: std::sync::Mutex<Option<LoggerState>> = std::sync::Mutex::new(None);
 
-            if e.root_cause().to_string() == "timeout" {
-                return plugin.error("timeout", -1);
-            }
+// Allows the active appender to be reused when the logger is reconfigured
+#[derive(Debug)]
+struct SharedAppender(std::sync::Arc<dyn log4rs::append::Append>);
 
-            error!("Call: {e:?}");
-            return plugin.error(e.context("Call failed"), -1);
-        }
-    };
+impl log4rs::append::Append for SharedAppender {
+    fn append(&self, record: &log::Record) -> anyhow::Result<()> {
+        self.0.append(record)
+    }
 
-    // If `results` is empty and the return value wasn't a WASI exit code then
-    // the call succeeded
-    if results.is_empty() {
-        return 0;
+    fn flush(&self) {
+        self.0.flush()
     }
+}
 
-    // Return result to caller
-    results[0].unwrap_i32()
+fn logger_config(
+    appender: std::sync::Arc<dyn log4rs::append::Append>,
+    level: log::LevelFilter,
+) -> Option<log4rs::Config> {
+    use log::LevelFilter;
+    use log4rs::config::{Appender, Config, Logger, Root};
+
+    Config::builder()
+        .appender(Appender::builder().build("logfile", Box::new(SharedAppender(appender))))
+        .logger(
+            Logger::builder()
+                .appender("logfile")
+                .build("extism_runtime", level),
+        )
+        .build(Root::builder().build(LevelFilter::Off))
+        .ok()
 }
 
-pub fn get_context_error(ctx: &Context) -> *const c_char {
-    match &ctx.error {
-        Some(e) => e.as_ptr() as *const _,
+// Install `appender` as the destination for logs from the runtime, replacing the current
+// appender if logging has already been initialized. `path` is the file the appender writes to
+fn init_logger(
+    appender: Box<dyn log4rs::append::Append>,
+    level: log::LevelFilter,
+    path: Option<std::path::PathBuf>,
+) -> bool {
+    let appender: std::sync::Arc<dyn log4rs::append::Append> = appender.into();
+    let config = match logger_config(appender.clone(), level) {
+        Some(x) => x,
+        None => return false,
+    };
+
+    let mut logger = match LOGGER.lock() {
+        Ok(x) => x,
+        Err(e) => e.into_inner(),
+    };
+
+    match &mut *logger {
+        Some(state) => {
+            state.handle.set_config(config);
+            state.appender = appender;
+            state.path = path;
+        }
         None => {
-            trace!("Context error is NULL");
-            std::ptr::null()
+            let handle = match log4rs::init_config(config) {
+                Ok(x) => x,
+                Err(_) => return false,
+            };
+            *logger = Some(LoggerState {
+                handle,
+                appender,
+                path,
+            });
         }
     }
+    true
 }
 
-/// Get the error associated with a `Context` or `Plugin`, if `plugin` is `-1` then the context
-/// error will be returned
+/// Change the log level without replacing the log destination. If logging hasn't been set up
+/// yet then logs are written to stderr
 #[no_mangle]
-pub unsafe extern "C" fn extism_error(ctx: *mut Context, plugin: PluginIndex) -> *const c_char {
-    trace!("Call to extism_error for plugin {plugin}");
-
-    let ctx = &mut *ctx;
-
-    if !ctx.plugin_exists(plugin) {
-        return get_context_error(ctx);
-    }
+pub unsafe extern "C" fn extism_log_set_level(log_level: *const c_char) -> bool {
+    let level = match parse_log_level(log_level) {
+        Some(x) => x,
+        None => {
+            return false;
+        }
+    };
 
-    let plugin = match PluginRef::new(ctx, plugin, false) {
-        None => return std::ptr::null(),
-        Some(p) => p,
+    let mut logger = match LOGGER.lock() {
+        Ok(x) => x,
+        Err(e) => e.into_inner(),
     };
 
-    let err = plugin.as_ref().last_error.borrow();
-    match err.as_ref() {
-        Some(e) => e.as_ptr() as *const _,
+    match &mut *logger {
+        Some(state) => {
+            let config = match logger_config(state.appender.clone(), level) {
+                Some(x) => x,
+                None => return false,
+            };
+            state.handle.set_config(config);
+            true
+        }
         None => {
-            trace!("Error is NULL");
-            std::ptr::null()
+            drop(logger);
+            let encoder = log4rs::encode::pattern::PatternEncoder::new(LOG_PATTERN);
+            log_file(std::ptr::null(), log_level, Box::new(encoder))
         }
     }
 }
 
-/// Get the length of a plugin's output data
+/// Set log file and level
 #[no_mangle]
-pub unsafe extern "C" fn extism_plugin_output_length(
-    ctx: *mut Context,
-    plugin: PluginIndex,
-) -> Size {
-    trace!("Call to extism_plugin_output_length for plugin {plugin}");
-
-    let ctx = &mut *ctx;
-    let plugin = match PluginRef::new(ctx, plugin, true) {
-        None => return 0,
-        Some(p) => p,
-    };
+pub unsafe extern "C" fn extism_log_file(
+    filename: *const c_char,
+    log_level: *const c_char,
+) -> bool {
+    use log4rs::encode::pattern::PatternEncoder;
 
-    let len = plugin.as_ref().memory.store.data().output_length as Size;
-    trace!("Output length: {len}");
-    len
+    let encoder = Box::new(PatternEncoder::new(LOG_PATTERN));
+    log_file(filename, log_level, encoder)
 }
 
-/// Get the length of a plugin's output data
+/// Set log file and level, like `extism_log_file` but each record is written as a JSON object
+/// on its own line
 #[no_mangle]
-pub unsafe extern "C" fn extism_plugin_output_data(
-    ctx: *mut Context,
-    plugin: PluginIndex,
-) -> *const u8 {
-    trace!("Call to extism_plugin_output_data for plugin {plugin}");
-
-    let ctx = &mut *ctx;
-    let plugin = match PluginRef::new(ctx, plugin, true) {
-        None => return std::ptr::null(),
-        Some(p) => p,
-    };
-    let data = plugin.as_ref().memory.store.data();
+pub unsafe extern "C" fn extism_log_file_json(
+    filename: *const c_char,
+    log_level: *const c_char,
+) -> bool {
+    use log4rs::encode::json::JsonEncoder;
 
-    plugin
-        .as_ref()
-        .memory
-        .ptr(MemoryBlock::new(data.output_offset, data.output_length))
-        .map(|x| x as *const _)
-        .unwrap_or(std::ptr::null())
+    log_file(filename, log_level, Box::new(JsonEncoder::new()))
 }
 
-/// Set log file and level
-#[no_mangle]
-pub unsafe extern "C" fn extism_log_file(
+unsafe fn log_file(
     filename: *const c_char,
     log_level: *const c_char,
+    encoder: Box<dyn log4rs::encode::Encode>,
 ) -> bool {
-    use log::LevelFilter;
     use log4rs::append::console::ConsoleAppender;
     use log4rs::append::file::FileAppender;
-    use log4rs::config::{Appender, Config, Logger, Root};
-    use log4rs::encode::pattern::PatternEncoder;
 
     let file = if !filename.is_null() {
         let file = std::ffi::CStr::from_ptr(filename);
@@ -401,27 +3863,14 @@ pub unsafe extern "C" fn extism_log_file(
         "stderr"
     };
 
-    let level = if log_level.is_null() {
-        "error"
-    } else {
-        let level = std::ffi::CStr::from_ptr(log_level);
-        match level.to_str() {
-            Ok(x) => x,
-            Err(_) => {
-                return false;
-            }
-        }
-    };
-
-    let level = match LevelFilter::from_str(level) {
-        Ok(x) => x,
-        Err(_) => {
+    let level = match parse_log_level(log_level) {
+        Some(x) => x,
+        None => {
             return false;
         }
     };
 
-    let encoder = Box::new(PatternEncoder::new("{t} {l} {d} - {m}\n"));
-
+    let mut path = None;
     let logfile: Box<dyn log4rs::append::Append> =
         if file == "-" || file == "stdout" || file == "stderr" {
             let target = if file == "-" || file == "stdout" {
@@ -433,32 +3882,147 @@ pub unsafe extern "C" fn extism_log_file(
             Box::new(console.build())
         } else {
             match FileAppender::builder().encoder(encoder).build(file) {
-                Ok(x) => Box::new(x),
+                Ok(x) => {
+                    path = Some(std::path::PathBuf::from(file));
+                    Box::new(x)
+                }
                 Err(_) => {
                     return false;
                 }
             }
         };
 
-    let config = match Config::builder()
-        .appender(Appender::builder().build("logfile", logfile))
-        .logger(
-            Logger::builder()
-                .appender("logfile")
-                .build("extism_runtime", level),
-        )
-        .build(Root::builder().build(LevelFilter::Off))
-    {
-        Ok(x) => x,
-        Err(_) => {
+    init_logger(logfile, level, path)
+}
+
+/// The level of a message passed to a log callback
+/// cbindgen:prefix-with-name
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogLevel {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<log::Level> for LogLevel {
+    fn from(level: log::Level) -> Self {
+        match level {
+            log::Level::Error => LogLevel::Error,
+            log::Level::Warn => LogLevel::Warn,
+            log::Level::Info => LogLevel::Info,
+            log::Level::Debug => LogLevel::Debug,
+            log::Level::Trace => LogLevel::Trace,
+        }
+    }
+}
+
+pub type LogCallback = Option<
+    unsafe extern "C" fn(
+        level: LogLevel,
+        target: *const c_char,
+        message: *const c_char,
+        user_data: *mut std::ffi::c_void,
+    ),
+>;
+
+// Forwards log records to a C callback
+#[derive(Debug)]
+struct CallbackAppender {
+    callback: unsafe extern "C" fn(LogLevel, *const c_char, *const c_char, *mut std::ffi::c_void),
+    user_data: UserData,
+}
+
+impl log4rs::append::Append for CallbackAppender {
+    fn append(&self, record: &log::Record) -> anyhow::Result<()> {
+        let target = std::ffi::CString::new(record.target().replace('\0', ""))?;
+        let message = std::ffi::CString::new(record.args().to_string().replace('\0', ""))?;
+        unsafe {
+            (self.callback)(
+                record.level().into(),
+                target.as_ptr(),
+                message.as_ptr(),
+                self.user_data.get(),
+            )
+        };
+        Ok(())
+    }
+
+    fn flush(&self) {}
+}
+
+/// Send logs to a callback instead of a file, this is an alternative to `extism_log_file` and
+/// only one of them can be used
+///
+/// The callback receives the level, target and message of each log record and `user_data`,
+/// the strings are only valid for the duration of the callback. No Extism locks are held
+/// while the callback is running
+#[no_mangle]
+pub unsafe extern "C" fn extism_log_callback(
+    callback: LogCallback,
+    user_data: *mut std::ffi::c_void,
+    log_level: *const c_char,
+) -> bool {
+    let callback = match callback {
+        Some(x) => x,
+        None => return false,
+    };
+
+    let level = match parse_log_level(log_level) {
+        Some(x) => x,
+        None => {
             return false;
         }
     };
 
-    if log4rs::init_config(config).is_err() {
-        return false;
+    let appender = CallbackAppender {
+        callback,
+        user_data: UserData(user_data),
+    };
+    init_logger(Box::new(appender), level, None)
+}
+
+/// Write any buffered log records, when logging to a file the file is also synced to disk.
+/// This should be called before exiting to make sure no logs are lost
+#[no_mangle]
+pub unsafe extern "C" fn extism_log_drain() {
+    let logger = match LOGGER.lock() {
+        Ok(x) => x,
+        Err(e) => e.into_inner(),
+    };
+
+    let state = match &*logger {
+        Some(x) => x,
+        None => return,
+    };
+
+    state.appender.flush();
+
+    // Syncing any handle to the file flushes the data written by the appender
+    if let Some(path) = &state.path {
+        if let Err(e) = std::fs::File::open(path).and_then(|f| f.sync_all()) {
+            error!("Unable to sync log file {}: {e:?}", path.display());
+        }
     }
-    true
+}
+
+/// Enable or disable parallel compilation of WASM modules, this is enabled by default and
+/// only affects plugins created after it is called
+#[no_mangle]
+pub unsafe extern "C" fn extism_set_parallel_compilation(enabled: bool) {
+    trace!("Call to extism_set_parallel_compilation: {enabled}");
+    set_parallel_compilation(enabled)
+}
+
+/// Set a timeout in milliseconds for plugins whose manifest doesn't specify one, a timeout set in
+/// the manifest always takes precedence. Passing `0` restores the initial setting, which is 30
+/// seconds. This only affects plugins created after it is called
+#[no_mangle]
+pub unsafe extern "C" fn extism_set_default_timeout_ms(ms: u64) {
+    trace!("Call to extism_set_default_timeout_ms: {ms}");
+    set_default_timeout_ms(ms)
 }
 
 const VERSION: &str = concat!(env!("CARGO_PKG_VERSION"), "\0");
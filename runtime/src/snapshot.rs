@@ -0,0 +1,98 @@
+use std::collections::BTreeMap;
+
+use crate::*;
+
+/// A copy of a plugin's memory that can be restored later using `Plugin::restore`
+///
+/// This includes the Extism memory and its allocations, every memory and mutable global exported
+/// by the plugin and the location of the output. Globals that aren't exported, such as the
+/// `__stack_pointer` used by most compilers, and tables can't be read from outside the plugin so
+/// they aren't included, restoring a snapshot isn't a complete rollback when a plugin relies on
+/// them
+#[derive(Clone)]
+pub struct Snapshot {
+    memory: Vec<u8>,
+    live_blocks: BTreeMap<usize, usize>,
+    free: Vec<MemoryBlock>,
+    position: usize,
+    exports: Vec<(String, Vec<u8>)>,
+    globals: Vec<(String, Val)>,
+    output_offset: usize,
+    output_length: usize,
+}
+
+impl Plugin {
+    /// Copy the plugin's current memory
+    pub fn snapshot(&mut self) -> Snapshot {
+        let exports = self
+            .instance
+            .exports(&mut self.memory.store)
+            .map(|x| (x.name().to_string(), x.into_extern()))
+            .collect::<Vec<_>>();
+        let store = &mut self.memory.store;
+        let globals = exports
+            .iter()
+            .filter_map(|(name, x)| match x {
+                Extern::Global(g) if g.ty(&*store).mutability() == Mutability::Var => {
+                    Some((name.clone(), g.get(&mut *store)))
+                }
+                _ => None,
+            })
+            .collect();
+        let exports = exports
+            .into_iter()
+            .filter_map(|(name, x)| x.into_memory().map(|mem| (name, mem)))
+            .map(|(name, mem)| (name, mem.data(&self.memory.store).to_vec()))
+            .collect();
+
+        let data = self.memory.store.data();
+        Snapshot {
+            memory: self.memory.data().to_vec(),
+            live_blocks: self.memory.live_blocks.clone(),
+            free: self.memory.free.clone(),
+            position: self.memory.position,
+            exports,
+            globals,
+            output_offset: data.output_offset,
+            output_length: data.output_length,
+        }
+    }
+
+    /// Overwrite the plugin's memory with a snapshot created by `Plugin::snapshot`, memory that
+    /// has grown since the snapshot was taken is zeroed
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<(), Error> {
+        for (name, data) in &snapshot.exports {
+            let mem = match self.instance.get_memory(&mut self.memory.store, name) {
+                Some(x) => x,
+                None => {
+                    return Err(Error::msg(format!(
+                        "Unable to restore snapshot, memory {name} no longer exists"
+                    )))
+                }
+            };
+            memory::replace(&mut self.memory.store, mem, data)?;
+        }
+
+        for (name, val) in &snapshot.globals {
+            let global = match self.instance.get_global(&mut self.memory.store, name) {
+                Some(x) => x,
+                None => {
+                    return Err(Error::msg(format!(
+                        "Unable to restore snapshot, global {name} no longer exists"
+                    )))
+                }
+            };
+            global.set(&mut self.memory.store, val.clone())?;
+        }
+
+        self.memory.replace(&snapshot.memory)?;
+        self.memory.live_blocks = snapshot.live_blocks.clone();
+        self.memory.free = snapshot.free.clone();
+        self.memory.position = snapshot.position;
+
+        let data = self.memory.store.data_mut();
+        data.output_offset = snapshot.output_offset;
+        data.output_length = snapshot.output_length;
+        Ok(())
+    }
+}
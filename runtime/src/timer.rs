@@ -5,27 +5,61 @@ pub(crate) enum TimerAction {
         id: uuid::Uuid,
         engine: Engine,
         duration: std::time::Duration,
+        interval: std::time::Duration,
     },
     Stop {
         id: uuid::Uuid,
     },
+    Pause {
+        id: uuid::Uuid,
+    },
+    Resume {
+        id: uuid::Uuid,
+    },
     Shutdown,
 }
 
 pub(crate) struct Timer {
     pub tx: std::sync::mpsc::SyncSender<TimerAction>,
     pub thread: Option<std::thread::JoinHandle<()>>,
+
+    /// Number of live contexts using the timer, the thread is stopped when this reaches zero
+    pub contexts: usize,
 }
 
+static CLEANUP_TIMER: std::sync::Once = std::sync::Once::new();
+
 extern "C" fn cleanup_timer() {
     drop(Context::timer().take())
 }
 
+/// How often the timer thread checks for expired deadlines, unless the context config sets
+/// `epoch_interval_ms`
+pub(crate) const DEFAULT_EPOCH_INTERVAL: std::time::Duration = std::time::Duration::from_millis(1);
+
+// Convert a timeout to the number of timer ticks before it expires
+fn ticks(duration: std::time::Duration, interval: std::time::Duration) -> u128 {
+    duration.as_nanos().div_ceil(interval.as_nanos()).max(1)
+}
+
+// A running deadline, each plugin is ticked at its own interval
+struct Deadline {
+    engine: Engine,
+    interval: std::time::Duration,
+    remaining: u128,
+    next_tick: std::time::Instant,
+}
+
 impl Timer {
     pub fn init(timer: &mut Option<Timer>) -> std::sync::mpsc::SyncSender<TimerAction> {
         let (tx, rx) = std::sync::mpsc::sync_channel(128);
-        let thread = std::thread::spawn(move || {
-            let mut plugins = std::collections::BTreeMap::new();
+        // Named so the thread can be found in tests and debuggers, `spawn` panics on failure too
+        let builder = std::thread::Builder::new().name(String::from("extism-timer"));
+        let thread = builder.spawn(move || {
+            // Deadlines are tracked as the number of ticks remaining
+            let mut plugins: std::collections::BTreeMap<uuid::Uuid, Deadline> =
+                std::collections::BTreeMap::new();
+            let mut paused = std::collections::BTreeMap::new();
 
             macro_rules! handle {
                 ($x:expr) => {
@@ -34,11 +68,32 @@ impl Timer {
                             id,
                             engine,
                             duration,
+                            interval,
                         } => {
-                            plugins.insert(id, (engine, std::time::Instant::now() + duration));
+                            plugins.insert(
+                                id,
+                                Deadline {
+                                    engine,
+                                    interval,
+                                    remaining: ticks(duration, interval),
+                                    next_tick: std::time::Instant::now() + interval,
+                                },
+                            );
                         }
                         TimerAction::Stop { id } => {
                             plugins.remove(&id);
+                            paused.remove(&id);
+                        }
+                        TimerAction::Pause { id } => {
+                            if let Some(x) = plugins.remove(&id) {
+                                paused.insert(id, x);
+                            }
+                        }
+                        TimerAction::Resume { id } => {
+                            if let Some(mut x) = paused.remove(&id) {
+                                x.next_tick = std::time::Instant::now() + x.interval;
+                                plugins.insert(id, x);
+                            }
                         }
                         TimerAction::Shutdown => return,
                     }
@@ -46,42 +101,73 @@ impl Timer {
             }
 
             loop {
-                if plugins.is_empty() {
-                    if let Ok(x) = rx.recv() {
-                        handle!(x)
-                    }
-                }
-
-                plugins = plugins
-                    .into_iter()
-                    .filter(|(_k, (engine, end))| {
-                        let now = std::time::Instant::now();
-                        if end <= &now {
-                            engine.increment_epoch();
-                            return false;
+                // Sleep until there's a deadline to track, otherwise wait for the next tick
+                let next_tick = match plugins.values().map(|x| x.next_tick).min() {
+                    Some(x) => x,
+                    None => {
+                        match rx.recv() {
+                            Ok(x) => handle!(x),
+                            Err(_) => return,
                         }
-                        true
-                    })
-                    .collect();
+                        continue;
+                    }
+                };
 
-                for x in rx.try_iter() {
-                    handle!(x)
+                let now = std::time::Instant::now();
+                if now < next_tick {
+                    match rx.recv_timeout(next_tick - now) {
+                        Ok(x) => handle!(x),
+                        Err(std::sync::mpsc::RecvTimeoutError::Timeout) => (),
+                        Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => return,
+                    }
+                    continue;
                 }
+
+                plugins.retain(|_id, x| {
+                    if x.next_tick > now {
+                        return true;
+                    }
+                    x.next_tick += x.interval;
+                    x.remaining -= 1;
+                    if x.remaining == 0 {
+                        x.engine.increment_epoch();
+                        return false;
+                    }
+                    true
+                });
             }
         });
+        let thread = thread.expect("Unable to start the timer thread");
         *timer = Some(Timer {
             thread: Some(thread),
             tx: tx.clone(),
+            contexts: 0,
         });
 
-        unsafe {
+        // The timer can be restarted after it's released, but the exit handler only needs to be
+        // registered once
+        CLEANUP_TIMER.call_once(|| unsafe {
             libc::atexit(cleanup_timer);
-        }
+        });
 
         tx
     }
 }
 
+impl Timer {
+    /// Release a context's reference to the timer, returning the timer if it's no longer used so
+    /// it can be joined after the lock is released
+    pub(crate) fn release(timer: &mut Option<Timer>) -> Option<Timer> {
+        if let Some(t) = timer {
+            t.contexts = t.contexts.saturating_sub(1);
+            if t.contexts == 0 {
+                return timer.take();
+            }
+        }
+        None
+    }
+}
+
 impl Drop for Timer {
     fn drop(&mut self) {
         let _ = self.tx.send(TimerAction::Shutdown);
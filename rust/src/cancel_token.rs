@@ -0,0 +1,12 @@
+/// Used to cancel calls to a plugin from another thread, tokens can be cloned and outlive the
+/// plugin they were created from
+#[derive(Clone)]
+pub struct CancelToken(pub(crate) std::sync::Arc<extism_runtime::CancelHandle>);
+
+impl CancelToken {
+    /// Interrupt the call that is currently running, it will fail with the error `cancelled`.
+    /// This does nothing if no call is running
+    pub fn cancel(&self) {
+        self.0.cancel()
+    }
+}
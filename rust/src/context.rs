@@ -1,6 +1,8 @@
 use crate::*;
 
-pub struct Context(pub(crate) std::sync::Arc<std::sync::Mutex<extism_runtime::Context>>);
+/// Plugins created in the same context can be called from different threads, calls to the same
+/// plugin wait for each other
+pub struct Context(pub(crate) std::sync::Arc<extism_runtime::Context>);
 
 impl Default for Context {
     fn default() -> Context {
@@ -11,20 +13,30 @@ impl Default for Context {
 impl Context {
     /// Create a new context
     pub fn new() -> Context {
-        Context(std::sync::Arc::new(std::sync::Mutex::new(
-            extism_runtime::Context::new(),
-        )))
+        Context(std::sync::Arc::new(
+            extism_runtime::Context::new_threadsafe(),
+        ))
+    }
+
+    /// Create a new context for plugins that will be called using `Plugin::call_async`, these
+    /// plugins periodically yield to the async executor while they run
+    pub fn new_async() -> Context {
+        let config = extism_runtime::ContextConfig {
+            async_support: Some(true),
+            ..Default::default()
+        };
+        // This can't fail because the config doesn't disable any features
+        let ctx = extism_runtime::Context::new_threadsafe_with_config(config).unwrap();
+        Context(std::sync::Arc::new(ctx))
     }
 
     /// Remove all registered plugins
     pub fn reset(&mut self) {
-        unsafe { bindings::extism_context_reset(&mut *self.lock()) }
+        unsafe { bindings::extism_context_reset(self.ptr()) }
     }
 
-    pub(crate) fn lock(&self) -> std::sync::MutexGuard<extism_runtime::Context> {
-        match self.0.lock() {
-            Ok(x) => x,
-            Err(x) => x.into_inner(),
-        }
+    // The C API takes a mutable pointer, but the runtime only uses it as a shared reference
+    pub(crate) fn ptr(&self) -> *mut extism_runtime::Context {
+        std::sync::Arc::as_ptr(&self.0) as *mut _
     }
 }
@@ -1,10 +1,12 @@
 pub use extism_manifest::{self as manifest, Manifest};
 pub use extism_runtime::{sdk as bindings, Function, ValType};
 
+mod cancel_token;
 mod context;
 mod plugin;
 mod plugin_builder;
 
+pub use cancel_token::CancelToken;
 pub use context::Context;
 pub use plugin::Plugin;
 pub use plugin_builder::PluginBuilder;
@@ -25,20 +27,32 @@ pub enum Error {
 pub fn extism_version() -> String {
     let err = unsafe { bindings::extism_version() };
     let buf = unsafe { std::ffi::CStr::from_ptr(err) };
-    return buf.to_str().unwrap().to_string();
+    buf.to_str().unwrap().to_string()
 }
 
 /// Set the log file and level, this is a global setting
 pub fn set_log_file(filename: impl AsRef<std::path::Path>, log_level: Option<log::Level>) -> bool {
     let log_level = log_level.map(|x| x.as_str());
     unsafe {
-        return bindings::extism_log_file(
+        bindings::extism_log_file(
             filename.as_ref().as_os_str().to_string_lossy().as_ptr() as *const _,
             log_level.map(|x| x.as_ptr()).unwrap_or(std::ptr::null()) as *const _,
-        );
+        )
     }
 }
 
+/// Enable or disable parallel compilation of WASM modules, this is a global setting
+pub fn set_parallel_compilation(enabled: bool) {
+    unsafe { bindings::extism_set_parallel_compilation(enabled) }
+}
+
+/// Set the timeout used by plugins whose manifest doesn't specify one, `None` restores the 30
+/// second default. This is a global setting
+pub fn set_default_timeout(timeout: Option<std::time::Duration>) {
+    let ms = timeout.map(|x| x.as_millis() as u64).unwrap_or(0);
+    unsafe { bindings::extism_set_default_timeout_ms(ms) }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -96,6 +110,7 @@ mod tests {
 
             let mut _native_vowel_count = 0;
             let input: &[u8] = input.as_ref();
+            #[allow(clippy::needless_range_loop)]
             for i in 0..input.len() {
                 if input[i] == b'A'
                     || input[i] == b'E'
@@ -138,6 +153,508 @@ mod tests {
         println!("wasm function call (avg, N = {}): {:?}", num_tests, avg);
     }
 
+    #[test]
+    fn test_call_vectored() {
+        let context = Context::new();
+        let mut plugin = Plugin::new(&context, WASM, false).unwrap();
+
+        let output = plugin.call_vectored("count_vowels", &[]).unwrap();
+        assert_eq!(output, b"{\"count\": 0}");
+
+        let output = plugin
+            .call_vectored("count_vowels", &[b"this is ", b"", b"a test"])
+            .unwrap();
+        assert_eq!(output, b"{\"count\": 4}");
+    }
+
+    #[test]
+    fn test_call_batch_output() {
+        unsafe extern "C" fn output(
+            _: extism_runtime::Size,
+            rc: i32,
+            data: *const u8,
+            len: extism_runtime::Size,
+            user_data: *mut std::ffi::c_void,
+        ) {
+            let outputs = &mut *(user_data as *mut Vec<(i32, Vec<u8>)>);
+            let data = std::slice::from_raw_parts(data, len as usize);
+            outputs.push((rc, data.to_vec()));
+        }
+
+        let context = Context::new();
+        let plugin = Plugin::new(&context, WASM, false).unwrap();
+        let call = |name: &std::ffi::CStr, input: &'static [u8]| bindings::BatchCall {
+            func_name: name.as_ptr(),
+            data: input.as_ptr(),
+            data_len: input.len() as _,
+        };
+        let calls = [
+            call(c"count_vowels", b"aaa"),
+            call(c"missing", b""),
+            call(c"count_vowels", b"ee"),
+        ];
+        let mut rcs = [0; 3];
+        let mut outputs: Vec<(i32, Vec<u8>)> = vec![];
+        let failed = unsafe {
+            bindings::extism_plugin_call_batch(
+                context.ptr(),
+                plugin.as_i32(),
+                calls.as_ptr(),
+                calls.len() as _,
+                rcs.as_mut_ptr(),
+                true,
+                Some(output),
+                &mut outputs as *mut _ as *mut _,
+            )
+        };
+        assert_eq!(failed, 1);
+        assert_eq!(rcs, [0, -1, 0]);
+        assert_eq!(
+            outputs,
+            [
+                (0, b"{\"count\": 3}".to_vec()),
+                (-1, vec![]),
+                (0, b"{\"count\": 2}".to_vec())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_call_observer_streaming() {
+        unsafe extern "C" fn observe(
+            _: extism_runtime::PluginIndex,
+            _: *const std::os::raw::c_char,
+            phase: extism_runtime::CallPhase,
+            rc: i32,
+            user_data: *mut std::ffi::c_void,
+        ) {
+            let calls = &mut *(user_data as *mut Vec<(extism_runtime::CallPhase, i32)>);
+            calls.push((phase, rc));
+        }
+
+        unsafe extern "C" fn emit(
+            _: extism_runtime::PluginIndex,
+            _: *const u8,
+            _: extism_runtime::Size,
+            _: *mut std::ffi::c_void,
+        ) {
+        }
+
+        let context = Context::new();
+        let plugin = Plugin::new(&context, WASM, false).unwrap();
+        let mut calls: Vec<(extism_runtime::CallPhase, i32)> = vec![];
+        let rc = unsafe {
+            bindings::extism_context_set_call_observer(
+                context.ptr(),
+                Some(observe),
+                &mut calls as *mut _ as *mut _,
+            );
+            let rc = bindings::extism_plugin_call_streaming(
+                context.ptr(),
+                plugin.as_i32(),
+                c"count_vowels".as_ptr(),
+                b"aaa".as_ptr(),
+                3,
+                Some(emit),
+                std::ptr::null_mut(),
+            );
+            bindings::extism_context_set_call_observer(context.ptr(), None, std::ptr::null_mut());
+            rc
+        };
+        assert_eq!(rc, 0);
+        assert_eq!(
+            calls,
+            [
+                (extism_runtime::CallPhase::Start, -1),
+                (extism_runtime::CallPhase::End, 0)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_host_function_panic() {
+        let wasm = r#"(module
+            (import "env" "hello" (func $hello))
+            (memory (export "memory") 1)
+            (func (export "run") (result i32) call $hello i32.const 0))"#;
+        let f = Function::new("hello", [], [], |_, _, _| panic!("oops"));
+        let context = Context::new();
+        let mut plugin = Plugin::new_with_functions(&context, wasm, [f], false).unwrap();
+
+        let err = plugin.call("run", "").unwrap_err();
+        assert!(err.to_string().contains("host function panicked: oops"));
+
+        // The plugin is still usable after a panic
+        let err = plugin.call("run", "").unwrap_err();
+        assert!(err.to_string().contains("host function panicked: oops"));
+    }
+
+    #[test]
+    fn test_sort_plugins_while_executing() {
+        let wasm = r#"(module
+            (import "env" "hello" (func $hello))
+            (memory (export "memory") 1)
+            (func (export "run") (result i32) call $hello i32.const 0))"#;
+        let context = Context::new();
+        let other = Plugin::new(&context, WASM, false).unwrap();
+
+        // A weak reference avoids a cycle between the context and its plugins
+        let ctx = std::sync::Arc::downgrade(&context.0);
+        let sorted = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+        let sorted_ref = sorted.clone();
+        let f = Function::new("hello", [], [], move |_, _, _| {
+            let ctx = ctx.upgrade().unwrap();
+            let mut sorted = sorted_ref.lock().unwrap();
+            sorted.push(ctx.plugins_by_last_used());
+            sorted.push(ctx.plugins_by_memory());
+            Ok(())
+        });
+        let mut plugin = Plugin::new_with_functions(&context, wasm, [f], false).unwrap();
+        plugin.call("run", "").unwrap();
+
+        let sorted = sorted.lock().unwrap();
+        assert_eq!(sorted[0], [other.as_i32(), plugin.as_i32()]);
+        assert_eq!(sorted[1].len(), 2);
+    }
+
+    // WASM memory is limited to 4GiB, this checks that lengths past `i32::MAX` aren't truncated
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn test_large_output() {
+        let wasm = r#"(module
+            (import "env" "extism_alloc" (func $alloc (param i64) (result i64)))
+            (import "env" "extism_output_set" (func $output_set (param i64 i64)))
+            (memory (export "memory") 1)
+            (func (export "run") (result i32)
+                (call $output_set (call $alloc (i64.const 3221225472)) (i64.const 3221225472))
+                i32.const 0))"#;
+        let context = Context::new();
+        let mut plugin = Plugin::new(&context, wasm, false).unwrap();
+        let output = plugin.call("run", "").unwrap();
+        assert_eq!(output.len() as u64, 3 << 30);
+    }
+
+    #[test]
+    fn test_free_user_data_once() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static FREED: AtomicUsize = AtomicUsize::new(0);
+
+        unsafe extern "C" fn func(
+            _: *mut extism_runtime::Internal,
+            _: *const bindings::ExtismVal,
+            _: extism_runtime::Size,
+            _: *mut bindings::ExtismVal,
+            _: extism_runtime::Size,
+            _: *mut std::ffi::c_void,
+        ) {
+        }
+
+        unsafe extern "C" fn free(_: *mut std::ffi::c_void) {
+            FREED.fetch_add(1, Ordering::SeqCst);
+        }
+
+        let mut user_data = 0u8;
+        let user_data: *mut std::ffi::c_void = &mut user_data as *mut u8 as *mut _;
+        let def = |name: &'static [u8]| bindings::FunctionDefinition {
+            name: name.as_ptr() as *const _,
+            namespace: std::ptr::null(),
+            params: std::ptr::null(),
+            n_params: 0,
+            results: std::ptr::null(),
+            n_results: 0,
+            func: Some(func),
+            user_data,
+            free_user_data: Some(free),
+        };
+        let defs = [def(b"a\0"), def(b"b\0")];
+
+        let context = Context::new();
+        unsafe {
+            let id = bindings::extism_plugin_new_with_functions(
+                context.ptr(),
+                WASM.as_ptr(),
+                WASM.len() as _,
+                false,
+                defs.as_ptr(),
+                defs.len() as _,
+            );
+            assert!(id >= 0);
+            bindings::extism_plugin_free(context.ptr(), id);
+        }
+        assert_eq!(FREED.load(Ordering::SeqCst), 1);
+
+        // `user_data` is also freed once when creating the plugin fails
+        unsafe {
+            let id = bindings::extism_plugin_new_with_functions(
+                context.ptr(),
+                std::ptr::null(),
+                0,
+                false,
+                defs.as_ptr(),
+                defs.len() as _,
+            );
+            assert_eq!(id, -1);
+        }
+        assert_eq!(FREED.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_resource_limits() {
+        let config = extism_runtime::ContextConfig {
+            max_memories: Some(1),
+            max_table_elements: Some(4),
+            ..Default::default()
+        };
+        let ctx = extism_runtime::Context::new_threadsafe_with_config(config).unwrap();
+        let context = Context(std::sync::Arc::new(ctx));
+
+        // Extism's own memory isn't counted, and the denied `table.grow` fails the call even
+        // though the function returns without checking for the interruption
+        let wasm = r#"(module
+            (memory (export "memory") 1)
+            (table 1 funcref)
+            (func (export "run") (result i32)
+                (drop (table.grow (ref.null func) (i32.const 8)))
+                i32.const 0))"#;
+        let mut plugin = Plugin::new(&context, wasm, false).unwrap();
+        let err = plugin.call("run", "").unwrap_err();
+        assert!(err.to_string().contains("resource limit exceeded"), "{err}");
+        let code =
+            unsafe { bindings::extism_plugin_last_error_code(context.ptr(), plugin.as_i32()) };
+        assert_eq!(code, extism_runtime::ErrorKind::ResourceLimit.code());
+    }
+
+    #[test]
+    fn test_serialize_skips_secrets() {
+        let manifest = Manifest::new([manifest::Wasm::data(WASM)])
+            .with_config([("public".to_string(), "visible-value".to_string())].into_iter())
+            .with_secret_config("token", "secret-value");
+        let context = Context::new();
+        let plugin = Plugin::new_with_manifest(&context, &manifest, false).unwrap();
+
+        let mut out = std::ptr::null();
+        let mut out_len = 0;
+        let data = unsafe {
+            assert!(bindings::extism_plugin_serialize(
+                context.ptr(),
+                plugin.as_i32(),
+                &mut out,
+                &mut out_len,
+            ));
+            std::slice::from_raw_parts(out, out_len as usize)
+        };
+        let contains = |x: &[u8]| data.windows(x.len()).any(|w| w == x);
+        assert!(contains(b"visible-value"));
+        assert!(!contains(b"secret-value"));
+    }
+
+    #[test]
+    fn test_rollback_on_error() {
+        // `set` stores its argument in memory and an exported global, then traps or exits
+        let wasm = r#"(module
+            (import "wasi_snapshot_preview1" "proc_exit" (func $exit (param i32)))
+            (memory (export "memory") 1)
+            (global $g (export "g") (mut i64) (i64.const 1))
+            (func $set (param i64)
+                (i64.store (i32.const 0) (local.get 0))
+                (global.set $g (local.get 0)))
+            (func (export "init") (result i32) (call $set (i64.const 1)) i32.const 0)
+            (func (export "trap") (result i32) (call $set (i64.const 2)) unreachable)
+            (func (export "exit") (result i32) (call $set (i64.const 3)) (call $exit (i32.const 0))
+                i32.const 0)
+            (func (export "state") (result i64 i64) (i64.load (i32.const 0)) (global.get $g)))"#;
+        let context = Context::new();
+        let mut plugin = Plugin::new(&context, wasm, true).unwrap();
+        let id = plugin.as_i32();
+        assert!(unsafe { bindings::extism_plugin_set_rollback(context.ptr(), id, true) });
+        let state = || {
+            let mut results = [0i64; 2];
+            let n = unsafe {
+                bindings::extism_plugin_call_multi(
+                    context.ptr(),
+                    id,
+                    c"state".as_ptr(),
+                    std::ptr::null(),
+                    0,
+                    results.as_mut_ptr(),
+                    2,
+                )
+            };
+            assert_eq!(n, 2);
+            results
+        };
+
+        plugin.call("init", "").unwrap();
+        assert_eq!(state(), [1, 1]);
+
+        // The changes made before the trap are undone
+        assert!(plugin.call("trap", "").is_err());
+        assert_eq!(state(), [1, 1]);
+
+        // Exiting using WASI isn't a failure, so it isn't rolled back
+        plugin.call("exit", "").unwrap();
+        assert_eq!(state(), [3, 3]);
+    }
+
+    #[test]
+    fn test_secrets_not_in_wasi_env() {
+        // Outputs the WASI environment
+        let wasm = r#"(module
+            (import "wasi_snapshot_preview1" "environ_sizes_get"
+                (func $sizes (param i32 i32) (result i32)))
+            (import "wasi_snapshot_preview1" "environ_get"
+                (func $get (param i32 i32) (result i32)))
+            (import "env" "extism_alloc" (func $alloc (param i64) (result i64)))
+            (import "env" "extism_store_u8" (func $store (param i64 i32)))
+            (import "env" "extism_output_set" (func $output_set (param i64 i64)))
+            (memory (export "memory") 1)
+            (func (export "run") (result i32)
+                (local $len i32) (local $out i64) (local $i i32)
+                (drop (call $sizes (i32.const 0) (i32.const 4)))
+                (drop (call $get (i32.const 16) (i32.const 1024)))
+                (local.set $len (i32.load (i32.const 4)))
+                (local.set $out (call $alloc (i64.extend_i32_u (local.get $len))))
+                (block $done
+                    (loop $copy
+                        (br_if $done (i32.ge_u (local.get $i) (local.get $len)))
+                        (call $store
+                            (i64.add (local.get $out) (i64.extend_i32_u (local.get $i)))
+                            (i32.load8_u (i32.add (i32.const 1024) (local.get $i))))
+                        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                        (br $copy)))
+                (call $output_set (local.get $out) (i64.extend_i32_u (local.get $len)))
+                i32.const 0))"#;
+        let manifest = Manifest::new([manifest::Wasm::data(wasm.as_bytes())])
+            .with_config([("public".to_string(), "visible-value".to_string())].into_iter())
+            .with_secret_config("token", "secret-value");
+        let context = Context::new();
+        let mut plugin = Plugin::new_with_manifest(&context, &manifest, true).unwrap();
+        let contains = |data: &[u8], x: &[u8]| data.windows(x.len()).any(|w| w == x);
+
+        let env = plugin.call("run", "").unwrap().to_vec();
+        assert!(contains(&env, b"public=visible-value"));
+        assert!(!contains(&env, b"secret-value"));
+
+        // Secrets updated using `extism_plugin_config` stay out of the environment
+        let json = br#"{"token": "updated-secret", "extra": "extra-value"}"#;
+        assert!(unsafe {
+            bindings::extism_plugin_config(
+                context.ptr(),
+                plugin.as_i32(),
+                json.as_ptr(),
+                json.len() as _,
+            )
+        });
+        let env = plugin.call("run", "").unwrap().to_vec();
+        assert!(contains(&env, b"extra=extra-value"));
+        assert!(!contains(&env, b"updated-secret"));
+
+        let mut out = std::ptr::null();
+        let mut out_len = 0;
+        let data = unsafe {
+            assert!(bindings::extism_plugin_serialize(
+                context.ptr(),
+                plugin.as_i32(),
+                &mut out,
+                &mut out_len,
+            ));
+            std::slice::from_raw_parts(out, out_len as usize)
+        };
+        assert!(contains(data, b"extra-value"));
+        assert!(!contains(data, b"secret-value"));
+        assert!(!contains(data, b"updated-secret"));
+    }
+
+    #[test]
+    fn test_default_timeout() {
+        let serialized_timeout = |context: &Context, manifest: &serde_json::Value| {
+            let plugin =
+                Plugin::new(context, serde_json::to_vec(manifest).unwrap(), false).unwrap();
+            let mut out = std::ptr::null();
+            let mut out_len = 0;
+            let data = unsafe {
+                assert!(bindings::extism_plugin_serialize(
+                    context.ptr(),
+                    plugin.as_i32(),
+                    &mut out,
+                    &mut out_len,
+                ));
+                std::slice::from_raw_parts(out, out_len as usize)
+            };
+            let contains = |x: &[u8]| data.windows(x.len()).any(|w| w == x);
+            [123456, 5000, 0]
+                .into_iter()
+                .find(|ms| contains(format!("\"timeout_ms\":{ms}").as_bytes()))
+        };
+
+        let manifest = serde_json::to_value(Manifest::new([manifest::Wasm::data(WASM)])).unwrap();
+        assert!(manifest.get("timeout_ms").is_none());
+
+        let context = Context::new();
+        set_default_timeout(Some(std::time::Duration::from_millis(123456)));
+        let missing = serialized_timeout(&context, &manifest);
+        let mut explicit = manifest.clone();
+        explicit["timeout_ms"] = 5000.into();
+        let explicit = serialized_timeout(&context, &explicit);
+        let mut unlimited = manifest.clone();
+        unlimited["timeout_ms"] = serde_json::Value::Null;
+        let unlimited = serialized_timeout(&context, &unlimited);
+        set_default_timeout(None);
+
+        assert_eq!(missing, Some(123456));
+        assert_eq!(explicit, Some(5000));
+        assert_eq!(unlimited, Some(0));
+    }
+
+    #[test]
+    fn test_cancel_handle_outlives_plugin() {
+        let context = Context::new();
+        let plugin = Plugin::new(&context, WASM, false).unwrap();
+        let id = plugin.as_i32();
+        unsafe {
+            let handle = bindings::extism_plugin_cancel_handle(context.ptr(), id);
+            assert!(!handle.is_null());
+            drop(plugin);
+            assert!(bindings::extism_plugin_cancel(handle));
+            bindings::extism_plugin_cancel_handle_free(handle);
+        }
+    }
+
+    #[test]
+    fn test_truncated_wasm() {
+        let context = Context::new();
+        let err = Plugin::new(&context, &WASM[..64], false).err().unwrap();
+        let msg = err.to_string();
+        assert!(msg.contains("Invalid WASM in the"), "{msg}");
+        assert!(msg.contains("unexpected end-of-file"), "{msg}");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_timer_thread_is_joined() {
+        // There's one timer shared by all contexts, so other tests running in parallel can't
+        // start a second one unless a released timer thread wasn't joined
+        fn timer_threads() -> usize {
+            std::fs::read_dir("/proc/self/task")
+                .unwrap()
+                .filter_map(|x| std::fs::read_to_string(x.ok()?.path().join("comm")).ok())
+                .filter(|x| x.trim() == "extism-timer")
+                .count()
+        }
+
+        let wasm = r#"(module
+            (memory (export "memory") 1)
+            (func (export "run") (result i32) i32.const 0))"#;
+        for _ in 0..1000 {
+            let context = Context::new();
+            let mut plugin = Plugin::new(&context, wasm, false).unwrap();
+            plugin.call("run", "").unwrap();
+            drop(plugin);
+            drop(context);
+            assert!(timer_threads() <= 1);
+        }
+    }
+
     #[test]
     fn test_threads() {
         use std::io::Write;
@@ -41,11 +41,11 @@ impl<'a> Plugin<'a> {
     }
 
     /// Create a new plugin from a WASM module
-    pub fn new(ctx: &'a Context, data: impl AsRef<[u8]>, wasi: bool) -> Result<Plugin, Error> {
-        let plugin = ctx.lock().new_plugin(data, wasi);
+    pub fn new(ctx: &'a Context, data: impl AsRef<[u8]>, wasi: bool) -> Result<Plugin<'a>, Error> {
+        let plugin = ctx.0.new_plugin(data, wasi);
 
         if plugin < 0 {
-            let err = unsafe { bindings::extism_error(&mut *ctx.lock(), -1) };
+            let err = unsafe { bindings::extism_error(ctx.ptr(), -1) };
             let buf = unsafe { std::ffi::CStr::from_ptr(err) };
             let buf = buf.to_str().unwrap().to_string();
             return Err(Error::UnableToLoadPlugin(buf));
@@ -63,11 +63,11 @@ impl<'a> Plugin<'a> {
         data: impl AsRef<[u8]>,
         imports: impl IntoIterator<Item = extism_runtime::Function>,
         wasi: bool,
-    ) -> Result<Plugin, Error> {
-        let plugin = ctx.lock().new_plugin_with_functions(data, imports, wasi);
+    ) -> Result<Plugin<'a>, Error> {
+        let plugin = ctx.0.new_plugin_with_functions(data, imports, wasi);
 
         if plugin < 0 {
-            let err = unsafe { bindings::extism_error(&mut *ctx.lock(), -1) };
+            let err = unsafe { bindings::extism_error(ctx.ptr(), -1) };
             let buf = unsafe { std::ffi::CStr::from_ptr(err) };
             let buf = buf.to_str().unwrap().to_string();
             return Err(Error::UnableToLoadPlugin(buf));
@@ -83,7 +83,7 @@ impl<'a> Plugin<'a> {
     pub fn update(&mut self, data: impl AsRef<[u8]>, wasi: bool) -> Result<(), Error> {
         let b = unsafe {
             bindings::extism_plugin_update(
-                &mut *self.context.lock(),
+                self.context.ptr(),
                 self.id,
                 data.as_ref().as_ptr(),
                 data.as_ref().len() as u64,
@@ -94,7 +94,7 @@ impl<'a> Plugin<'a> {
             return Ok(());
         }
 
-        let err = unsafe { bindings::extism_error(&mut *self.context.lock(), -1) };
+        let err = unsafe { bindings::extism_error(self.context.ptr(), -1) };
         if !err.is_null() {
             let s = unsafe { std::ffi::CStr::from_ptr(err) };
             return Err(Error::Message(s.to_str().unwrap().to_string()));
@@ -114,7 +114,7 @@ impl<'a> Plugin<'a> {
         let encoded = serde_json::to_vec(config)?;
         unsafe {
             bindings::extism_plugin_config(
-                &mut *self.context.lock(),
+                self.context.ptr(),
                 self.id,
                 encoded.as_ptr() as *const _,
                 encoded.len() as u64,
@@ -134,28 +134,94 @@ impl<'a> Plugin<'a> {
         let name = std::ffi::CString::new(name.as_ref()).expect("Invalid function name");
         unsafe {
             bindings::extism_plugin_function_exists(
-                &mut *self.context.lock(),
+                self.context.ptr(),
                 self.id,
                 name.as_ptr() as *const _,
             )
         }
     }
 
+    /// Get a token that can be used to cancel calls to this plugin from another thread
+    pub fn cancel_token(&self) -> Result<CancelToken, Error> {
+        match self.context.0.cancel_handle(self.id) {
+            Some(handle) => Ok(CancelToken(handle)),
+            None => Err(Error::Message(format!(
+                "Plugin does not exist: {}",
+                self.id
+            ))),
+        }
+    }
+
     /// Call a function with the given input
     pub fn call(&mut self, name: impl AsRef<str>, input: impl AsRef<[u8]>) -> Result<&[u8], Error> {
         let name = std::ffi::CString::new(name.as_ref()).expect("Invalid function name");
         let rc = unsafe {
             bindings::extism_plugin_call(
-                &mut *self.context.lock(),
+                self.context.ptr(),
+                self.id,
+                name.as_ptr() as *const _,
+                input.as_ref().as_ptr() as *const _,
+                input.as_ref().len() as u64,
+            )
+        };
+
+        self.output(rc)
+    }
+
+    /// Call a function with input made up of multiple segments, the segments are concatenated
+    /// in order by the runtime so the caller doesn't need to
+    pub fn call_vectored(
+        &mut self,
+        name: impl AsRef<str>,
+        segments: &[&[u8]],
+    ) -> Result<&[u8], Error> {
+        let name = std::ffi::CString::new(name.as_ref()).expect("Invalid function name");
+        let iovecs: Vec<_> = segments
+            .iter()
+            .map(|x| bindings::IoVec {
+                ptr: x.as_ptr(),
+                len: x.len() as u64,
+            })
+            .collect();
+        let rc = unsafe {
+            bindings::extism_plugin_call_vectored(
+                self.context.ptr(),
+                self.id,
+                name.as_ptr() as *const _,
+                iovecs.as_ptr(),
+                iovecs.len() as u64,
+            )
+        };
+
+        self.output(rc)
+    }
+
+    /// Call a function with the given input without blocking the current thread, plugins
+    /// created using `Context::new_async` yield to the executor while they run
+    pub async fn call_async(
+        &mut self,
+        name: impl AsRef<str>,
+        input: impl AsRef<[u8]>,
+    ) -> Result<&[u8], Error> {
+        let name = std::ffi::CString::new(name.as_ref()).expect("Invalid function name");
+        let rc = unsafe {
+            bindings::plugin_call_async(
+                &self.context.0,
                 self.id,
                 name.as_ptr() as *const _,
                 input.as_ref().as_ptr() as *const _,
                 input.as_ref().len() as u64,
             )
+            .await
         };
 
+        self.output(rc)
+    }
+
+    // Get the output of the last call, or its error if `rc` isn't 0
+    fn output(&self, rc: i32) -> Result<&[u8], Error> {
         if rc != 0 {
-            let err = unsafe { bindings::extism_error(&mut *self.context.lock(), self.id) };
+            let err = unsafe { bindings::extism_error(self.context.ptr(), self.id) };
             if !err.is_null() {
                 let s = unsafe { std::ffi::CStr::from_ptr(err) };
                 return Err(Error::Message(s.to_str().unwrap().to_string()));
@@ -164,10 +230,9 @@ impl<'a> Plugin<'a> {
             return Err(Error::Message("extism_call failed".to_string()));
         }
 
-        let out_len =
-            unsafe { bindings::extism_plugin_output_length(&mut *self.context.lock(), self.id) };
+        let out_len = unsafe { bindings::extism_plugin_output_length(self.context.ptr(), self.id) };
         unsafe {
-            let ptr = bindings::extism_plugin_output_data(&mut *self.context.lock(), self.id);
+            let ptr = bindings::extism_plugin_output_data(self.context.ptr(), self.id);
             Ok(std::slice::from_raw_parts(ptr, out_len as usize))
         }
     }
@@ -175,6 +240,6 @@ impl<'a> Plugin<'a> {
 
 impl<'a> Drop for Plugin<'a> {
     fn drop(&mut self) {
-        unsafe { bindings::extism_plugin_free(&mut *self.context.lock(), self.id) }
+        unsafe { bindings::extism_plugin_free(self.context.ptr(), self.id) }
     }
 }
@@ -1,7 +1,7 @@
 use crate::*;
 
 enum Source {
-    Manifest(Manifest),
+    Manifest(Box<Manifest>),
     Data(Vec<u8>),
 }
 
@@ -25,7 +25,7 @@ impl PluginBuilder {
     /// Create a new `PluginBuilder` from a `Manifest`
     pub fn new(manifest: Manifest) -> Self {
         PluginBuilder {
-            source: Source::Manifest(manifest),
+            source: Source::Manifest(Box::new(manifest)),
             wasi: false,
             functions: vec![],
         }
@@ -49,7 +49,7 @@ impl PluginBuilder {
         self
     }
 
-    pub fn build(self, context: &Context) -> Result<Plugin, Error> {
+    pub fn build(self, context: &Context) -> Result<Plugin<'_>, Error> {
         match self.source {
             Source::Manifest(m) => {
                 Plugin::new_with_manifest_and_functions(context, &m, self.functions, self.wasi)